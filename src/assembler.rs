@@ -0,0 +1,1499 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use expression::{self, Context, Relocation, Value};
+use opcodes::{opcode, Operand};
+use rel::{self, Address, Chain, Module, Segment};
+
+/// Which assembler's conventions the source follows. They share every directive and
+/// operator, and differ only where their syntax conflicts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dialect {
+    /// This assembler's own, where `$1F` and `0x1F` are hex.
+    Native,
+    /// Microsoft's MACRO-80, where `X'1F'` is hex and `*` in the first column starts a
+    /// comment.
+    M80,
+    /// Intel's ASM80, where `$` inside names and numbers is ignored and starts a control
+    /// line in the first column.
+    Asm80,
+}
+
+impl Dialect {
+    pub fn named(name: &str) -> Option<Dialect> {
+        match name.to_lowercase().as_str() {
+            "native" => Some(Dialect::Native),
+            "m80" => Some(Dialect::M80),
+            "asm80" => Some(Dialect::Asm80),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+/// Words, by offset, whose value depends on where the linker puts things.
+pub type Fixups = Vec<(usize, Relocation)>;
+
+/// Bytes emitted at one address, in the order the source produced them.
+pub struct Chunk {
+    pub segment: Segment,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub fixups: Fixups,
+}
+
+/// A line of source as it appears in the listing.
+pub struct Listed {
+    pub file: String,
+    pub line: usize,
+    pub address: Option<u16>,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    /// Whether the line came out of a macro or REPT rather than straight from the source.
+    pub expanded: bool,
+    pub instruction: bool,
+}
+
+pub struct Assembly {
+    /// The module name, from NAME or the source file's name.
+    pub name: String,
+    pub chunks: Vec<Chunk>,
+    pub listing: Vec<Listed>,
+    pub symbols: BTreeMap<String, u16>,
+    /// What each symbol that isn't absolute is relative to.
+    pub relocations: BTreeMap<String, Relocation>,
+    pub publics: BTreeSet<String>,
+    pub externals: BTreeSet<String>,
+    pub code_size: u16,
+    pub data_size: u16,
+    /// The start address given to END, if any.
+    pub entry: Option<(u16, Relocation)>,
+}
+
+impl Assembly {
+    /// The assembled bytes as one contiguous image starting at the lowest address written,
+    /// with any gaps left by ORG or DS filled with zeroes.
+    pub fn image(&self) -> (u16, Vec<u8>) {
+        // Empty chunks, like a bare DB or DS 0, take up no room wherever they are.
+        let chunks = || self.chunks.iter().filter(|chunk| !chunk.bytes.is_empty());
+        let start = chunks().map(|chunk| chunk.address).min().unwrap_or(0);
+        let end = chunks().map(|chunk| chunk.address as usize + chunk.bytes.len()).max().unwrap_or(0);
+
+        let mut image = vec![0; end.saturating_sub(start as usize)];
+        for chunk in chunks() {
+            let offset = chunk.address as usize - start as usize;
+            image[offset..offset + chunk.bytes.len()].copy_from_slice(&chunk.bytes);
+        }
+        (start, image)
+    }
+
+    /// Each source line with the address it was assembled at, the bytes it produced and
+    /// their cost in clock cycles, followed by a table of every symbol.
+    pub fn listing(&self) -> String {
+        const BYTES_PER_LINE: usize = 4;
+        let mut listing = String::new();
+        let mut file = "";
+
+        for listed in &self.listing {
+            if listed.file != file {
+                file = &listed.file;
+                listing.push_str(&format!("{:>32}{}\n", "", file));
+            }
+
+            let address = listed.address.map_or(String::new(), |address| format!("{:04x}", address));
+            let bytes: Vec<String> = listed.bytes.iter().take(BYTES_PER_LINE).map(|byte| format!("{:02x}", byte)).collect();
+            let cycles = if listed.instruction && !listed.bytes.is_empty() {
+                let opcode = opcode(listed.bytes[0]);
+                if opcode.cycles == opcode.taken_cycles() {
+                    opcode.cycles.to_string()
+                } else {
+                    format!("{}/{}", opcode.taken_cycles(), opcode.cycles)
+                }
+            } else {
+                String::new()
+            };
+            let marker = if listed.expanded { "+" } else { " " };
+            listing.push_str(&format!("{:<4}  {:<11}  {:>5}  {:>5}{} {}\n", address, bytes.join(" "), cycles, listed.line, marker, listed.text));
+
+            // Long runs of data carry on over as many lines as they need.
+            for (index, chunk) in listed.bytes.chunks(BYTES_PER_LINE).enumerate().skip(1) {
+                let address = listed.address.unwrap_or(0).wrapping_add((index * BYTES_PER_LINE) as u16);
+                let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+                listing.push_str(&format!("{:04x}  {}\n", address, bytes.join(" ")));
+            }
+        }
+
+        // Relocatable values are marked the way M80 marks them.
+        listing.push_str("\nSymbols:\n");
+        for (name, value) in &self.symbols {
+            let marker = match self.relocations.get(name) {
+                Some(Relocation::Code) => "'",
+                Some(Relocation::Data) => "\"",
+                Some(Relocation::External(_)) => "*",
+                _ => " ",
+            };
+            listing.push_str(&format!("{:04x}{} {}\n", value, marker, name));
+        }
+        listing
+    }
+
+    /// Whether the source used anything that needs linking: the code or data segments, or
+    /// symbols from other modules.
+    pub fn relocatable(&self) -> bool {
+        !self.externals.is_empty() || self.chunks.iter().any(|chunk| chunk.segment != Segment::Absolute || !chunk.fixups.is_empty())
+            || self.code_size != 0 || self.data_size != 0
+    }
+
+    /// The assembly as a module for the linker. References to each external symbol are
+    /// chained together through the words that hold them, as M80 does.
+    pub fn module(&self) -> Module {
+        let mut module = Module { name: rel::name(&self.name), code_size: self.code_size, data_size: self.data_size, ..Module::default() };
+        let mut references: BTreeMap<&String, Vec<(Address, i32)>> = BTreeMap::new();
+
+        for chunk in &self.chunks {
+            let start = Address { segment: chunk.segment, offset: chunk.address };
+            for (index, &byte) in chunk.bytes.iter().enumerate() {
+                module.bytes.insert(start.next(index as u16), byte);
+            }
+            for &(offset, ref relocation) in &chunk.fixups {
+                let address = start.next(offset as u16);
+                let value = (chunk.bytes[offset] as u16 | (chunk.bytes[offset + 1] as u16) << 8) as i16 as i32;
+                match *relocation {
+                    Relocation::Code => { module.relocations.insert(address, Segment::Code); },
+                    Relocation::Data => { module.relocations.insert(address, Segment::Data); },
+                    Relocation::External(ref name) => references.entry(name).or_default().push((address, value)),
+                    Relocation::Absolute => {},
+                }
+            }
+        }
+
+        for (name, references) in references {
+            let mut previous = Address::absolute(0);
+            for (address, offset) in references {
+                module.bytes.insert(address, previous.offset as u8);
+                module.bytes.insert(address.next(1), (previous.offset >> 8) as u8);
+                if previous.segment != Segment::Absolute {
+                    module.relocations.insert(address, previous.segment);
+                }
+                if offset != 0 {
+                    module.offsets.insert(address, offset);
+                }
+                previous = address;
+            }
+            module.chains.push((previous, Chain::External(rel::name(name))));
+        }
+
+        for name in &self.publics {
+            let segment = match self.relocations.get(name) {
+                Some(Relocation::Code) => Segment::Code,
+                Some(Relocation::Data) => Segment::Data,
+                _ => Segment::Absolute,
+            };
+            module.publics.insert(rel::name(name), Address { segment, offset: self.symbols[name] });
+        }
+
+        module.entry = self.entry.as_ref().map(|&(offset, ref relocation)| Address { segment: segment(relocation), offset });
+        module
+    }
+}
+
+
+/// Splits `text` on `separator`, ignoring any inside quotes or inside the angle brackets a
+/// macro argument can be wrapped in.
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut quote = None;
+    let mut brackets = 0;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '\'' || c == '"' => quote = Some(c),
+            // Only a < opening a part groups; anywhere else it is a comparison or shift.
+            None if c == '<' && (brackets > 0 || text[start..index].trim().is_empty()) => brackets += 1,
+            None if c == '>' && brackets > 0 => brackets -= 1,
+            None if c == separator && brackets == 0 => {
+                parts.push(&text[start..index]);
+                start = index + c.len_utf8();
+            },
+            None => {},
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn strip_comment(line: &str) -> &str {
+    split_unquoted(line, ';')[0]
+}
+
+/// `text` without the angle brackets around it, if it has them.
+fn angle(text: &str) -> &str {
+    let text = text.trim();
+    text.strip_prefix('<').and_then(|text| text.strip_suffix('>')).unwrap_or(text)
+}
+
+const DIRECTIVES: &[&str] = &[
+    "ORG", "DB", "DW", "DS", "EQU", "SET", "END", "MACRO", "ENDM", "LOCAL", "REPT", "IRP", "IRPC", "EXITM",
+    "IF", "ELSE", "ENDIF", "INCLUDE",
+    // Microsoft's spellings and additions.
+    "DEFB", "DEFW", "DEFS", "DEFM", "DC", "DEFL", "ASET", "=", "COND", "ENDC", "IFT", "IFE", "IFF", "IF1", "IF2",
+    "IFDEF", "IFNDEF", "IFB", "IFNB", "IFIDN", "IFDIF", ".PHASE", ".DEPHASE", ".RADIX", ".COMMENT", ".Z80",
+    "ASEG", "CSEG", "DSEG", "COMMON", "PUBLIC", "ENTRY", "GLOBAL", "EXTRN", "EXT", "EXTERNAL",
+    // Listing controls and the like, which make no difference to the bytes produced.
+    "TITLE", "SUBTTL", "PAGE", "EJECT", "SPACE", "NAME", "STKLN", ".LIST", ".XLIST", ".CREF", ".XCREF",
+    ".LALL", ".SALL", ".XALL", ".SFCOND", ".LFCOND", ".TFCOND", ".PRINTX", ".8080", ".REQUEST",
+];
+
+fn is_directive(word: &str) -> bool {
+    DIRECTIVES.contains(&word)
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    (0..=255).any(|op_code| opcode(op_code).mnemonic == word)
+}
+
+/// The string `text` holds, if it is a single quoted string. A doubled quote inside it
+/// stands for the quote itself, so `'IT''S'` is `IT'S`.
+fn quoted(text: &str) -> Option<String> {
+    let text = text.trim();
+    let quote = text.chars().next().filter(|&c| c == '\'' || c == '"')?;
+    let mut string = String::new();
+    let mut characters = text[1..].chars();
+    while let Some(c) = characters.next() {
+        if c != quote {
+            string.push(c);
+            continue
+        }
+        match characters.next() {
+            Some(c) if c == quote => string.push(quote),
+            Some(_) => return None,
+            None => return Some(string),
+        }
+    }
+    None
+}
+
+/// The file an ASM80 `$INCLUDE(file)` control names.
+fn control_include(line: &str) -> Option<&str> {
+    let start = line.to_ascii_uppercase().find("INCLUDE(")? + "INCLUDE(".len();
+    let end = start + line[start..].find(')')?;
+    let name = line[start..end].trim();
+    // Names like :F1:FILE.SRC pick an ISIS drive; look next to the including file instead.
+    match name.get(3..4) {
+        Some(":") if name.starts_with(':') => Some(&name[4..]),
+        _ => Some(name),
+    }
+}
+
+/// `value` written out in `radix`, the way M80 turns a `%expression` macro argument into text.
+fn in_radix(value: u16, radix: u32) -> String {
+    let mut value = value as u32;
+    let mut digits = vec![];
+    loop {
+        digits.push(std::char::from_digit(value % radix, radix).unwrap().to_ascii_uppercase());
+        value /= radix;
+        if value == 0 {
+            return digits.iter().rev().collect()
+        }
+    }
+}
+
+fn segment(relocation: &Relocation) -> Segment {
+    match *relocation {
+        Relocation::Code => Segment::Code,
+        Relocation::Data => Segment::Data,
+        _ => Segment::Absolute,
+    }
+}
+
+fn relocation(segment: Segment) -> Relocation {
+    match segment {
+        Segment::Absolute => Relocation::Absolute,
+        Segment::Code => Relocation::Code,
+        Segment::Data => Relocation::Data,
+    }
+}
+
+fn byte(value: i32) -> Result<u8, String> {
+    if (-128..=255).contains(&value) { Ok(value as u8) } else { Err(format!("value out of range for a byte: {}", value)) }
+}
+
+fn word(value: i32) -> Result<u16, String> {
+    if (-32768..=65535).contains(&value) { Ok(value as u16) } else { Err(format!("value out of range for a word: {}", value)) }
+}
+
+struct Statement<'a> {
+    label: Option<&'a str>,
+    /// Whether the label ended in two colons, which makes it public.
+    public: bool,
+    operation: Option<String>,
+    operands: Vec<&'a str>,
+}
+
+fn parse(line: &str) -> Statement<'_> {
+    let line = strip_comment(line).trim_end();
+    let indented = line.starts_with(|c: char| c.is_whitespace());
+    let line = line.trim_start();
+
+    let (first, rest) = match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim_start()),
+        None => (line, ""),
+    };
+
+    let second = rest.split_whitespace().next().unwrap_or("").to_uppercase();
+
+    // Labels either end in a colon (two for M80's public ones), start in the first column
+    // or name what MACRO, EQU and SET define.
+    let (label, line) = if let Some(label) = first.strip_suffix("::").or_else(|| first.strip_suffix(':')) {
+        (Some(label), rest)
+    } else if matches!(second.as_str(), "MACRO" | "EQU" | "SET" | "DEFL" | "ASET" | "=")
+        || !indented && !first.is_empty() && !is_mnemonic(&first.to_uppercase()) && !is_directive(&first.to_uppercase()) {
+        (Some(first), rest)
+    } else {
+        (None, line)
+    };
+
+    let (operation, operands) = match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    };
+
+    Statement {
+        label,
+        public: first.ends_with("::"),
+        operation: if operation.is_empty() { None } else { Some(operation.to_uppercase()) },
+        operands: if operands.is_empty() { vec![] } else { split_unquoted(operands, ',').into_iter().map(|operand| operand.trim()).collect() },
+    }
+}
+
+struct Assembler {
+    dialect: Dialect,
+    symbols: BTreeMap<String, u16>,
+    /// What each symbol that isn't absolute is relative to.
+    relocations: BTreeMap<String, Relocation>,
+    /// Symbols given their value by SET, which can be changed later.
+    variables: BTreeSet<String>,
+    publics: BTreeSet<String>,
+    externals: BTreeSet<String>,
+    segment: Segment,
+    pc: u16,
+    /// Where each segment had got to when another was switched to.
+    counters: BTreeMap<Segment, u16>,
+    /// How far the addresses labels get are from where the code is put, inside .PHASE.
+    phase: u16,
+    radix: u32,
+    /// The last ordinary label, which local labels belong to.
+    scope: String,
+    /// The character that ends the .COMMENT block being skipped, if in one.
+    comment: Option<char>,
+    final_pass: bool,
+    assembly: Assembly,
+    macros: BTreeMap<String, Macro>,
+    conditions: Vec<Condition>,
+    /// How many LOCAL labels have been made so far this pass.
+    locals: usize,
+    nesting: usize,
+}
+
+impl Assembler {
+    /// The value of `$`: where the code being assembled will run.
+    fn location(&self) -> u16 {
+        self.pc.wrapping_add(self.phase)
+    }
+
+    /// What `$` is relative to. Code moved with .PHASE runs at a fixed address.
+    fn location_relocation(&self) -> Relocation {
+        if self.phase == 0 { relocation(self.segment) } else { Relocation::Absolute }
+    }
+
+    fn context(&self) -> Context<'_> {
+        Context {
+            symbols: &self.symbols,
+            relocations: &self.relocations,
+            location: self.location(),
+            segment: self.location_relocation(),
+            radix: self.radix,
+            dialect: self.dialect,
+            scope: &self.scope,
+        }
+    }
+
+    fn value(&self, text: &str) -> Result<Value, String> {
+        match expression::evaluate(text, &self.context()) {
+            // Forward references are only resolved in the second pass.
+            Err(_) if !self.final_pass => Ok(Value::absolute(0)),
+            result => result,
+        }
+    }
+
+    fn evaluate(&self, text: &str) -> Result<i32, String> {
+        let value = self.value(text)?;
+        if self.final_pass && value.relocation != Relocation::Absolute {
+            return Err(format!("{} isn't an absolute value", text))
+        }
+        Ok(value.number)
+    }
+
+    /// Evaluates an expression whose value is needed in the first pass. It can be relative
+    /// to the segment being assembled, which is how ORG and DS see it.
+    fn evaluate_now(&self, text: &str) -> Result<i32, String> {
+        let value = expression::evaluate(text, &self.context())?;
+        if value.relocation != Relocation::Absolute && value.relocation != relocation(self.segment) {
+            return Err(format!("{} isn't an absolute value", text))
+        }
+        Ok(value.number)
+    }
+
+    /// A word operand, and the fixup the linker needs to make to it if it isn't absolute.
+    fn word_operand(&self, text: &str, offset: usize, fixups: &mut Fixups) -> Result<u16, String> {
+        let value = self.value(text)?;
+        if value.relocation != Relocation::Absolute {
+            fixups.push((offset, value.relocation));
+        }
+        word(value.number)
+    }
+
+    fn name(&self, name: &str) -> String {
+        expression::canonical(name, self.dialect, &self.scope)
+    }
+
+    /// The name a symbol being defined is stored under, which can't be empty, as ASM80's
+    /// `$$` would be once its separators are gone.
+    fn defined_name(&self, name: &str) -> Result<String, String> {
+        let canonical = self.name(name);
+        if canonical.is_empty() {
+            return Err(format!("invalid name: {}", name))
+        }
+        Ok(canonical)
+    }
+
+    fn define(&mut self, name: &str, value: Value) -> Result<(), String> {
+        let name = self.defined_name(name)?;
+        let number = word(value.number)?;
+        if self.variables.contains(&name) {
+            return Err(format!("symbol already defined with SET: {}", name))
+        }
+        match self.symbols.get(&name) {
+            Some(&existing) if existing != number || !self.final_pass => Err(format!("symbol defined twice: {}", name)),
+            _ => {
+                self.symbols.insert(name.clone(), number);
+                if value.relocation != Relocation::Absolute {
+                    self.relocations.insert(name, value.relocation);
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// Defines `name` as the current location. Labels other than local ones also become the
+    /// label later local labels belong to.
+    fn label(&mut self, name: &str) -> Result<(), String> {
+        let location = Value { number: self.location() as i32, relocation: self.location_relocation() };
+        self.define(name, location)?;
+        if !name.starts_with('.') && !name.starts_with("??") {
+            self.scope = self.name(name);
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: Value) -> Result<(), String> {
+        let name = self.defined_name(name)?;
+        if self.symbols.contains_key(&name) && !self.variables.contains(&name) {
+            return Err(format!("symbol defined twice: {}", name))
+        }
+        self.variables.insert(name.clone());
+        self.symbols.insert(name.clone(), word(value.number)?);
+        match value.relocation {
+            Relocation::Absolute => self.relocations.remove(&name),
+            relocation => self.relocations.insert(name, relocation),
+        };
+        Ok(())
+    }
+
+    fn external(&mut self, name: &str) -> Result<(), String> {
+        let name = self.defined_name(name)?;
+        match self.relocations.get(&name) {
+            Some(Relocation::External(_)) => {},
+            _ if self.symbols.contains_key(&name) => return Err(format!("symbol defined twice: {}", name)),
+            _ => {},
+        }
+        self.symbols.insert(name.clone(), 0);
+        self.relocations.insert(name.clone(), Relocation::External(name.clone()));
+        self.externals.insert(name);
+        Ok(())
+    }
+
+    /// Moves the location counter on, keeping track of how big each segment has got.
+    fn advance(&mut self, count: u16) {
+        self.pc = self.pc.wrapping_add(count);
+        match self.segment {
+            Segment::Code => self.assembly.code_size = self.assembly.code_size.max(self.pc),
+            Segment::Data => self.assembly.data_size = self.assembly.data_size.max(self.pc),
+            Segment::Absolute => {},
+        }
+    }
+
+    fn emit(&mut self, bytes: Vec<u8>, fixups: Fixups) {
+        if self.final_pass {
+            for (_, relocation) in &fixups {
+                if let Relocation::External(ref name) = *relocation {
+                    self.externals.insert(name.clone());
+                }
+            }
+            self.assembly.chunks.push(Chunk { segment: self.segment, address: self.pc, bytes: bytes.clone(), fixups });
+        }
+        self.advance(bytes.len() as u16);
+    }
+
+    fn switch(&mut self, segment: Segment) {
+        self.counters.insert(self.segment, self.pc);
+        self.segment = segment;
+        self.pc = self.counters.get(&segment).cloned().unwrap_or(0);
+    }
+
+    /// Returns false once END has been reached.
+    fn statement(&mut self, statement: &Statement) -> Result<bool, String> {
+        let operation = match statement.operation {
+            Some(ref operation) => operation.as_str(),
+            None => {
+                if let Some(label) = statement.label {
+                    self.label(label)?;
+                }
+                if statement.public {
+                    let name = self.name(statement.label.unwrap());
+                    self.publics.insert(name);
+                }
+                return Ok(true)
+            },
+        };
+
+        match operation {
+            "EQU" => {
+                let label = statement.label.ok_or_else(|| "EQU needs a label".to_string())?;
+                // An EQU that refers forward is left undefined until the second pass.
+                match expression::evaluate(self.operand(statement, 0)?, &self.context()) {
+                    Ok(value) => self.define(label, value)?,
+                    Err(message) => if self.final_pass { return Err(message) },
+                }
+                return Ok(true)
+            },
+            "SET" | "DEFL" | "ASET" | "=" => {
+                let label = statement.label.ok_or_else(|| format!("{} needs a label", operation))?;
+                let value = self.value(self.operand(statement, 0)?)?;
+                self.set(label, value)?;
+                return Ok(true)
+            },
+            _ => {},
+        }
+
+        if let Some(label) = statement.label {
+            self.label(label)?;
+            if statement.public {
+                let name = self.name(label);
+                self.publics.insert(name);
+            }
+        }
+
+        match operation {
+            "ORG" => self.pc = word(self.evaluate_now(self.operand(statement, 0)?)?)?,
+            "DB" | "DEFB" | "DEFM" | "DC" => {
+                let mut bytes = vec![];
+                for operand in &statement.operands {
+                    match quoted(operand) {
+                        Some(text) if text.len() != 1 => bytes.extend(text.bytes()),
+                        _ => bytes.push(byte(self.evaluate(operand)?)?),
+                    }
+                }
+                // DC marks the end of a string by setting the top bit of its last character.
+                if operation == "DC" {
+                    if let Some(last) = bytes.last_mut() {
+                        *last |= 0x80;
+                    }
+                }
+                self.emit(bytes, vec![]);
+            },
+            "DW" | "DEFW" => {
+                let mut bytes = vec![];
+                let mut fixups = vec![];
+                for operand in &statement.operands {
+                    let value = self.word_operand(operand, bytes.len(), &mut fixups)?;
+                    bytes.push(value as u8);
+                    bytes.push((value >> 8) as u8);
+                }
+                self.emit(bytes, fixups);
+            },
+            "DS" | "DEFS" => {
+                let size = word(self.evaluate_now(self.operand(statement, 0)?)?)?;
+                match statement.operands.get(1) {
+                    Some(fill) => {
+                        let fill = byte(self.evaluate(fill)?)?;
+                        self.emit(vec![fill; size as usize], vec![]);
+                    },
+                    None => self.advance(size),
+                }
+            },
+            ".PHASE" => {
+                let location = word(self.evaluate_now(self.operand(statement, 0)?)?)?;
+                self.phase = location.wrapping_sub(self.pc);
+            },
+            ".DEPHASE" => self.phase = 0,
+            ".RADIX" => {
+                // The new radix is always written in decimal.
+                let radix = self.operand(statement, 0)?;
+                self.radix = radix.parse().ok().filter(|radix| (2..=16).contains(radix))
+                    .ok_or_else(|| format!("invalid radix: {}", radix))?;
+            },
+            "END" => {
+                if let Some(entry) = statement.operands.first() {
+                    let value = self.value(entry)?;
+                    self.assembly.entry = Some((word(value.number)?, value.relocation));
+                }
+                return Ok(false)
+            },
+            "ASEG" => self.switch(Segment::Absolute),
+            "CSEG" => self.switch(Segment::Code),
+            "DSEG" => self.switch(Segment::Data),
+            "PUBLIC" | "ENTRY" | "GLOBAL" => {
+                for operand in &statement.operands {
+                    let name = self.name(operand);
+                    self.publics.insert(name);
+                }
+            },
+            "EXTRN" | "EXT" | "EXTERNAL" => {
+                for operand in &statement.operands {
+                    self.external(operand)?;
+                }
+            },
+            "NAME" => {
+                // M80 writes NAME('MODULE'), Intel NAME MODULE.
+                let name = self.operand(statement, 0)?.trim_start_matches('(').trim_end_matches(')');
+                self.assembly.name = quoted(name).unwrap_or_else(|| name.to_string());
+            },
+            "COMMON" => return Err("COMMON blocks aren't supported".to_string()),
+            ".Z80" => return Err("Z80 instructions aren't supported".to_string()),
+            "TITLE" | "SUBTTL" | "PAGE" | "EJECT" | "SPACE" | "STKLN" | ".LIST" | ".XLIST" | ".CREF" | ".XCREF"
+            | ".LALL" | ".SALL" | ".XALL" | ".SFCOND" | ".LFCOND" | ".TFCOND" | ".PRINTX" | ".8080" | ".REQUEST" => {},
+            _ => {
+                let (bytes, fixups) = self.instruction(operation, &statement.operands)?;
+                self.emit(bytes, fixups);
+            },
+        }
+
+        Ok(true)
+    }
+
+    fn operand<'a>(&self, statement: &Statement<'a>, index: usize) -> Result<&'a str, String> {
+        statement.operands.get(index).cloned().ok_or_else(|| format!("{} needs an operand", statement.operation.as_ref().unwrap()))
+    }
+
+    /// Whether the condition an IF-like directive tests holds.
+    fn condition(&self, operation: &str, statement: &Statement) -> Result<bool, String> {
+        match operation {
+            "IF1" => Ok(!self.final_pass),
+            "IF2" => Ok(self.final_pass),
+            "IFDEF" | "IFNDEF" => {
+                let defined = self.symbols.contains_key(&self.name(self.operand(statement, 0)?));
+                Ok(defined == (operation == "IFDEF"))
+            },
+            "IFB" | "IFNB" => {
+                let blank = angle(&statement.operands.join(",")).trim().is_empty();
+                Ok(blank == (operation == "IFB"))
+            },
+            "IFIDN" | "IFDIF" => {
+                let identical = angle(statement.operands.first().unwrap_or(&"")) == angle(statement.operands.get(1).unwrap_or(&""));
+                Ok(identical == (operation == "IFIDN"))
+            },
+            "IFE" | "IFF" => Ok(self.evaluate(self.operand(statement, 0)?)? == 0),
+            _ => Ok(self.evaluate(self.operand(statement, 0)?)? != 0),
+        }
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[&str]) -> Result<(Vec<u8>, Fixups), String> {
+        if mnemonic == "RST" {
+            let vector = match operands {
+                [vector] => self.evaluate(vector)?,
+                _ => return Err("RST needs one operand".to_string()),
+            };
+            if !(0..8).contains(&vector) {
+                return Err(format!("RST vector out of range: {}", vector))
+            }
+            return Ok((vec![0xc7 | (vector as u8) << 3], vec![]))
+        }
+
+        let mut known = false;
+        for op_code in 0..=255 {
+            let opcode = opcode(op_code);
+            if opcode.mnemonic != mnemonic {
+                continue
+            }
+            known = true;
+
+            let registers: Vec<&str> = opcode.registers.split(',').filter(|register| !register.is_empty()).collect();
+            let immediate = if opcode.operand == Operand::Implied { 0 } else { 1 };
+            if operands.len() != registers.len() + immediate {
+                continue
+            }
+            if !registers.iter().zip(operands).all(|(register, operand)| operand.eq_ignore_ascii_case(register)) {
+                continue
+            }
+
+            let mut bytes = vec![op_code];
+            let mut fixups = vec![];
+            match opcode.operand {
+                Operand::Implied => {},
+                Operand::Byte => bytes.push(byte(self.evaluate(operands[registers.len()])?)?),
+                Operand::Word => {
+                    let value = self.word_operand(operands[registers.len()], 1, &mut fixups)?;
+                    bytes.push(value as u8);
+                    bytes.push((value >> 8) as u8);
+                },
+            }
+            return Ok((bytes, fixups))
+        }
+
+        if known {
+            Err(format!("invalid operands for {}: {}", mnemonic, operands.join(",")))
+        } else {
+            Err(format!("unknown instruction: {}", mnemonic))
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SourceLine {
+    file: Rc<String>,
+    number: usize,
+    text: String,
+    /// Where the macro or REPT this line came out of was expanded, if it did.
+    expansion: Option<Rc<String>>,
+}
+
+impl SourceLine {
+    fn error(&self, message: String) -> Error {
+        let message = match self.expansion {
+            Some(ref expansion) => format!("{} (expanded from {})", message, expansion),
+            None => message,
+        };
+        Error { file: self.file.to_string(), line: self.number, message }
+    }
+}
+
+fn source_lines(file: &str, source: &str) -> Vec<SourceLine> {
+    let file = Rc::new(file.to_string());
+    source.lines().enumerate()
+        .map(|(index, text)| SourceLine { file: file.clone(), number: index + 1, text: text.to_string(), expansion: None })
+        .collect()
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<SourceLine>,
+}
+
+struct Condition {
+    active: bool,
+    /// Whether the enclosing block is being assembled at all.
+    enclosing: bool,
+    taken: bool,
+    line: SourceLine,
+}
+
+/// How assembling a block of lines finished.
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Continue,
+    /// EXITM left the macro or repetition early.
+    Exit,
+    End,
+}
+
+// Macros that expand themselves, or files that include themselves, stop here.
+const MAX_NESTING: usize = 64;
+
+/// Replaces every whole word in `text` that names one of `names` with its value. An `&`
+/// next to a replaced word joins it to the text on its other side and is dropped.
+fn substitute(text: &str, names: &[String], values: &[String]) -> String {
+    let mut tokens: Vec<(String, bool)> = vec![];
+    let mut word = String::new();
+    for c in text.chars().chain(Some('\0')) {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '?' || c == '@' {
+            word.push(c);
+            continue
+        }
+        if !word.is_empty() {
+            match names.iter().position(|name| name.eq_ignore_ascii_case(&word)) {
+                Some(index) => tokens.push((values[index].clone(), true)),
+                None => tokens.push((word.clone(), false)),
+            }
+            word.clear();
+        }
+        if c != '\0' {
+            tokens.push((c.to_string(), false));
+        }
+    }
+
+    let mut result = String::new();
+    for (index, (token, _)) in tokens.iter().enumerate() {
+        let joins = token == "&" && (index > 0 && tokens[index - 1].1 || tokens.get(index + 1).is_some_and(|next| next.1));
+        if !joins {
+            result.push_str(token);
+        }
+    }
+    result
+}
+
+impl Assembler {
+    fn list(&mut self, line: &SourceLine) {
+        if self.final_pass {
+            self.assembly.listing.push(Listed {
+                file: line.file.to_string(),
+                line: line.number,
+                address: None,
+                bytes: vec![],
+                text: line.text.clone(),
+                expanded: line.expansion.is_some(),
+                instruction: false,
+            });
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.conditions.last().is_none_or(|condition| condition.active)
+    }
+
+    /// Collects the body of a MACRO, REPT, IRP or IRPC starting at `index`, up to its ENDM.
+    fn body(lines: &[SourceLine], mut index: usize, opening: &SourceLine) -> Result<(Vec<SourceLine>, usize), Error> {
+        let mut depth = 0;
+        let mut body = vec![];
+        while index < lines.len() {
+            let line = &lines[index];
+            index += 1;
+            match parse(&line.text).operation.as_deref() {
+                Some("MACRO") | Some("REPT") | Some("IRP") | Some("IRPC") => depth += 1,
+                Some("ENDM") if depth == 0 => return Ok((body, index)),
+                Some("ENDM") => depth -= 1,
+                _ => {},
+            }
+            body.push(line.clone());
+        }
+        Err(opening.error("missing ENDM".to_string()))
+    }
+
+    /// The body of a macro with `arguments` in place of its parameters.
+    fn expand(&mut self, name: &str, arguments: &[&str], line: &SourceLine) -> Result<Vec<SourceLine>, Error> {
+        let (mut names, body) = {
+            let definition = &self.macros[name];
+            (definition.parameters.clone(), definition.body.clone())
+        };
+        if arguments.len() > names.len() {
+            return Err(line.error(format!("too many arguments to {}", name)))
+        }
+
+        // Arguments can be wrapped in <> to pass commas and spaces, or written %expression
+        // to pass the expression's value.
+        let mut values = vec![];
+        for index in 0..names.len() {
+            values.push(match arguments.get(index) {
+                Some(argument) if argument.starts_with('%') => {
+                    let value = self.evaluate(&argument[1..]).and_then(word).map_err(|message| line.error(message))?;
+                    in_radix(value, self.radix)
+                },
+                Some(argument) => angle(argument).to_string(),
+                None => String::new(),
+            });
+        }
+
+        // LOCAL names get a fresh label for every expansion.
+        let mut expanded = vec![];
+        for body_line in body {
+            let statement = parse(&body_line.text);
+            if statement.operation.as_deref() == Some("LOCAL") {
+                for local in &statement.operands {
+                    self.locals += 1;
+                    names.push(local.to_string());
+                    values.push(format!("??{:04}", self.locals));
+                }
+                continue
+            }
+            expanded.push(body_line);
+        }
+
+        Ok(Assembler::expanded(&expanded, &names, &values, line))
+    }
+
+    /// `body` with `names` replaced by `values`, marked as expanded from `line`.
+    fn expanded(body: &[SourceLine], names: &[String], values: &[String], line: &SourceLine) -> Vec<SourceLine> {
+        let expansion = Rc::new(format!("{}:{}", line.file, line.number));
+        body.iter().map(|body_line| SourceLine {
+            text: substitute(&body_line.text, names, values),
+            expansion: Some(expansion.clone()),
+            ..body_line.clone()
+        }).collect()
+    }
+
+    /// Assembles what a macro, REPT or IRP expanded to. An EXITM inside stops it, and closes
+    /// any IF it was in.
+    fn expansion(&mut self, lines: &[SourceLine]) -> Result<Outcome, Error> {
+        if lines.is_empty() {
+            return Ok(Outcome::Continue)
+        }
+        let conditions = self.conditions.len();
+        let outcome = self.block(lines)?;
+        if outcome == Outcome::Exit {
+            self.conditions.truncate(conditions);
+        }
+        Ok(outcome)
+    }
+
+    fn include(&mut self, name: &str, line: &SourceLine) -> Result<Vec<SourceLine>, Error> {
+        let name = quoted(name).unwrap_or_else(|| name.to_string());
+        let directory = Path::new(line.file.as_str()).parent().map_or(PathBuf::new(), Path::to_path_buf);
+        let mut path = directory.join(&name);
+        // Sources written on CP/M and ISIS name their files in upper case whatever case
+        // they were copied over in.
+        if !path.exists() && directory.join(name.to_lowercase()).exists() {
+            path = directory.join(name.to_lowercase());
+        }
+
+        let mut source = String::new();
+        File::open(&path).and_then(|mut file| file.read_to_string(&mut source))
+            .map_err(|error| line.error(format!("can't include {}: {}", path.display(), error)))?;
+        Ok(source_lines(&path.to_string_lossy(), &source))
+    }
+
+    fn block(&mut self, lines: &[SourceLine]) -> Result<Outcome, Error> {
+        self.nesting += 1;
+        if self.nesting > MAX_NESTING {
+            return Err(lines[0].error("macros or includes nested too deeply".to_string()))
+        }
+        let outcome = self.lines(lines);
+        self.nesting -= 1;
+        outcome
+    }
+
+    fn lines(&mut self, lines: &[SourceLine]) -> Result<Outcome, Error> {
+        let mut index = 0;
+        while index < lines.len() {
+            let line = &lines[index];
+            index += 1;
+            let listed = self.assembly.listing.len();
+            let (location, chunks) = (self.location(), self.assembly.chunks.len());
+            self.list(line);
+
+            if let Some(delimiter) = self.comment {
+                if line.text.contains(delimiter) {
+                    self.comment = None;
+                }
+                continue
+            }
+            if self.dialect == Dialect::M80 && line.text.starts_with('*') {
+                continue
+            }
+            // ASM80 controls start with $ in the first column. Only $INCLUDE affects the bytes.
+            if self.dialect == Dialect::Asm80 && line.text.starts_with('$') {
+                if let Some(name) = control_include(&line.text).filter(|_| self.active()) {
+                    let included = self.include(name, line)?;
+                    match self.block(&included)? {
+                        Outcome::Continue => {},
+                        outcome => return Ok(outcome),
+                    }
+                }
+                continue
+            }
+
+            let statement = parse(&line.text);
+            let operation = statement.operation.clone().unwrap_or_default();
+            let operand = |index: usize| statement.operands.get(index).cloned().ok_or_else(|| line.error(format!("{} needs an operand", operation)));
+
+            match operation.as_str() {
+                "IF" | "IFT" | "COND" | "IFE" | "IFF" | "IF1" | "IF2" | "IFDEF" | "IFNDEF" | "IFB" | "IFNB" | "IFIDN" | "IFDIF" => {
+                    let enclosing = self.active();
+                    let active = enclosing && self.condition(&operation, &statement).map_err(|message| line.error(message))?;
+                    self.conditions.push(Condition { active, enclosing, taken: active, line: line.clone() });
+                },
+                "ELSE" => {
+                    let condition = self.conditions.last_mut().ok_or_else(|| line.error("ELSE without IF".to_string()))?;
+                    condition.active = condition.enclosing && !condition.taken;
+                    condition.taken = true;
+                },
+                "ENDIF" | "ENDC" => {
+                    self.conditions.pop().ok_or_else(|| line.error(format!("{} without IF", operation)))?;
+                },
+                _ if !self.active() => {},
+                ".COMMENT" => {
+                    // The comment runs from the first character after .COMMENT to the next
+                    // place that character appears.
+                    let start = line.text.to_ascii_uppercase().find(".COMMENT").unwrap() + ".COMMENT".len();
+                    let mut rest = line.text[start..].trim_start().chars();
+                    let delimiter = rest.next().ok_or_else(|| line.error(".COMMENT needs a delimiter".to_string()))?;
+                    if !rest.as_str().contains(delimiter) {
+                        self.comment = Some(delimiter);
+                    }
+                },
+                "MACRO" => {
+                    let name = statement.label.ok_or_else(|| line.error("MACRO needs a name".to_string()))?.to_uppercase();
+                    let (body, next) = Assembler::body(lines, index, line)?;
+                    index = next;
+                    for body_line in &lines[index - body.len() - 1..index] {
+                        self.list(body_line);
+                    }
+                    let parameters = statement.operands.iter().map(|parameter| parameter.to_string()).collect();
+                    self.macros.insert(name, Macro { parameters, body });
+                },
+                "REPT" | "IRP" | "IRPC" => {
+                    // Each repetition gets its own value for the parameter, if there is one.
+                    let (names, repetitions) = if operation == "REPT" {
+                        let count = self.evaluate(operand(0)?).map_err(|message| line.error(message))?;
+                        (vec![], vec![vec![]; count.max(0) as usize])
+                    } else {
+                        let values = angle(statement.operands.get(1).cloned().unwrap_or(""));
+                        let values: Vec<String> = if operation == "IRP" {
+                            split_unquoted(values, ',').into_iter().filter(|value| !value.trim().is_empty()).map(|value| angle(value).to_string()).collect()
+                        } else {
+                            values.chars().map(|c| c.to_string()).collect()
+                        };
+                        (vec![operand(0)?.to_string()], values.into_iter().map(|value| vec![value]).collect())
+                    };
+                    let (body, next) = Assembler::body(lines, index, line)?;
+                    index = next;
+                    self.list(&lines[index - 1]);
+                    for values in repetitions {
+                        match self.expansion(&Assembler::expanded(&body, &names, &values, line))? {
+                            Outcome::Continue => {},
+                            Outcome::Exit => break,
+                            Outcome::End => return Ok(Outcome::End),
+                        }
+                    }
+                },
+                "EXITM" => {
+                    if line.expansion.is_none() {
+                        return Err(line.error("EXITM outside a macro".to_string()))
+                    }
+                    return Ok(Outcome::Exit)
+                },
+                "ENDM" => return Err(line.error("ENDM without MACRO or REPT".to_string())),
+                "INCLUDE" => {
+                    let included = self.include(operand(0)?, line)?;
+                    if !included.is_empty() {
+                        match self.block(&included)? {
+                            Outcome::Continue => {},
+                            outcome => return Ok(outcome),
+                        }
+                    }
+                },
+                name if self.macros.contains_key(name) => {
+                    if let Some(label) = statement.label {
+                        self.label(label).map_err(|message| line.error(message))?;
+                        if statement.public {
+                            let name = self.name(label);
+                            self.publics.insert(name);
+                        }
+                        if self.final_pass {
+                            self.assembly.listing[listed].address = Some(location);
+                        }
+                    }
+                    let expanded = self.expand(name, &statement.operands, line)?;
+                    if self.expansion(&expanded)? == Outcome::End {
+                        return Ok(Outcome::End)
+                    }
+                },
+                _ => {
+                    let result = self.statement(&statement);
+                    if self.final_pass {
+                        let bytes: Vec<u8> = self.assembly.chunks[chunks..].iter().flat_map(|chunk| chunk.bytes.clone()).collect();
+                        let instruction = is_mnemonic(&operation);
+                        // EQU and SET lines show the value they define rather than where they are.
+                        let address = if matches!(operation.as_str(), "EQU" | "SET" | "DEFL" | "ASET" | "=") {
+                            statement.label.and_then(|label| self.symbols.get(&self.name(label)).cloned())
+                        } else if matches!(operation.as_str(), "ORG" | ".PHASE" | ".DEPHASE") {
+                            Some(self.location())
+                        } else if statement.label.is_some() || !bytes.is_empty() || operation == "DS" || operation == "DEFS" {
+                            Some(location)
+                        } else {
+                            None
+                        };
+                        self.assembly.listing[listed].address = address;
+                        self.assembly.listing[listed].bytes = bytes;
+                        self.assembly.listing[listed].instruction = instruction;
+                    }
+                    match result {
+                        Ok(true) => {},
+                        Ok(false) => return Ok(Outcome::End),
+                        Err(message) => return Err(line.error(message)),
+                    }
+                },
+            }
+        }
+
+        Ok(Outcome::Continue)
+    }
+}
+
+/// Assembles 8080 source in two passes: the first to find where every label is, the second
+/// to emit bytes now that all of them are known. `name` is used in error messages and to
+/// find INCLUDEd files.
+pub fn assemble(name: &str, source: &str, dialect: Dialect) -> Result<Assembly, Error> {
+    let mut assembler = Assembler {
+        dialect,
+        symbols: BTreeMap::new(),
+        relocations: BTreeMap::new(),
+        variables: BTreeSet::new(),
+        publics: BTreeSet::new(),
+        externals: BTreeSet::new(),
+        segment: Segment::Absolute,
+        pc: 0,
+        counters: BTreeMap::new(),
+        phase: 0,
+        radix: 10,
+        scope: String::new(),
+        comment: None,
+        final_pass: false,
+        assembly: Assembly {
+            name: Path::new(name).file_stem().map_or(String::new(), |stem| stem.to_string_lossy().to_uppercase()),
+            chunks: vec![],
+            listing: vec![],
+            symbols: BTreeMap::new(),
+            relocations: BTreeMap::new(),
+            publics: BTreeSet::new(),
+            externals: BTreeSet::new(),
+            code_size: 0,
+            data_size: 0,
+            entry: None,
+        },
+        macros: BTreeMap::new(),
+        conditions: vec![],
+        locals: 0,
+        nesting: 0,
+    };
+    let lines = source_lines(name, source);
+
+    for &final_pass in &[false, true] {
+        assembler.segment = Segment::Absolute;
+        assembler.counters.clear();
+        assembler.pc = 0;
+        assembler.phase = 0;
+        assembler.radix = 10;
+        assembler.scope.clear();
+        assembler.comment = None;
+        assembler.final_pass = final_pass;
+        assembler.macros.clear();
+        assembler.conditions.clear();
+        assembler.locals = 0;
+        assembler.nesting = 0;
+
+        if !lines.is_empty() && assembler.block(&lines)? != Outcome::End {
+            if let Some(condition) = assembler.conditions.last() {
+                return Err(condition.line.error("IF without ENDIF".to_string()))
+            }
+        }
+    }
+
+    for symbol in &assembler.publics {
+        let message = match assembler.relocations.get(symbol) {
+            _ if !assembler.symbols.contains_key(symbol) => format!("public symbol never defined: {}", symbol),
+            Some(Relocation::External(_)) => format!("symbol is both public and external: {}", symbol),
+            _ => continue,
+        };
+        return Err(Error { file: name.to_string(), line: 0, message })
+    }
+
+    assembler.assembly.symbols = assembler.symbols;
+    assembler.assembly.relocations = assembler.relocations;
+    assembler.assembly.publics = assembler.publics;
+    assembler.assembly.externals = assembler.externals;
+    Ok(assembler.assembly)
+}
+
+pub fn assemble_file(path: &str, dialect: Dialect) -> Result<Assembly, Error> {
+    let mut source = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|error| Error { file: path.to_string(), line: 0, message: error.to_string() })?;
+    assemble(path, &source, dialect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disassembler;
+    use std::env;
+    use std::fs;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        match assemble("test.asm", source, Dialect::Native) {
+            Ok(assembly) => assembly.image().1,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    fn error(source: &str) -> String {
+        match assemble("test.asm", source, Dialect::Native) {
+            Ok(_) => panic!("{:?} assembled", source),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn every_opcode_survives_disassembly_and_reassembly() {
+        for opcode in 0..=0xff {
+            let memory = [opcode, 0x34, 0x12];
+            let instruction = disassembler::decode(&memory, 0);
+            let source = format!("\t{}\n", instruction.source());
+            assert_eq!(bytes(&source), instruction.bytes, "{:02x} disassembled to {:?}", opcode, source);
+        }
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        assert_eq!(bytes("\tJMP NEXT\n\tLXI H,DATA\nNEXT:\tHLT\nDATA:\tDW NEXT\n"), [0xc3, 0x06, 0x00, 0x21, 0x07, 0x00, 0x76, 0x06, 0x00]);
+    }
+
+    #[test]
+    fn a_label_that_moves_between_passes_is_an_error() {
+        assert_eq!(error("\tIF1\n\tNOP\n\tENDIF\nHERE:\tNOP\n"), "test.asm:4: symbol defined twice: HERE");
+    }
+
+    #[test]
+    fn undefined_symbols_are_reported_with_their_line() {
+        assert_eq!(error("\tNOP\n\tJMP NOWHERE\n"), "test.asm:2: undefined symbol: NOWHERE");
+    }
+
+    #[test]
+    fn numbers_take_a_radix_from_their_suffix_or_prefix() {
+        assert_eq!(bytes("\tDB 10H,17O,17Q,101B,99D,99,0x1F,$1F,0FFH\n"), [0x10, 0o17, 0o17, 5, 99, 99, 0x1f, 0x1f, 0xff]);
+        assert_eq!(bytes("\t.RADIX 16\n\tDB 10,1B,1D,11O\n"), [0x10, 0x1b, 0x1d, 9]);
+    }
+
+    #[test]
+    fn dollar_is_where_the_line_starts() {
+        assert_eq!(bytes("\tORG 100H\n\tNOP\n\tJMP $\n\tDW $+2\n"), [0x00, 0xc3, 0x01, 0x01, 0x06, 0x01]);
+    }
+
+    #[test]
+    fn equ_set_and_strings() {
+        assert_eq!(bytes("TWO\tEQU 2\nN\tSET 1\n\tDB N\nN\tSET N+TWO\n\tDB N,'Hi',\"'\"\n"), [1, 3, b'H', b'i', b'\'']);
+    }
+
+    #[test]
+    fn macros_substitute_their_arguments() {
+        let source = "
+LOAD    MACRO   REG,VALUE
+        MVI     REG,VALUE
+        ENDM
+        LOAD    A,1
+        LOAD    B,2
+";
+        assert_eq!(bytes(source), [0x3e, 1, 0x06, 2]);
+    }
+
+    #[test]
+    fn local_labels_are_new_in_each_expansion() {
+        let source = "
+WAIT    MACRO
+        LOCAL   AGAIN
+AGAIN:  DCR     A
+        JNZ     AGAIN
+        ENDM
+        WAIT
+        WAIT
+";
+        assert_eq!(bytes(source), [0x3d, 0xc2, 0x00, 0x00, 0x3d, 0xc2, 0x04, 0x00]);
+    }
+
+    #[test]
+    fn conditions_pick_a_branch() {
+        assert_eq!(bytes("\tIF 1\n\tDB 1\n\tELSE\n\tDB 2\n\tENDIF\n\tIF 0\n\tDB 3\n\tELSE\n\tDB 4\n\tENDIF\n"), [1, 4]);
+    }
+
+    #[test]
+    fn exitm_stops_an_expansion() {
+        let source = "
+SOME    MACRO   COUNT
+        IF      COUNT EQ 0
+        EXITM
+        ENDIF
+        DB      COUNT
+        ENDM
+        SOME    0
+        SOME    1
+";
+        assert_eq!(bytes(source), [1]);
+    }
+
+    #[test]
+    fn repeats_expand_their_bodies() {
+        assert_eq!(bytes("\tREPT 3\n\tNOP\n\tENDM\n"), [0, 0, 0]);
+        assert_eq!(bytes("\tIRP X,<1,2,3>\n\tDB X*2\n\tENDM\n"), [2, 4, 6]);
+        assert_eq!(bytes("\tIRPC C,ABC\n\tDB 'C'\n\tENDM\n"), b"ABC");
+    }
+
+    #[test]
+    fn an_unclosed_macro_is_an_error() {
+        assert_eq!(error("\tREPT 2\n\tNOP\n"), "test.asm:1: missing ENDM");
+    }
+
+    #[test]
+    fn includes_are_found_next_to_the_source() {
+        let directory = env::temp_dir().join(format!("rs8080-include-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("inner.asm"), "VALUE\tEQU 42\n\tDB VALUE\n").unwrap();
+        fs::write(directory.join("outer.asm"), "\tNOP\n\tINCLUDE inner.asm\n\tDB VALUE+1\n\tINCLUDE missing.asm\n").unwrap();
+
+        let result = assemble_file(&directory.join("outer.asm").to_string_lossy(), Dialect::Native);
+        let error = result.err().unwrap();
+        assert_eq!(error.line, 4);
+        assert!(error.message.starts_with("can't include "), "{}", error);
+
+        fs::write(directory.join("outer.asm"), "\tNOP\n\tINCLUDE inner.asm\n\tDB VALUE+1\n").unwrap();
+        let assembly = assemble_file(&directory.join("outer.asm").to_string_lossy(), Dialect::Native).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(assembly.image().1, [0, 42, 43]);
+    }
+
+    #[test]
+    fn errors_in_included_files_name_that_file() {
+        let directory = env::temp_dir().join(format!("rs8080-include-error-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("inner.asm"), "\tNOP\n\tBOGUS\n").unwrap();
+        fs::write(directory.join("outer.asm"), "\tINCLUDE inner.asm\n").unwrap();
+        let error = assemble_file(&directory.join("outer.asm").to_string_lossy(), Dialect::Native).err().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(error.file.ends_with("inner.asm"), "{}", error);
+        assert_eq!((error.line, error.message.as_str()), (2, "unknown instruction: BOGUS"));
+    }
+
+    #[test]
+    fn the_image_starts_at_the_lowest_byte_written() {
+        let (start, image) = assemble("test", "\tORG 200H\n\tNOP\n\tORG 100H\n\tHLT\n", Dialect::Native).unwrap().image();
+        assert_eq!((start, image.len()), (0x100, 0x101));
+        assert_eq!(image[0], 0x76);
+        assert!(image[1..].iter().all(|&byte| byte == 0), "the gap is filled with zeroes");
+    }
+
+    #[test]
+    fn empty_chunks_below_the_code_are_ignored() {
+        for empty in &["\tDB ''\n", "\tDB\n", "\tDS 0,1\n"] {
+            let source = format!("{}\tORG 100H\n\tNOP\n", empty);
+            assert_eq!(assemble("test", &source, Dialect::Native).unwrap().image(), (0x100, vec![0]), "{:?}", empty);
+        }
+    }
+
+    fn assemble_in(source: &str, dialect: Dialect) -> Assembly {
+        match assemble("test.asm", source, dialect) {
+            Ok(assembly) => assembly,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    #[test]
+    fn m80_comments_are_skipped() {
+        let source = "* A comment in the first column.\n\tNOP\n\t.COMMENT /\n\tHLT\n\t/\n\t.COMMENT %one line% \n\tDB X'1F'\n";
+        assert_eq!(assemble_in(source, Dialect::M80).image().1, [0x00, 0x1f]);
+        assert_eq!(bytes("\tDB 2\n\tDB 3*4\n"), [2, 12], "* is only a comment to M80");
+    }
+
+    #[test]
+    fn m80_phase_moves_labels_but_not_bytes() {
+        let source = "\tNOP\n\t.PHASE 1000H\nHERE:\tJMP HERE\n\t.DEPHASE\nTHERE:\tDW $\n";
+        let assembly = assemble_in(source, Dialect::M80);
+        assert_eq!(assembly.image(), (0, vec![0x00, 0xc3, 0x00, 0x10, 0x04, 0x00]));
+        assert_eq!((assembly.symbols["HERE"], assembly.symbols["THERE"]), (0x1000, 4));
+    }
+
+    #[test]
+    fn m80_radix_changes_plain_numbers() {
+        let source = "\t.RADIX 8\n\tDB 17,10D\n\t.RADIX 16\n\tDB 1F,1B\n";
+        assert_eq!(assemble_in(source, Dialect::M80).image().1, [0o17, 10, 0x1f, 0x1b]);
+        assert_eq!(error("\t.RADIX 17\n"), "test.asm:1: invalid radix: 17");
+    }
+
+    #[test]
+    fn m80_colons_and_hashes_make_publics_and_externals() {
+        let assembly = assemble_in("\tCSEG\nMAIN::\tCALL PRINT##\n\tJMP MAIN\n", Dialect::M80);
+        assert_eq!(assembly.publics.iter().collect::<Vec<_>>(), ["MAIN"]);
+        assert_eq!(assembly.externals.iter().collect::<Vec<_>>(), ["PRINT"]);
+        assert_eq!(assembly.relocations["MAIN"], Relocation::Code);
+        assert!(assembly.relocatable());
+    }
+
+    #[test]
+    fn asm80_includes_files_from_controls() {
+        let directory = env::temp_dir().join(format!("rs8080-asm80-include-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("COMMON.SRC"), "ONE$K\tEQU 1$024\n").unwrap();
+        fs::write(directory.join("MAIN.SRC"), "$TITLE('A test') NOLIST\n$INCLUDE(:F1:COMMON.SRC)\n\tDW ONEK,ONE$K\n").unwrap();
+        let assembly = assemble_file(&directory.join("MAIN.SRC").to_string_lossy(), Dialect::Asm80);
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(assembly.unwrap().image().1, [0x00, 0x04, 0x00, 0x04]);
+    }
+
+    #[test]
+    fn asm80_names_that_are_all_separators_are_refused() {
+        assert_eq!(assemble("test.asm", "\tDB $$\n", Dialect::Asm80).err().unwrap().to_string(), "test.asm:1: invalid name: $$");
+        assert_eq!(assemble("test.asm", "\t$$ EQU 5\n", Dialect::Asm80).err().unwrap().to_string(), "test.asm:1: invalid name: $$");
+        assert_eq!(assemble("test.asm", "\t$$:\tNOP\n", Dialect::Asm80).err().unwrap().to_string(), "test.asm:1: invalid name: $$");
+        assert_eq!(assemble("test.asm", "\tEXTRN $$\n", Dialect::Asm80).err().unwrap().to_string(), "test.asm:1: invalid name: $$");
+    }
+
+    #[test]
+    fn listings_show_addresses_bytes_and_cycles() {
+        let source = "\
+TWICE\tMACRO
+\tINR A
+\tINR A
+\tENDM
+\tORG 100H
+START:\tCNZ START
+\tRNZ
+\tTWICE
+\tDB 'Hello',0
+\tJMP START
+";
+        let listing = assemble("test.asm", source, Dialect::Native).unwrap().listing();
+        let expected = [
+            "                                test.asm",
+            "                              1  TWICE\tMACRO",
+            "                              2  \tINR A",
+            "                              3  \tINR A",
+            "                              4  \tENDM",
+            "0100                          5  \tORG 100H",
+            "0100  c4 00 01     17/11      6  START:\tCNZ START",
+            "0103  c0            11/5      7  \tRNZ",
+            "                              8  \tTWICE",
+            "0104  3c               5      2+ \tINR A",
+            "0105  3c               5      3+ \tINR A",
+            "0106  48 65 6c 6c             9  \tDB 'Hello',0",
+            "010a  6f 00",
+            "010c  c3 00 01        10     10  \tJMP START",
+            "",
+            "Symbols:",
+            "0100  START",
+        ];
+        assert_eq!(listing.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn listed_symbols_are_marked_by_what_they_move_with() {
+        let source = "\tEXTRN PRINT\nSIZE\tEQU 4\n\tCSEG\nSTART:\tCALL PRINT\n\tDSEG\nBUFFER:\tDS SIZE\n";
+        let listing = assemble("test.asm", source, Dialect::Native).unwrap().listing();
+        let symbols: Vec<&str> = listing.lines().skip_while(|&line| line != "Symbols:").skip(1).collect();
+        assert_eq!(symbols, ["0000\" BUFFER", "0000* PRINT", "0004  SIZE", "0000' START"]);
+    }
+}
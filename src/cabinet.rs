@@ -0,0 +1,285 @@
+//! The Space Invaders cabinet's controls and DIP switches, which the program reads through
+//! ports 0, 1 and 2.
+
+use std::fs;
+
+use ports::Device;
+
+/// One player's joystick and fire button.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Controls {
+    pub left: bool,
+    pub right: bool,
+    pub fire: bool,
+}
+
+impl Controls {
+    // The fire, left and right bits, which are in the same place in every port.
+    fn bits(&self) -> u8 {
+        (self.fire as u8) << 4 | (self.left as u8) << 5 | (self.right as u8) << 6
+    }
+}
+
+/// What is being pressed on the cabinet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Inputs {
+    pub coin: bool,
+    pub start1: bool,
+    pub start2: bool,
+    pub tilt: bool,
+    pub player1: Controls,
+    pub player2: Controls,
+}
+
+impl Inputs {
+    /// The inputs with each control named in `names` held down: coin, start1, start2, tilt,
+    /// left1, right1, fire1, left2, right2 and fire2.
+    pub fn named(names: &[&str]) -> Result<Inputs, String> {
+        let mut inputs = Inputs::default();
+        for name in names {
+            let held = match name.to_lowercase().as_str() {
+                "coin" => &mut inputs.coin,
+                "start1" => &mut inputs.start1,
+                "start2" => &mut inputs.start2,
+                "tilt" => &mut inputs.tilt,
+                "left1" => &mut inputs.player1.left,
+                "right1" => &mut inputs.player1.right,
+                "fire1" => &mut inputs.player1.fire,
+                "left2" => &mut inputs.player2.left,
+                "right2" => &mut inputs.player2.right,
+                "fire2" => &mut inputs.player2.fire,
+                _ => return Err(format!("unknown control: {}", name)),
+            };
+            *held = true;
+        }
+        Ok(inputs)
+    }
+}
+
+/// What to hold down from which frame on, in order of frame.
+pub type Script = Vec<(u64, Inputs)>;
+
+/// Reads a script from the file at `path`. Each line is a frame number followed by the
+/// controls held from that frame until the next line's; a frame number on its own lets go
+/// of everything. Blank lines and ones starting with `#` are skipped.
+pub fn read_script(path: &str) -> Result<Script, String> {
+    let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    let mut script = Script::new();
+    for (index, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.first().is_none_or(|word| word.starts_with('#')) {
+            continue
+        }
+        let frame = words[0].parse().map_err(|_| format!("{}:{}: invalid frame: {}", path, index + 1, words[0]))?;
+        let inputs = Inputs::named(&words[1..]).map_err(|error| format!("{}:{}: {}", path, index + 1, error))?;
+        script.push((frame, inputs));
+    }
+    script.sort_by_key(|&(frame, _)| frame);
+    Ok(script)
+}
+
+/// What `script` holds down during `frame`.
+pub fn scripted(script: &Script, frame: u64) -> Inputs {
+    script.iter().rev().find(|&&(from, _)| from <= frame).map_or(Inputs::default(), |&(_, inputs)| inputs)
+}
+
+/// The DIP switches on the board, which set up the game for the arcade operator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dips {
+    /// Ships a game starts with, 3 to 6.
+    pub lives: u8,
+    /// The score that earns an extra ship, 1000 or 1500.
+    pub bonus: u16,
+    /// Whether the attract mode says how many coins a game costs.
+    pub coin_info: bool,
+}
+
+impl Default for Dips {
+    fn default() -> Dips {
+        Dips { lives: 3, bonus: 1500, coin_info: true }
+    }
+}
+
+impl Dips {
+    /// Sets the switch called `key` from `value`, as written on the command line or in a
+    /// DIP file: `lives` 3 to 6, `bonus` 1000 or 1500, `coin-info` on or off.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let (key, value) = (key.trim(), value.trim());
+        match (key, value) {
+            ("lives", _) => match value.parse() {
+                Ok(lives @ 3..=6) => self.lives = lives,
+                _ => return Err(format!("lives must be 3 to 6: {}", value)),
+            },
+            ("bonus", "1000") => self.bonus = 1000,
+            ("bonus", "1500") => self.bonus = 1500,
+            ("bonus", _) => return Err(format!("bonus must be 1000 or 1500: {}", value)),
+            ("coin-info", "on") => self.coin_info = true,
+            ("coin-info", "off") => self.coin_info = false,
+            ("coin-info", _) => return Err(format!("coin-info must be on or off: {}", value)),
+            _ => return Err(format!("unknown DIP switch: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Sets the switches from a `key=value` setting.
+    pub fn assign(&mut self, setting: &str) -> Result<(), String> {
+        let (key, value) = setting.split_once('=').ok_or_else(|| format!("expected KEY=VALUE: {}", setting))?;
+        self.set(key, value)
+    }
+
+    /// Reads `key = value` lines from the file at `path`. Blank lines and ones starting with
+    /// `#` are skipped.
+    pub fn read(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.assign(line).map_err(|error| format!("{}:{}: {}", path, index + 1, error))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The three input ports. Bits that aren't wired to anything read as the board leaves them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cabinet {
+    pub inputs: Inputs,
+    pub dips: Dips,
+}
+
+impl Cabinet {
+    pub fn port0(&self) -> u8 {
+        0x0e | self.inputs.player1.bits()
+    }
+
+    pub fn port1(&self) -> u8 {
+        0x08 | self.inputs.coin as u8 | (self.inputs.start2 as u8) << 1 | (self.inputs.start1 as u8) << 2 | self.inputs.player1.bits()
+    }
+
+    pub fn port2(&self) -> u8 {
+        (self.dips.lives - 3) & 3
+            | (self.inputs.tilt as u8) << 2
+            | ((self.dips.bonus == 1000) as u8) << 3
+            | self.inputs.player2.bits()
+            | (!self.dips.coin_info as u8) << 7
+    }
+}
+
+impl Device for Cabinet {
+    fn name(&self) -> &'static str {
+        "cabinet"
+    }
+
+    fn input(&mut self, port: u8) -> Option<u8> {
+        match port {
+            0 => Some(self.port0()),
+            1 => Some(self.port1()),
+            2 => Some(self.port2()),
+            _ => None,
+        }
+    }
+
+    fn output(&mut self, _port: u8, _value: u8) {}
+
+    // The ports as they read, which hold everything there is to save.
+    fn save(&self) -> Vec<u8> {
+        vec![self.port1(), self.port2()]
+    }
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.len() != 2 {
+            return Err(format!("expected 2 bytes, got {}", bytes.len()))
+        }
+        let bit = |byte: u8, bit: u8| byte >> bit & 1 != 0;
+        let controls = |byte: u8| Controls { fire: bit(byte, 4), left: bit(byte, 5), right: bit(byte, 6) };
+        let (port1, port2) = (bytes[0], bytes[1]);
+        self.inputs = Inputs {
+            coin: bit(port1, 0),
+            start2: bit(port1, 1),
+            start1: bit(port1, 2),
+            tilt: bit(port2, 2),
+            player1: controls(port1),
+            player2: controls(port2),
+        };
+        self.dips = Dips { lives: (port2 & 3) + 3, bonus: if bit(port2, 3) { 1000 } else { 1500 }, coin_info: !bit(port2, 7) };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn dips_are_set_by_name() {
+        let mut dips = Dips::default();
+        dips.set(" lives ", "5").unwrap();
+        dips.assign("bonus=1000").unwrap();
+        dips.assign("coin-info = off").unwrap();
+        assert_eq!(dips, Dips { lives: 5, bonus: 1000, coin_info: false });
+
+        assert_eq!(dips.set("lives", "7"), Err("lives must be 3 to 6: 7".to_string()));
+        assert_eq!(dips.set("bonus", "2000"), Err("bonus must be 1000 or 1500: 2000".to_string()));
+        assert_eq!(dips.set("coin-info", "yes"), Err("coin-info must be on or off: yes".to_string()));
+        assert_eq!(dips.set("credits", "1"), Err("unknown DIP switch: credits".to_string()));
+        assert_eq!(dips.assign("lives"), Err("expected KEY=VALUE: lives".to_string()));
+        assert_eq!(dips, Dips { lives: 5, bonus: 1000, coin_info: false }, "errors change nothing");
+    }
+
+    #[test]
+    fn port2_packs_the_dips_around_player_two() {
+        let mut cabinet = Cabinet::default();
+        assert_eq!(cabinet.port2(), 0x00);
+        for (lives, bits) in [(3, 0), (4, 1), (5, 2), (6, 3)] {
+            cabinet.dips.lives = lives;
+            assert_eq!(cabinet.port2(), bits, "{} lives", lives);
+        }
+        cabinet.dips = Dips { lives: 3, bonus: 1000, coin_info: false };
+        assert_eq!(cabinet.port2(), 0x88);
+
+        cabinet.dips = Dips::default();
+        cabinet.inputs = Inputs::named(&["tilt", "fire2", "left2", "right2", "fire1"]).unwrap();
+        assert_eq!(cabinet.port2(), 0x74);
+        assert_eq!(cabinet.port1(), 0x18);
+    }
+
+    #[test]
+    fn cabinets_survive_saving_and_restoring() {
+        let cabinet = Cabinet {
+            inputs: Inputs::named(&["coin", "start1", "right1", "tilt", "left2"]).unwrap(),
+            dips: Dips { lives: 6, bonus: 1000, coin_info: false },
+        };
+        let mut restored = Cabinet::default();
+        restored.restore(&cabinet.save()).unwrap();
+        assert_eq!(restored, cabinet);
+    }
+
+    #[test]
+    fn scripts_are_sorted_by_frame() {
+        let path = env::temp_dir().join(format!("rs8080-script-{}.txt", std::process::id()));
+        fs::write(&path, "# Coin up, start, then shoot.\n120 start1\n\n60 coin\n200\n180 fire1 left1\n").unwrap();
+        let script = read_script(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let script = script.unwrap();
+        assert_eq!(script.iter().map(|&(frame, _)| frame).collect::<Vec<_>>(), [60, 120, 180, 200]);
+        assert_eq!(scripted(&script, 59), Inputs::default());
+        assert!(scripted(&script, 119).coin);
+        assert!(scripted(&script, 120).start1 && !scripted(&script, 120).coin);
+        assert_eq!(scripted(&script, 199).player1, Controls { left: true, right: false, fire: true });
+        assert_eq!(scripted(&script, 1000), Inputs::default());
+    }
+
+    #[test]
+    fn script_errors_say_where() {
+        let path = env::temp_dir().join(format!("rs8080-bad-script-{}.txt", std::process::id()));
+        let path_text = path.to_str().unwrap().to_string();
+        fs::write(&path, "10 coin\nsoon start1\n").unwrap();
+        assert_eq!(read_script(&path_text), Err(format!("{}:2: invalid frame: soon", path_text)));
+        fs::write(&path, "10 coin jump\n").unwrap();
+        assert_eq!(read_script(&path_text), Err(format!("{}:1: unknown control: jump", path_text)));
+        fs::remove_file(&path).unwrap();
+    }
+}
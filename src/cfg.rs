@@ -0,0 +1,180 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use disassembler::{self, Instruction};
+use opcodes::Flow;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    Taken,
+    FallThrough,
+}
+
+impl Edge {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Edge::Taken => "taken",
+            Edge::FallThrough => "fall-through",
+        }
+    }
+}
+
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<Instruction>,
+    pub edges: Vec<(u16, Edge)>,
+}
+
+/// Splits the routine at `entry` into basic blocks. Calls stay inside a block since they
+/// return to the next instruction; every other transfer of control ends one.
+pub fn blocks(memory: &[u8], range: &Range<usize>, entry: u16) -> Vec<Block> {
+    let analysis = disassembler::analyse_routine(memory, range, entry);
+    let instructions = disassembler::reached(memory, &analysis);
+
+    let mut leaders = BTreeSet::new();
+    leaders.insert(entry);
+    for instruction in &instructions {
+        match instruction.opcode.flow() {
+            Flow::Jump | Flow::ConditionalJump => {
+                leaders.extend(instruction.target());
+                leaders.insert(instruction.next());
+            },
+            Flow::Indirect => {
+                leaders.extend(analysis.indirect.get(&instruction.address).into_iter().flatten());
+                leaders.insert(instruction.next());
+            },
+            Flow::Return | Flow::ConditionalReturn | Flow::Halt => {
+                leaders.insert(instruction.next());
+            },
+            _ => {},
+        }
+    }
+
+    let mut blocks: Vec<Block> = vec![];
+    for instruction in instructions {
+        let contiguous = blocks.last().is_some_and(|block| {
+            block.instructions.last().map(|last| last.next()) == Some(instruction.address)
+        });
+
+        if leaders.contains(&instruction.address) || !contiguous {
+            blocks.push(Block { start: instruction.address, instructions: vec![], edges: vec![] });
+        }
+        blocks.last_mut().unwrap().instructions.push(instruction);
+    }
+
+    for block in &mut blocks {
+        let last = block.instructions.last().unwrap();
+        let next = last.next();
+        let falls_through = analysis.code.contains(&next);
+
+        match last.opcode.flow() {
+            Flow::Jump => block.edges.extend(last.target().map(|target| (target, Edge::Taken))),
+            Flow::ConditionalJump => {
+                block.edges.extend(last.target().map(|target| (target, Edge::Taken)));
+                if falls_through { block.edges.push((next, Edge::FallThrough)) }
+            },
+            Flow::Indirect => if let Some(targets) = analysis.indirect.get(&last.address) {
+                block.edges.extend(targets.iter().map(|&target| (target, Edge::Taken)));
+            },
+            Flow::Return | Flow::Halt => {},
+            _ => if falls_through { block.edges.push((next, Edge::FallThrough)) },
+        }
+    }
+
+    blocks
+}
+
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Renders `blocks` as a Graphviz digraph called `name`, one node per block.
+pub fn dot(name: &str, blocks: &[Block]) -> String {
+    let mut dot = format!("digraph {} {{\n", dot_string(name));
+    dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+    let starts: BTreeSet<u16> = blocks.iter().map(|block| block.start).collect();
+    for block in blocks {
+        // `\l` left-justifies each line of the label.
+        let text: String = block.instructions.iter()
+            .map(|instruction| format!("{:04x}  {}\\l", instruction.address, instruction.assembly()))
+            .collect();
+        dot.push_str(&format!("    b_{:04x} [label=\"{}\"];\n", block.start, text.replace('"', "\\\"")));
+
+        for &(target, edge) in &block.edges {
+            // Jumps out of the routine still get an edge, to a node outside it.
+            if !starts.contains(&target) {
+                dot.push_str(&format!("    b_{:04x} [label=\"{:04x}\", shape=ellipse];\n", target, target));
+            }
+            dot.push_str(&format!("    b_{:04x} -> b_{:04x} [label={}];\n", block.start, target, dot_string(edge.name())));
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A countdown loop, then a call that stays in its block.
+    const PROGRAM: [u8; 11] = [
+        0x06, 0x03,       // START: MVI B,3
+        0x05,             // LOOP:  DCR B
+        0xc2, 0x02, 0x00, //        JNZ LOOP
+        0xcd, 0x0a, 0x00, //        CALL SUB
+        0xc9,             //        RET
+        0xc9,             // SUB:   RET
+    ];
+
+    fn load(program: &[u8]) -> (Vec<u8>, Range<usize>) {
+        let mut memory = vec![0; 0x10000];
+        memory[..program.len()].copy_from_slice(program);
+        (memory, 0..program.len())
+    }
+
+    // Each block's start, instruction addresses and edges.
+    type Shape = (u16, Vec<u16>, Vec<(u16, Edge)>);
+
+    fn shape(blocks: &[Block]) -> Vec<Shape> {
+        blocks.iter()
+            .map(|block| (block.start, block.instructions.iter().map(|instruction| instruction.address).collect(), block.edges.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn jumps_and_their_targets_split_blocks() {
+        let (memory, range) = load(&PROGRAM);
+        assert_eq!(shape(&blocks(&memory, &range, 0)), [
+            (0, vec![0], vec![(2, Edge::FallThrough)]),
+            (2, vec![2, 3], vec![(2, Edge::Taken), (6, Edge::FallThrough)]),
+            (6, vec![6, 9], vec![]),
+        ]);
+    }
+
+    #[test]
+    fn the_routine_called_is_a_graph_of_its_own() {
+        let (memory, range) = load(&PROGRAM);
+        assert_eq!(shape(&blocks(&memory, &range, 10)), [(10, vec![10], vec![])]);
+    }
+
+    #[test]
+    fn dot_has_a_node_per_block_and_labelled_edges() {
+        let (memory, range) = load(&PROGRAM);
+        let dot = dot("count\"down", &blocks(&memory, &range, 0));
+        assert!(dot.starts_with("digraph \"count\\\"down\" {\n"), "{}", dot);
+        assert!(dot.contains("    b_0002 [label=\"0002  DCR B\\l0003  JNZ $0002\\l\"];\n"), "{}", dot);
+        assert!(dot.contains("    b_0000 -> b_0002 [label=\"fall-through\"];\n"), "{}", dot);
+        assert!(dot.contains("    b_0002 -> b_0002 [label=\"taken\"];\n"), "{}", dot);
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn jumps_out_of_the_routine_go_to_an_outside_node() {
+        let (memory, range) = load(&[0xc3, 0x34, 0x12]);
+        let dot = dot("out", &blocks(&memory, &range, 0));
+        assert!(dot.contains("    b_1234 [label=\"1234\", shape=ellipse];\n"), "{}", dot);
+        assert!(dot.contains("    b_0000 -> b_1234 [label=\"taken\"];\n"), "{}", dot);
+    }
+}
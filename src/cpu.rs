@@ -0,0 +1,520 @@
+use std::fmt;
+
+use disassembler;
+use opcodes;
+use ports::Ports;
+
+/// How addresses reach memory, for machines that don't decode every address line or have
+/// ROM at the bottom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryMap {
+    /// Addresses are ANDed with this, so memory repeats every `mask + 1` bytes.
+    pub mask: u16,
+    /// Writes below this are ignored.
+    pub rom_end: u16,
+}
+
+/// 64K of RAM.
+pub const FLAT: MemoryMap = MemoryMap { mask: 0xffff, rom_end: 0 };
+
+pub struct State {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    /// The flag byte, as PUSH PSW would store it.
+    pub flags: u8,
+    pub pc: u16,
+    pub sp: u16,
+    pub cycles: u64,
+    /// Whether interrupts are enabled, as EI and DI set.
+    pub interrupts: bool,
+    pub memory: [u8; 0x10000], // 16k
+    pub map: MemoryMap,
+    /// Why the CPU stopped, once it has.
+    pub stopped: Option<String>,
+}
+
+impl State {
+    /// A CPU with every register clear, about to run `memory` from `pc`.
+    pub fn new(memory: &[u8], pc: u16) -> State {
+        let mut state = State { a: 0, b: 0, c: 0, d: 0, e: 0, h: 0, l: 0, flags: 0x02, pc, sp: 0, cycles: 0, interrupts: false, memory: [0; 0x10000], map: FLAT, stopped: None };
+        state.memory.copy_from_slice(memory);
+        state
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        self.memory[(address & self.map.mask) as usize]
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let address = address & self.map.mask;
+        if address >= self.map.rom_end {
+            self.memory[address as usize] = value;
+        }
+    }
+
+    pub fn de(&self) -> u16 {
+        (self.d as u16) << 8 | self.e as u16
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x} a: {:02x}, b: {:02x}, c: {:02x}, d: {:02x}, e: {:02x}, h: {:02x}, l: {:02x}, flags: {:02x}, sp: {:02x}",
+            self.pc, self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.flags, self.sp)
+    }
+}
+
+fn read_16(state: &State, pc: usize) -> u16 {
+    let pc = pc as u16;
+    ((state.read(pc.wrapping_add(2)) as u16) << 8) | (state.read(pc.wrapping_add(1)) as u16)
+}
+
+fn not_implemented(state: &mut State, pc: u16) {
+    let instruction = disassembler::decode(&state.memory, pc);
+    state.stopped = Some(format!("{:04x}: {} isn't implemented", pc, instruction.assembly()));
+}
+
+pub fn step(mut state: State, ports: &mut Ports) -> State {
+    let pc = state.pc as usize;
+    let op_code = state.read(pc as u16);
+
+    if state.pc == (0x10000 - 1) as u16 {
+        state.stopped = Some("PC out of range".to_string()); return state
+    }
+
+    state.pc += 1;
+    state.cycles += opcodes::opcode(op_code).cycles as u64;
+
+    match op_code {
+        0x00 => { },
+        0x01 => not_implemented(&mut state, pc as u16),
+        0x02 => not_implemented(&mut state, pc as u16),
+        0x03 => not_implemented(&mut state, pc as u16),
+        0x04 => not_implemented(&mut state, pc as u16),
+        0x05 => not_implemented(&mut state, pc as u16),
+        0x06 => {
+            state.b = state.read(pc as u16 + 1);
+            state.pc += 1;
+        },
+        0x07 => not_implemented(&mut state, pc as u16),
+        0x08 => not_implemented(&mut state, pc as u16),
+        0x09 => not_implemented(&mut state, pc as u16),
+        0x0a => not_implemented(&mut state, pc as u16),
+        0x0b => not_implemented(&mut state, pc as u16),
+        0x0c => not_implemented(&mut state, pc as u16),
+        0x0d => not_implemented(&mut state, pc as u16),
+        0x0e => not_implemented(&mut state, pc as u16),
+        0x0f => not_implemented(&mut state, pc as u16),
+        0x10 => not_implemented(&mut state, pc as u16),
+        0x11 => not_implemented(&mut state, pc as u16),
+        0x12 => not_implemented(&mut state, pc as u16),
+        0x13 => not_implemented(&mut state, pc as u16),
+        0x14 => not_implemented(&mut state, pc as u16),
+        0x15 => not_implemented(&mut state, pc as u16),
+        0x16 => not_implemented(&mut state, pc as u16),
+        0x17 => not_implemented(&mut state, pc as u16),
+        0x18 => not_implemented(&mut state, pc as u16),
+        0x19 => not_implemented(&mut state, pc as u16),
+        0x1a => not_implemented(&mut state, pc as u16),
+        0x1b => not_implemented(&mut state, pc as u16),
+        0x1c => not_implemented(&mut state, pc as u16),
+        0x1d => not_implemented(&mut state, pc as u16),
+        0x1e => not_implemented(&mut state, pc as u16),
+        0x1f => not_implemented(&mut state, pc as u16),
+        0x20 => not_implemented(&mut state, pc as u16),
+        0x21 => not_implemented(&mut state, pc as u16),
+        0x22 => not_implemented(&mut state, pc as u16),
+        0x23 => not_implemented(&mut state, pc as u16),
+        0x24 => not_implemented(&mut state, pc as u16),
+        0x25 => not_implemented(&mut state, pc as u16),
+        0x26 => not_implemented(&mut state, pc as u16),
+        0x27 => not_implemented(&mut state, pc as u16),
+        0x28 => not_implemented(&mut state, pc as u16),
+        0x29 => not_implemented(&mut state, pc as u16),
+        0x2a => not_implemented(&mut state, pc as u16),
+        0x2b => not_implemented(&mut state, pc as u16),
+        0x2c => not_implemented(&mut state, pc as u16),
+        0x2d => not_implemented(&mut state, pc as u16),
+        0x2e => not_implemented(&mut state, pc as u16),
+        0x2f => not_implemented(&mut state, pc as u16),
+        0x30 => not_implemented(&mut state, pc as u16),
+        0x31 => {
+            state.sp = read_16(&state, pc);
+            state.pc += 2;
+        },
+        0x32 => not_implemented(&mut state, pc as u16),
+        0x33 => not_implemented(&mut state, pc as u16),
+        0x34 => not_implemented(&mut state, pc as u16),
+        0x35 => not_implemented(&mut state, pc as u16),
+        0x36 => not_implemented(&mut state, pc as u16),
+        0x37 => not_implemented(&mut state, pc as u16),
+        0x38 => not_implemented(&mut state, pc as u16),
+        0x39 => not_implemented(&mut state, pc as u16),
+        0x3a => not_implemented(&mut state, pc as u16),
+        0x3b => not_implemented(&mut state, pc as u16),
+        0x3c => not_implemented(&mut state, pc as u16),
+        0x3d => not_implemented(&mut state, pc as u16),
+        0x3e => not_implemented(&mut state, pc as u16),
+        0x3f => not_implemented(&mut state, pc as u16),
+        0x40 => not_implemented(&mut state, pc as u16),
+        0x41 => not_implemented(&mut state, pc as u16),
+        0x42 => not_implemented(&mut state, pc as u16),
+        0x43 => not_implemented(&mut state, pc as u16),
+        0x44 => not_implemented(&mut state, pc as u16),
+        0x45 => not_implemented(&mut state, pc as u16),
+        0x46 => not_implemented(&mut state, pc as u16),
+        0x47 => not_implemented(&mut state, pc as u16),
+        0x48 => not_implemented(&mut state, pc as u16),
+        0x49 => not_implemented(&mut state, pc as u16),
+        0x4a => not_implemented(&mut state, pc as u16),
+        0x4b => not_implemented(&mut state, pc as u16),
+        0x4c => not_implemented(&mut state, pc as u16),
+        0x4d => not_implemented(&mut state, pc as u16),
+        0x4e => not_implemented(&mut state, pc as u16),
+        0x4f => not_implemented(&mut state, pc as u16),
+        0x50 => not_implemented(&mut state, pc as u16),
+        0x51 => not_implemented(&mut state, pc as u16),
+        0x52 => not_implemented(&mut state, pc as u16),
+        0x53 => not_implemented(&mut state, pc as u16),
+        0x54 => not_implemented(&mut state, pc as u16),
+        0x55 => not_implemented(&mut state, pc as u16),
+        0x56 => not_implemented(&mut state, pc as u16),
+        0x57 => not_implemented(&mut state, pc as u16),
+        0x58 => not_implemented(&mut state, pc as u16),
+        0x59 => not_implemented(&mut state, pc as u16),
+        0x5a => not_implemented(&mut state, pc as u16),
+        0x5b => not_implemented(&mut state, pc as u16),
+        0x5c => not_implemented(&mut state, pc as u16),
+        0x5d => not_implemented(&mut state, pc as u16),
+        0x5e => not_implemented(&mut state, pc as u16),
+        0x5f => not_implemented(&mut state, pc as u16),
+        0x60 => not_implemented(&mut state, pc as u16),
+        0x61 => not_implemented(&mut state, pc as u16),
+        0x62 => not_implemented(&mut state, pc as u16),
+        0x63 => not_implemented(&mut state, pc as u16),
+        0x64 => not_implemented(&mut state, pc as u16),
+        0x65 => not_implemented(&mut state, pc as u16),
+        0x66 => not_implemented(&mut state, pc as u16),
+        0x67 => not_implemented(&mut state, pc as u16),
+        0x68 => not_implemented(&mut state, pc as u16),
+        0x69 => not_implemented(&mut state, pc as u16),
+        0x6a => not_implemented(&mut state, pc as u16),
+        0x6b => not_implemented(&mut state, pc as u16),
+        0x6c => not_implemented(&mut state, pc as u16),
+        0x6d => not_implemented(&mut state, pc as u16),
+        0x6e => not_implemented(&mut state, pc as u16),
+        0x6f => not_implemented(&mut state, pc as u16),
+        0x70 => not_implemented(&mut state, pc as u16),
+        0x71 => not_implemented(&mut state, pc as u16),
+        0x72 => not_implemented(&mut state, pc as u16),
+        0x73 => not_implemented(&mut state, pc as u16),
+        0x74 => not_implemented(&mut state, pc as u16),
+        0x75 => not_implemented(&mut state, pc as u16),
+        0x76 => not_implemented(&mut state, pc as u16),
+        0x77 => not_implemented(&mut state, pc as u16),
+        0x78 => not_implemented(&mut state, pc as u16),
+        0x79 => not_implemented(&mut state, pc as u16),
+        0x7a => not_implemented(&mut state, pc as u16),
+        0x7b => not_implemented(&mut state, pc as u16),
+        0x7c => not_implemented(&mut state, pc as u16),
+        0x7d => not_implemented(&mut state, pc as u16),
+        0x7e => not_implemented(&mut state, pc as u16),
+        0x7f => not_implemented(&mut state, pc as u16),
+        0x80 => not_implemented(&mut state, pc as u16),
+        0x81 => not_implemented(&mut state, pc as u16),
+        0x82 => not_implemented(&mut state, pc as u16),
+        0x83 => not_implemented(&mut state, pc as u16),
+        0x84 => not_implemented(&mut state, pc as u16),
+        0x85 => not_implemented(&mut state, pc as u16),
+        0x86 => not_implemented(&mut state, pc as u16),
+        0x87 => not_implemented(&mut state, pc as u16),
+        0x88 => not_implemented(&mut state, pc as u16),
+        0x89 => not_implemented(&mut state, pc as u16),
+        0x8a => not_implemented(&mut state, pc as u16),
+        0x8b => not_implemented(&mut state, pc as u16),
+        0x8c => not_implemented(&mut state, pc as u16),
+        0x8d => not_implemented(&mut state, pc as u16),
+        0x8e => not_implemented(&mut state, pc as u16),
+        0x8f => not_implemented(&mut state, pc as u16),
+        0x90 => not_implemented(&mut state, pc as u16),
+        0x91 => not_implemented(&mut state, pc as u16),
+        0x92 => not_implemented(&mut state, pc as u16),
+        0x93 => not_implemented(&mut state, pc as u16),
+        0x94 => not_implemented(&mut state, pc as u16),
+        0x95 => not_implemented(&mut state, pc as u16),
+        0x96 => not_implemented(&mut state, pc as u16),
+        0x97 => not_implemented(&mut state, pc as u16),
+        0x98 => not_implemented(&mut state, pc as u16),
+        0x99 => not_implemented(&mut state, pc as u16),
+        0x9a => not_implemented(&mut state, pc as u16),
+        0x9b => not_implemented(&mut state, pc as u16),
+        0x9c => not_implemented(&mut state, pc as u16),
+        0x9d => not_implemented(&mut state, pc as u16),
+        0x9e => not_implemented(&mut state, pc as u16),
+        0x9f => not_implemented(&mut state, pc as u16),
+        0xa0 => not_implemented(&mut state, pc as u16),
+        0xa1 => not_implemented(&mut state, pc as u16),
+        0xa2 => not_implemented(&mut state, pc as u16),
+        0xa3 => not_implemented(&mut state, pc as u16),
+        0xa4 => not_implemented(&mut state, pc as u16),
+        0xa5 => not_implemented(&mut state, pc as u16),
+        0xa6 => not_implemented(&mut state, pc as u16),
+        0xa7 => not_implemented(&mut state, pc as u16),
+        0xa8 => not_implemented(&mut state, pc as u16),
+        0xa9 => not_implemented(&mut state, pc as u16),
+        0xaa => not_implemented(&mut state, pc as u16),
+        0xab => not_implemented(&mut state, pc as u16),
+        0xac => not_implemented(&mut state, pc as u16),
+        0xad => not_implemented(&mut state, pc as u16),
+        0xae => not_implemented(&mut state, pc as u16),
+        0xaf => not_implemented(&mut state, pc as u16),
+        0xb0 => not_implemented(&mut state, pc as u16),
+        0xb1 => not_implemented(&mut state, pc as u16),
+        0xb2 => not_implemented(&mut state, pc as u16),
+        0xb3 => not_implemented(&mut state, pc as u16),
+        0xb4 => not_implemented(&mut state, pc as u16),
+        0xb5 => not_implemented(&mut state, pc as u16),
+        0xb6 => not_implemented(&mut state, pc as u16),
+        0xb7 => not_implemented(&mut state, pc as u16),
+        0xb8 => not_implemented(&mut state, pc as u16),
+        0xb9 => not_implemented(&mut state, pc as u16),
+        0xba => not_implemented(&mut state, pc as u16),
+        0xbb => not_implemented(&mut state, pc as u16),
+        0xbc => not_implemented(&mut state, pc as u16),
+        0xbd => not_implemented(&mut state, pc as u16),
+        0xbe => not_implemented(&mut state, pc as u16),
+        0xbf => not_implemented(&mut state, pc as u16),
+        0xc0 => not_implemented(&mut state, pc as u16),
+        0xc1 => not_implemented(&mut state, pc as u16),
+        0xc2 => not_implemented(&mut state, pc as u16),
+        0xc3 => {
+            state.pc = read_16(&state, pc);
+        },
+        0xc4 => not_implemented(&mut state, pc as u16),
+        0xc5 => not_implemented(&mut state, pc as u16),
+        0xc6 => not_implemented(&mut state, pc as u16),
+        0xc7 => not_implemented(&mut state, pc as u16),
+        0xc8 => not_implemented(&mut state, pc as u16),
+        0xc9 => not_implemented(&mut state, pc as u16),
+        0xca => not_implemented(&mut state, pc as u16),
+        0xcb => not_implemented(&mut state, pc as u16),
+        0xcc => not_implemented(&mut state, pc as u16),
+        0xcd => not_implemented(&mut state, pc as u16),
+        0xce => not_implemented(&mut state, pc as u16),
+        0xcf => not_implemented(&mut state, pc as u16),
+        0xd0 => not_implemented(&mut state, pc as u16),
+        0xd1 => not_implemented(&mut state, pc as u16),
+        0xd2 => not_implemented(&mut state, pc as u16),
+        0xd3 => {
+            let port = state.read(pc as u16 + 1);
+            ports.output(port, state.a);
+            state.pc += 1;
+        },
+        0xd4 => not_implemented(&mut state, pc as u16),
+        0xd5 => not_implemented(&mut state, pc as u16),
+        0xd6 => not_implemented(&mut state, pc as u16),
+        0xd7 => not_implemented(&mut state, pc as u16),
+        0xd8 => not_implemented(&mut state, pc as u16),
+        0xd9 => not_implemented(&mut state, pc as u16),
+        0xda => not_implemented(&mut state, pc as u16),
+        0xdb => {
+            let port = state.read(pc as u16 + 1);
+            state.a = ports.input(port);
+            state.pc += 1;
+        },
+        0xdc => not_implemented(&mut state, pc as u16),
+        0xdd => not_implemented(&mut state, pc as u16),
+        0xde => not_implemented(&mut state, pc as u16),
+        0xdf => not_implemented(&mut state, pc as u16),
+        0xe0 => not_implemented(&mut state, pc as u16),
+        0xe1 => not_implemented(&mut state, pc as u16),
+        0xe2 => not_implemented(&mut state, pc as u16),
+        0xe3 => not_implemented(&mut state, pc as u16),
+        0xe4 => not_implemented(&mut state, pc as u16),
+        0xe5 => not_implemented(&mut state, pc as u16),
+        0xe6 => not_implemented(&mut state, pc as u16),
+        0xe7 => not_implemented(&mut state, pc as u16),
+        0xe8 => not_implemented(&mut state, pc as u16),
+        0xe9 => not_implemented(&mut state, pc as u16),
+        0xea => not_implemented(&mut state, pc as u16),
+        0xeb => not_implemented(&mut state, pc as u16),
+        0xec => not_implemented(&mut state, pc as u16),
+        0xed => not_implemented(&mut state, pc as u16),
+        0xee => not_implemented(&mut state, pc as u16),
+        0xef => not_implemented(&mut state, pc as u16),
+        0xf0 => not_implemented(&mut state, pc as u16),
+        0xf1 => not_implemented(&mut state, pc as u16),
+        0xf2 => not_implemented(&mut state, pc as u16),
+        0xf3 => state.interrupts = false,
+        0xf4 => not_implemented(&mut state, pc as u16),
+        0xf5 => not_implemented(&mut state, pc as u16),
+        0xf6 => not_implemented(&mut state, pc as u16),
+        0xf7 => not_implemented(&mut state, pc as u16),
+        0xf8 => not_implemented(&mut state, pc as u16),
+        0xf9 => not_implemented(&mut state, pc as u16),
+        0xfa => not_implemented(&mut state, pc as u16),
+        // The 8080 waits an instruction before taking an interrupt after EI; here it doesn't.
+        0xfb => state.interrupts = true,
+        0xfc => not_implemented(&mut state, pc as u16),
+        0xfd => not_implemented(&mut state, pc as u16),
+        0xfe => not_implemented(&mut state, pc as u16),
+        0xff => not_implemented(&mut state, pc as u16),
+    }
+
+    state
+}
+
+/// What the RESET line does: the program starts again from 0 with interrupts off. The
+/// registers and memory are left as they were.
+pub fn reset(state: &mut State) {
+    state.pc = 0;
+    state.interrupts = false;
+}
+
+/// Answers an interrupt by running RST `restart`, which is what the boards we emulate put on
+/// the bus, and turns interrupts off until the program turns them back on.
+pub fn interrupt(state: &mut State, restart: u8) {
+    state.interrupts = false;
+    let (pc, sp) = (state.pc, state.sp);
+    state.write(sp.wrapping_sub(1), (pc >> 8) as u8);
+    state.write(sp.wrapping_sub(2), pc as u8);
+    state.sp = sp.wrapping_sub(2);
+    state.pc = (restart as u16 & 7) * 8;
+    state.cycles += opcodes::opcode(0xc7 | (restart & 7) << 3).cycles as u64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ports::Device;
+
+    // Answers IN with the port number plus one and remembers every OUT.
+    #[derive(Default)]
+    struct Recorder {
+        outputs: Vec<(u8, u8)>,
+    }
+
+    impl Device for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        fn input(&mut self, port: u8) -> Option<u8> {
+            Some(port.wrapping_add(1))
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.outputs.push((port, value));
+        }
+
+        fn save(&self) -> Vec<u8> {
+            vec![]
+        }
+
+        fn restore(&mut self, _bytes: &[u8]) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    fn boot(program: &[u8]) -> (State, Ports) {
+        let mut memory = [0; 0x10000];
+        memory[..program.len()].copy_from_slice(program);
+        let ports = Ports { devices: vec![Box::new(Recorder::default())] };
+        (State::new(&memory, 0), ports)
+    }
+
+    fn steps(mut state: State, ports: &mut Ports, count: usize) -> State {
+        for _ in 0..count {
+            state = step(state, ports);
+        }
+        state
+    }
+
+    #[test]
+    fn loads_jumps_and_their_cycles() {
+        let (state, mut ports) = boot(&[0x00, 0x06, 0x42, 0x31, 0x34, 0x12, 0xc3, 0x00, 0x01]);
+        let state = steps(state, &mut ports, 4);
+        assert_eq!((state.b, state.sp, state.pc), (0x42, 0x1234, 0x0100));
+        assert_eq!(state.cycles, 4 + 7 + 10 + 10);
+        assert_eq!(state.stopped, None);
+    }
+
+    #[test]
+    fn in_and_out_go_through_the_ports() {
+        let (state, mut ports) = boot(&[0xdb, 0x07, 0xd3, 0x03]);
+        let state = steps(state, &mut ports, 2);
+        assert_eq!(state.a, 0x08);
+        assert_eq!(ports.find::<Recorder>().unwrap().outputs, [(0x03, 0x08)]);
+        assert_eq!(state.cycles, 20);
+    }
+
+    #[test]
+    fn ports_nothing_answers_read_high() {
+        let mut memory = [0; 0x10000];
+        memory[..2].copy_from_slice(&[0xdb, 0x01]);
+        let state = step(State::new(&memory, 0), &mut Ports::default());
+        assert_eq!(state.a, 0xff);
+    }
+
+    #[test]
+    fn ei_and_di_switch_interrupts() {
+        let (state, mut ports) = boot(&[0xfb, 0xf3]);
+        let state = step(state, &mut ports);
+        assert!(state.interrupts);
+        let state = step(state, &mut ports);
+        assert!(!state.interrupts);
+    }
+
+    #[test]
+    fn unimplemented_instructions_stop_the_cpu() {
+        let (state, mut ports) = boot(&[0x00, 0x3e, 0x12]);
+        let state = steps(state, &mut ports, 2);
+        assert_eq!(state.stopped, Some("0001: MVI A,$12 isn't implemented".to_string()));
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory_stops_the_cpu() {
+        let (mut state, mut ports) = boot(&[]);
+        state.pc = 0xffff;
+        let state = step(state, &mut ports);
+        assert_eq!(state.stopped, Some("PC out of range".to_string()));
+    }
+
+    #[test]
+    fn the_memory_map_mirrors_and_protects() {
+        let (mut state, _) = boot(&[0xaa]);
+        state.map = MemoryMap { mask: 0x3fff, rom_end: 0x2000 };
+        assert_eq!(state.read(0x4000), 0xaa, "addresses wrap at the mask");
+        state.write(0x4000, 0x55);
+        assert_eq!(state.read(0), 0xaa, "ROM can't be written");
+        state.write(0x6000, 0x55);
+        assert_eq!(state.memory[0x2000], 0x55, "RAM is written through its mirror");
+    }
+
+    #[test]
+    fn interrupts_push_the_pc_and_restart() {
+        let (mut state, _) = boot(&[]);
+        state.pc = 0x1234;
+        state.sp = 0x2400;
+        state.interrupts = true;
+        interrupt(&mut state, 2);
+        assert_eq!((state.pc, state.sp, state.interrupts), (0x0010, 0x23fe, false));
+        assert_eq!(state.memory[0x23fe..0x2400], [0x34, 0x12]);
+        assert_eq!(state.cycles, 11);
+    }
+
+    #[test]
+    fn reset_starts_over_keeping_the_registers() {
+        let (mut state, _) = boot(&[]);
+        state.pc = 0x1234;
+        state.b = 7;
+        state.interrupts = true;
+        reset(&mut state);
+        assert_eq!((state.pc, state.b, state.interrupts), (0, 7, false));
+    }
+}
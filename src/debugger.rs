@@ -0,0 +1,328 @@
+//! A monitor for stepping through a program, reading one command a line.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use cabinet::{Cabinet, Inputs};
+use cpu::State;
+use disassembler;
+use machine::{Machine, Stop};
+use parse_address;
+use ports::Ports;
+use savestate;
+use video;
+
+const HELP: &str = "\
+s [COUNT]        step COUNT instructions, 1 unless given
+c                continue to a breakpoint, the end or the cycle limit
+b [ADDR]         set or clear a breakpoint at ADDR, or list them
+r                show the registers
+m ADDR [LENGTH]  show LENGTH bytes of memory from ADDR, 16 unless given
+w ADDR BYTE...   write bytes from ADDR, as the CPU would, so ROM stays as it is
+d [ADDR] [COUNT] disassemble COUNT instructions from ADDR, 8 from PC unless given
+i [CONTROL...]   hold down the cabinet's controls, letting go of the rest
+screenshot FILE  write the screen to FILE, as PNG if it ends in .png and PPM otherwise
+save FILE        save the machine's state to FILE
+load FILE        go back to the state saved in FILE
+q                quit
+";
+
+fn describe(stop: &Stop) -> String {
+    match *stop {
+        Stop::Exit => "program exited".to_string(),
+        Stop::Fault(ref message) => format!("stopped: {}", message),
+        Stop::CycleLimit => "cycle limit reached".to_string(),
+        Stop::Breakpoint(address) => format!("breakpoint at {:04x}", address),
+        Stop::Watchdog => "the watchdog reset the CPU".to_string(),
+    }
+}
+
+fn show(state: &State, output: &mut dyn Write) -> io::Result<()> {
+    writeln!(output, "{:?} cycles: {}", state, state.cycles)?;
+    writeln!(output, "{}", disassembler::decode(&state.memory, state.pc).text())
+}
+
+fn number(text: Option<&&str>, default: usize) -> io::Result<usize> {
+    text.map_or(Ok(default), |text| text.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid count: {}", text))))
+}
+
+/// Runs `state` on `machine` under the commands read from `input`, until they run out or
+/// say to quit. Screenshots show the screen as `screen` says.
+pub fn debug(machine: Machine, mut state: State, mut ports: Ports, limit: Option<u64>, screen: video::Options, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<()> {
+    let mut breakpoints = BTreeSet::new();
+    let mut stop = None;
+    show(&state, output)?;
+
+    let mut line = String::new();
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(())
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let result = match words.first().cloned() {
+            None => Ok(()),
+            Some("s") | Some("c") if matches!(stop, Some(Stop::Exit) | Some(Stop::Fault(_))) => {
+                writeln!(output, "{}", describe(stop.as_ref().unwrap()))
+            },
+            Some("s") => match number(words.get(1), 1) {
+                Ok(count) => {
+                    for _ in 0..count {
+                        let (next, stopped) = machine.tick(state, &mut ports, output);
+                        state = next;
+                        if let Some(stopped) = stopped {
+                            writeln!(output, "{}", describe(&stopped))?;
+                            stop = Some(stopped);
+                            break
+                        }
+                    }
+                    show(&state, output)
+                },
+                Err(error) => Err(error),
+            },
+            Some("c") => {
+                let (next, stopped) = machine.run(state, &mut ports, limit, &breakpoints, output);
+                state = next;
+                writeln!(output, "{}", describe(&stopped))?;
+                stop = Some(stopped);
+                show(&state, output)
+            },
+            Some("b") => match words.get(1) {
+                Some(address) => parse_address(address).and_then(|address| {
+                    if breakpoints.remove(&address) {
+                        writeln!(output, "cleared breakpoint at {:04x}", address)
+                    } else {
+                        breakpoints.insert(address);
+                        writeln!(output, "breakpoint at {:04x}", address)
+                    }
+                }),
+                None => breakpoints.iter().try_for_each(|address| writeln!(output, "{:04x}", address)),
+            },
+            Some("r") => show(&state, output),
+            Some("m") => match words.get(1) {
+                Some(address) => parse_address(address).and_then(|address| {
+                    let length = number(words.get(2), 16)?;
+                    let end = (address as usize + length).min(state.memory.len());
+                    for row in (address as usize..end).step_by(16) {
+                        let bytes: Vec<String> = (row..end.min(row + 16)).map(|at| format!("{:02x}", state.read(at as u16))).collect();
+                        writeln!(output, "{:04x}  {}", row, bytes.join(" "))?;
+                    }
+                    Ok(())
+                }),
+                None => writeln!(output, "m needs an address"),
+            },
+            Some("w") if words.len() < 3 => writeln!(output, "w needs an address and bytes"),
+            Some("w") => parse_address(words[1]).and_then(|address| {
+                for (offset, byte) in words[2..].iter().enumerate() {
+                    let byte = u8::from_str_radix(byte, 16).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid byte: {}", byte)))?;
+                    state.write(address.wrapping_add(offset as u16), byte);
+                }
+                Ok(())
+            }),
+            Some("d") => {
+                let address = words.get(1).map_or(Ok(state.pc), |address| parse_address(address));
+                address.and_then(|mut address| {
+                    for _ in 0..number(words.get(2), 8)? {
+                        let instruction = disassembler::decode(&state.memory, address);
+                        writeln!(output, "{}", instruction.text())?;
+                        address = instruction.next();
+                    }
+                    Ok(())
+                })
+            },
+            Some("i") => match (ports.find::<Cabinet>(), Inputs::named(&words[1..])) {
+                (Some(cabinet), Ok(inputs)) => {
+                    cabinet.inputs = inputs;
+                    Ok(())
+                },
+                (None, _) => writeln!(output, "the {} machine has no controls", machine.name()),
+                (_, Err(error)) => writeln!(output, "{}", error),
+            },
+            Some("screenshot") if words.len() != 2 => writeln!(output, "screenshot needs a file"),
+            Some("screenshot") => match machine.screen(&state, &mut ports, screen) {
+                Some(frame) => match frame.write(words[1]) {
+                    Ok(()) => writeln!(output, "wrote {}", words[1]),
+                    Err(error) => writeln!(output, "{}", error),
+                },
+                None => writeln!(output, "the {} machine has no screen", machine.name()),
+            },
+            Some("save") | Some("load") if words.len() != 2 => writeln!(output, "{} needs a file", words[0]),
+            Some("save") => match savestate::write_file(words[1], machine, &state, &ports.save()) {
+                Ok(()) => writeln!(output, "saved to {}", words[1]),
+                Err(error) => writeln!(output, "{}", error),
+            },
+            Some("load") => match savestate::read_file(words[1]) {
+                Ok(ref snapshot) if snapshot.machine != machine => writeln!(output, "{} is for the {} machine", words[1], snapshot.machine.name()),
+                Ok(snapshot) => {
+                    let mut restored = machine.ports();
+                    match restored.restore(&snapshot.devices) {
+                        Ok(()) => {
+                            state = snapshot.state;
+                            ports = restored;
+                            stop = None;
+                            show(&state, output)
+                        },
+                        Err(error) => writeln!(output, "{}: {}", words[1], error),
+                    }
+                },
+                Err(error) => writeln!(output, "{}", error),
+            },
+            Some("q") => return Ok(()),
+            Some("h") | Some("?") => write!(output, "{}", HELP),
+            Some(command) => writeln!(output, "unknown command: {} (h for help)", command),
+        };
+
+        // A mistyped command shouldn't end the session.
+        if let Err(error) = result {
+            if error.kind() != io::ErrorKind::InvalidInput {
+                return Err(error)
+            }
+            writeln!(output, "{}", error)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    // The debugger's output from running `commands` on `state`.
+    fn session(machine: Machine, state: State, commands: &str) -> String {
+        let mut output = vec![];
+        debug(machine, state, machine.ports(), None, video::Options::default(), &mut commands.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    // MVI B,1 / NOP / NOP / JMP 0, which ends a CP/M program.
+    fn cpm() -> State {
+        let mut memory = vec![0; 0x10000];
+        memory[0x100..0x106].copy_from_slice(&[0x06, 0x01, 0x00, 0x00, 0xc3, 0x00]);
+        Machine::Cpm.boot(&memory, None)
+    }
+
+    #[test]
+    fn stepping_shows_each_instruction() {
+        let output = session(Machine::Cpm, cpm(), "s\ns 2\nr\n");
+        let shown: Vec<&str> = output.lines().filter(|line| line.contains('\t')).collect();
+        assert_eq!(shown, ["0100 06 01\tMVI B\t$01", "0102 00 \tNOP", "0104 c3 00 00\tJMP\t$0000", "0104 c3 00 00\tJMP\t$0000"]);
+        assert!(output.contains("b: 01,"), "{}", output);
+        assert!(output.contains("cycles: 15\n"), "{}", output);
+    }
+
+    #[test]
+    fn continuing_stops_at_breakpoints_then_the_end() {
+        let output = session(Machine::Cpm, cpm(), "b 104\nc\nc\ns\n");
+        assert_eq!(output.matches("breakpoint at 0104").count(), 2, "{}", output);
+        assert!(output.contains("> program exited\n0000 "), "{}", output);
+        assert!(output.ends_with("> program exited\n> "), "stepping after the end: {}", output);
+    }
+
+    #[test]
+    fn breakpoints_toggle() {
+        let output = session(Machine::Cpm, cpm(), "b 103\nb 104\nb 103\nb\n");
+        assert!(output.ends_with("> breakpoint at 0103\n> breakpoint at 0104\n> cleared breakpoint at 0103\n> 0104\n> "), "{}", output);
+    }
+
+    #[test]
+    fn memory_is_written_and_shown() {
+        let output = session(Machine::Cpm, cpm(), "w 200 12 34\nm 200 2\nm 100\nw 200\n");
+        assert!(output.contains("> > 0200  12 34\n"), "{}", output);
+        assert!(output.contains("> 0100  06 01 00 00 c3 00 00 00 00 00 00 00 00 00 00 00\n"), "{}", output);
+        assert!(output.ends_with("> w needs an address and bytes\n> "), "{}", output);
+    }
+
+    #[test]
+    fn mistakes_are_reported_and_the_session_carries_on() {
+        let output = session(Machine::Cpm, cpm(), "x\ns x\nb zz\nw 200 1ff\nq\nr\n");
+        assert!(output.contains("> unknown command: x (h for help)\n"), "{}", output);
+        assert!(output.contains("> invalid count: x\n"), "{}", output);
+        assert!(output.contains("> invalid address: zz\n"), "{}", output);
+        assert!(output.contains("> invalid byte: 1ff\n"), "{}", output);
+        assert!(output.ends_with("> "), "nothing after q: {}", output);
+    }
+
+    #[test]
+    fn saved_states_load_back() {
+        let path = env::temp_dir().join(format!("rs8080-debug-save-{}.state", process::id()));
+        let path = path.to_string_lossy();
+        let output = session(Machine::Cpm, cpm(), &format!("s\nw 200 aa\nsave {0}\ns\nw 200 bb\nload {0}\nm 200 1\n", path));
+        fs::remove_file(&*path).unwrap();
+        assert!(output.contains(&format!("> saved to {}\n", path)), "{}", output);
+        assert!(output.contains("cycles: 7\n0102 00 \tNOP\n> 0200  aa\n"), "{}", output);
+    }
+
+    #[test]
+    fn states_from_another_machine_are_refused() {
+        let path = env::temp_dir().join(format!("rs8080-debug-invaders-{}.state", process::id()));
+        let path = path.to_string_lossy();
+        let invaders = Machine::Invaders.boot(&[0; 0x10000], None);
+        savestate::write_file(&path, Machine::Invaders, &invaders, &Machine::Invaders.ports().save()).unwrap();
+        let output = session(Machine::Cpm, cpm(), &format!("load {}\nload\nr\n", path));
+        fs::remove_file(&*path).unwrap();
+        assert!(output.contains(&format!("> {} is for the invaders machine\n", path)), "{}", output);
+        assert!(output.contains("> load needs a file\n"), "{}", output);
+        assert!(output.ends_with("cycles: 0\n0100 06 01\tMVI B\t$01\n> "), "the state is as it was: {}", output);
+    }
+
+    #[test]
+    fn memory_commands_go_through_the_memory_map() {
+        let mut memory = vec![0; 0x10000];
+        memory[0] = 0xc3;
+        let state = Machine::Invaders.boot(&memory, None);
+        let output = session(Machine::Invaders, state, "w 0 ff\nw 6000 5a\nm 0 1\nm 2000 1\nm e000 1\n");
+        assert!(output.ends_with("> > > 0000  c3\n> 2000  5a\n> e000  5a\n> "), "ROM stays as it is and RAM is mirrored: {}", output);
+    }
+
+    #[test]
+    fn controls_are_held_until_changed() {
+        // IN 1 / IN 1
+        let mut memory = vec![0; 0x10000];
+        memory[..4].copy_from_slice(&[0xdb, 0x01, 0xdb, 0x01]);
+        let state = Machine::Invaders.boot(&memory, None);
+        let output = session(Machine::Invaders, state, "i coin start1\ns\ni\ns\ni jump\n");
+        assert!(output.contains("0002 a: 0d,"), "{}", output);
+        assert!(output.contains("0004 a: 08,"), "{}", output);
+        assert!(output.ends_with("> unknown control: jump\n> "), "{}", output);
+
+        let output = session(Machine::Cpm, cpm(), "i coin\n");
+        assert!(output.ends_with("> the cpm machine has no controls\n> "), "{}", output);
+    }
+
+    #[test]
+    fn screenshots_are_written_for_machines_with_a_screen() {
+        let path = env::temp_dir().join(format!("rs8080-debug-screenshot-{}.ppm", process::id()));
+        let path = path.to_string_lossy();
+        let state = Machine::Invaders.boot(&[0; 0x10000], None);
+        let output = session(Machine::Invaders, state, &format!("screenshot {}\nscreenshot\n", path));
+        let ppm = fs::read(&*path);
+        fs::remove_file(&*path).unwrap();
+        assert!(output.contains(&format!("> wrote {}\n", path)), "{}", output);
+        assert!(output.ends_with("> screenshot needs a file\n> "), "{}", output);
+        assert!(ppm.unwrap().starts_with(b"P6\n224 256\n255\n"));
+
+        let output = session(Machine::Cpm, cpm(), "screenshot screen.ppm\n");
+        assert!(output.ends_with("> the cpm machine has no screen\n> "), "{}", output);
+    }
+
+    #[test]
+    fn screenshots_are_shown_as_the_cabinet_would() {
+        let path = env::temp_dir().join(format!("rs8080-debug-overlay-{}.ppm", process::id()));
+        let path = path.to_string_lossy();
+        // A pixel at the left end of row 32, under the red strip.
+        let mut memory = vec![0; 0x10000];
+        memory[0x2400 + 27] = 0x80;
+        let state = Machine::Invaders.boot(&memory, None);
+        let screen = video::Options { overlay: true, cocktail: false };
+        let mut output = vec![];
+        let commands = format!("screenshot {}\n", path);
+        debug(Machine::Invaders, state, Machine::Invaders.ports(), None, screen, &mut commands.as_bytes(), &mut output).unwrap();
+        let ppm = fs::read(&*path).unwrap();
+        fs::remove_file(&*path).unwrap();
+        let at = 15 + 32 * video::WIDTH * 3;
+        assert_eq!(ppm[at..at + 3], [0xff, 0x20, 0x20]);
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use opcodes::{opcode, Flow, Opcode, Operand};
+use values::{self, Indirect};
+
+// Jump tables are read until an entry stops looking like an address in the image, but
+// never further than this.
+const MAX_TABLE_ENTRIES: usize = 64;
+
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub opcode: Opcode,
+}
+
+impl Instruction {
+    fn word(&self) -> u16 {
+        ((self.bytes[2] as u16) << 8) | (self.bytes[1] as u16)
+    }
+
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.opcode.size())
+    }
+
+    pub fn operands(&self) -> Vec<String> {
+        let mut operands: Vec<String> = self.opcode.registers
+            .split(',')
+            .filter(|register| !register.is_empty())
+            .map(|register| register.to_string())
+            .collect();
+
+        match self.opcode.operand {
+            Operand::Implied => {},
+            Operand::Byte => operands.push(format!("${:02x}", self.bytes[1])),
+            Operand::Word => operands.push(format!("${:04x}", self.word())),
+        }
+
+        operands
+    }
+
+    /// Address control may transfer to, for jumps, calls and restarts.
+    pub fn target(&self) -> Option<u16> {
+        match self.opcode.flow() {
+            Flow::Jump | Flow::ConditionalJump | Flow::Call | Flow::ConditionalCall => Some(self.word()),
+            Flow::Restart => Some((self.bytes[0] & 0x38) as u16),
+            _ => None,
+        }
+    }
+
+    /// Address of the data this instruction loads or stores, if any.
+    pub fn data_reference(&self) -> Option<u16> {
+        if self.opcode.references_data() { Some(self.word()) } else { None }
+    }
+
+    pub fn instruction(&self) -> String {
+        if self.opcode.registers.is_empty() {
+            self.opcode.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.opcode.mnemonic, self.opcode.registers)
+        }
+    }
+
+    /// The instruction as it would be written in assembly source, e.g. `MVI B,$00`.
+    pub fn assembly(&self) -> String {
+        let operands = self.operands();
+        if operands.is_empty() {
+            self.opcode.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.opcode.mnemonic, operands.join(","))
+        }
+    }
+
+    /// A line of source the assembler turns back into exactly these bytes.
+    pub fn source(&self) -> String {
+        if self.opcode.mnemonic == "-" {
+            format!("DB ${:02x}", self.bytes[0])
+        } else {
+            self.assembly()
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let instruction = self.instruction();
+        match self.opcode.operand {
+            Operand::Implied => format!("{:04x} {:02x} \t{}", self.address, self.bytes[0], instruction),
+            Operand::Byte => format!("{:04x} {:02x} {byte_1:02x}\t{}\t${byte_1:02x}", self.address, self.bytes[0], instruction, byte_1 = self.bytes[1]),
+            Operand::Word => format!("{:04x} {:02x} {byte_1:02x} {byte_2:02x}\t{}\t${byte_2:02x}{byte_1:02x}", self.address, self.bytes[0], instruction, byte_1 = self.bytes[1], byte_2 = self.bytes[2]),
+        }
+    }
+}
+
+pub fn decode(memory: &[u8], address: u16) -> Instruction {
+    let opcode = opcode(memory[address as usize]);
+    let bytes = (0..opcode.size())
+        .map(|offset| *memory.get(address as usize + offset as usize).unwrap_or(&0))
+        .collect();
+
+    Instruction { address, bytes, opcode }
+}
+
+/// Decodes every instruction in `range`, one after the other.
+pub fn sweep(memory: &[u8], range: Range<usize>) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut pc = range.start;
+    while pc < range.end {
+        let instruction = decode(memory, pc as u16);
+        pc += instruction.opcode.size() as usize;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Class {
+    Code,
+    Data,
+    Unknown,
+}
+
+impl Class {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Class::Code => "code",
+            Class::Data => "data",
+            Class::Unknown => "unknown",
+        }
+    }
+}
+
+/// What following control flow from a set of entry points reveals about an image.
+pub struct Analysis {
+    pub code: BTreeSet<u16>,
+    pub subroutines: BTreeSet<u16>,
+    pub data: BTreeSet<u16>,
+    pub labels: BTreeMap<u16, String>,
+    /// Where each PCHL was found to go. An empty list means it couldn't be resolved and
+    /// needs annotating by hand.
+    pub indirect: BTreeMap<u16, Vec<u16>>,
+}
+
+impl Analysis {
+    pub fn class(&self, address: u16) -> Class {
+        if self.code.contains(&address) {
+            Class::Code
+        } else if self.data.contains(&address) {
+            Class::Data
+        } else {
+            Class::Unknown
+        }
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|label| label.as_str())
+    }
+
+    pub fn note(&self, address: u16) -> Option<String> {
+        self.indirect.get(&address).map(|targets| {
+            if targets.is_empty() {
+                "unresolved indirect jump".to_string()
+            } else {
+                let targets: Vec<String> = targets.iter().map(|target| format!("${:04x}", target)).collect();
+                format!("indirect jump to {}", targets.join(", "))
+            }
+        })
+    }
+}
+
+/// Follows control flow from `entry_points`, never leaving the loaded `range` of `memory`.
+pub fn analyse(memory: &[u8], range: &Range<usize>, entry_points: &[u16]) -> Analysis {
+    follow(memory, range, entry_points, true)
+}
+
+/// Like `analyse`, but stays within the routine at `entry` rather than descending into the
+/// subroutines it calls.
+pub fn analyse_routine(memory: &[u8], range: &Range<usize>, entry: u16) -> Analysis {
+    follow(memory, range, &[entry], false)
+}
+
+fn follow(memory: &[u8], range: &Range<usize>, entry_points: &[u16], calls: bool) -> Analysis {
+    let mut analysis = Analysis { code: BTreeSet::new(), subroutines: BTreeSet::new(), data: BTreeSet::new(), labels: BTreeMap::new(), indirect: BTreeMap::new() };
+    let mut pending: Vec<u16> = entry_points.to_vec();
+    // Maps the address after each straight-line instruction back to it, so the lead-up
+    // to a PCHL can be replayed.
+    let mut previous: BTreeMap<u16, u16> = BTreeMap::new();
+    analysis.subroutines.extend(entry_points);
+
+    while let Some(address) = pending.pop() {
+        if !range.contains(&(address as usize)) || analysis.code.contains(&address) {
+            continue
+        }
+        analysis.code.insert(address);
+
+        let instruction = decode(memory, address);
+        if let Some(data) = instruction.data_reference() {
+            analysis.data.insert(data);
+            analysis.labels.entry(data).or_insert_with(|| format!("dat_{:04x}", data));
+        }
+
+        let flow = instruction.opcode.flow();
+        if let Some(target) = instruction.target() {
+            let call = flow == Flow::Call || flow == Flow::ConditionalCall || flow == Flow::Restart;
+            if call {
+                analysis.labels.insert(target, format!("sub_{:04x}", target));
+            } else {
+                analysis.labels.entry(target).or_insert_with(|| format!("loc_{:04x}", target));
+            }
+
+            if !call {
+                pending.push(target);
+            } else if calls && range.contains(&(target as usize)) {
+                analysis.subroutines.insert(target);
+                pending.push(target);
+            }
+        }
+
+        if flow == Flow::Indirect {
+            let targets = resolve_indirect(memory, range, &previous, &mut analysis, address);
+            pending.extend(&targets);
+            analysis.indirect.insert(address, targets);
+        }
+
+        match flow {
+            Flow::Jump | Flow::Return | Flow::Indirect | Flow::Halt => {},
+            Flow::Next => {
+                previous.insert(instruction.next(), address);
+                pending.push(instruction.next());
+            },
+            _ => pending.push(instruction.next()),
+        }
+    }
+
+    // Code that is also read as data (self-modifying or shared tables) is still code.
+    analysis.data = analysis.data.difference(&analysis.code).cloned().collect();
+    analysis
+}
+
+fn resolve_indirect(memory: &[u8], range: &Range<usize>, previous: &BTreeMap<u16, u16>, analysis: &mut Analysis, address: u16) -> Vec<u16> {
+    let mut path = vec![];
+    let mut start = address;
+    while let Some(&address) = previous.get(&start) {
+        if path.len() == 16 { break }
+        path.push(decode(memory, address));
+        start = address;
+    }
+    path.reverse();
+
+    match values::resolve_indirect(memory, range, &path) {
+        Indirect::Target(target) => {
+            analysis.labels.entry(target).or_insert_with(|| format!("loc_{:04x}", target));
+            vec![target]
+        },
+        Indirect::Table(base) => {
+            analysis.labels.entry(base).or_insert_with(|| format!("tbl_{:04x}", base));
+            let mut targets = vec![];
+            for entry in 0..MAX_TABLE_ENTRIES {
+                let entry_address = base as usize + entry * 2;
+                if !range.contains(&(entry_address + 1)) || analysis.code.contains(&(entry_address as u16)) {
+                    break
+                }
+                // Another label means another table or routine starts here.
+                if entry > 0 && analysis.labels.contains_key(&(entry_address as u16)) {
+                    break
+                }
+
+                let target = ((memory[entry_address + 1] as u16) << 8) | memory[entry_address] as u16;
+                if !range.contains(&(target as usize)) {
+                    break
+                }
+                // Whatever follows a table is often data that happens to look like addresses in
+                // the image. Padding, repeats and words that can't be code mark where it ends.
+                if target == 0 || targets.contains(&target) || !could_be_code(memory, analysis, target) {
+                    break
+                }
+                analysis.data.insert(entry_address as u16);
+                analysis.labels.entry(target).or_insert_with(|| format!("loc_{:04x}", target));
+                targets.push(target);
+            }
+            targets
+        },
+        Indirect::Unresolved => vec![],
+    }
+}
+
+/// Whether `address` could start an instruction, as far as the analysis knows so far: it
+/// isn't data, and no instruction already found runs over it.
+fn could_be_code(memory: &[u8], analysis: &Analysis, address: u16) -> bool {
+    !analysis.data.contains(&address) && (1..3).all(|back: u16| {
+        let start = address.wrapping_sub(back);
+        !analysis.code.contains(&start) || decode(memory, start).bytes.len() <= back as usize
+    })
+}
+
+/// The instructions the analysis reached, in address order.
+pub fn reached(memory: &[u8], analysis: &Analysis) -> Vec<Instruction> {
+    analysis.code.iter().map(|&address| decode(memory, address)).collect()
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// One JSON object per instruction, for scripts that post-process the listing.
+pub fn json_record(instruction: &Instruction, analysis: &Analysis) -> String {
+    let bytes: Vec<String> = instruction.bytes.iter().map(|byte| byte.to_string()).collect();
+    let operands: Vec<String> = instruction.operands().iter().map(|operand| json_string(operand)).collect();
+    let target = instruction.target().map_or("null".to_string(), |target| target.to_string());
+    let label = analysis.label(instruction.address).map_or("null".to_string(), json_string);
+    let note = analysis.note(instruction.address).map_or("null".to_string(), |note| json_string(&note));
+
+    format!(
+        "{{\"address\":{},\"bytes\":[{}],\"mnemonic\":{},\"operands\":[{}],\"target\":{},\"label\":{},\"class\":{},\"note\":{}}}",
+        instruction.address,
+        bytes.join(","),
+        json_string(instruction.opcode.mnemonic),
+        operands.join(","),
+        target,
+        label,
+        json_string(analysis.class(instruction.address).name()),
+        note,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A call to a subroutine that reads data, then a jump out of the image, from 100H.
+    const PROGRAM: [u8; 10] = [
+        0xcd, 0x06, 0x01, // START: CALL SUB
+        0xc3, 0x00, 0x20, //        JMP 2000H
+        0x3a, 0x20, 0x01, // SUB:   LDA 0120H
+        0xc9,             //        RET
+    ];
+
+    fn load(program: &[u8]) -> (Vec<u8>, Range<usize>) {
+        let mut memory = vec![0; 0x10000];
+        memory[0x100..0x100 + program.len()].copy_from_slice(program);
+        (memory, 0x100..0x100 + program.len())
+    }
+
+    #[test]
+    fn sweeping_starts_at_the_range() {
+        let (memory, range) = load(&PROGRAM);
+        let addresses: Vec<u16> = sweep(&memory, range).iter().map(|instruction| instruction.address).collect();
+        assert_eq!(addresses, [0x100, 0x103, 0x106, 0x109]);
+    }
+
+    #[test]
+    fn analysis_follows_calls_but_stays_in_the_range() {
+        let (memory, range) = load(&PROGRAM);
+        let analysis = analyse(&memory, &range, &[0x100]);
+        assert_eq!(analysis.code.iter().cloned().collect::<Vec<_>>(), [0x100, 0x103, 0x106, 0x109]);
+        assert_eq!(analysis.subroutines.iter().cloned().collect::<Vec<_>>(), [0x100, 0x106]);
+        assert_eq!(analysis.label(0x106), Some("sub_0106"));
+        assert_eq!(analysis.label(0x2000), Some("loc_2000"), "jumps out of the range are still labelled");
+        assert_eq!(analysis.class(0x120), Class::Data);
+        assert_eq!(analysis.label(0x120), Some("dat_0120"));
+    }
+
+    #[test]
+    fn a_routine_leaves_out_what_it_calls() {
+        let (memory, range) = load(&PROGRAM);
+        let analysis = analyse_routine(&memory, &range, 0x100);
+        assert_eq!(analysis.code.iter().cloned().collect::<Vec<_>>(), [0x100, 0x103]);
+        assert_eq!(analysis.label(0x106), Some("sub_0106"));
+        assert_eq!(analysis.class(0x106), Class::Unknown);
+    }
+
+    #[test]
+    fn undefined_opcodes_are_written_as_bytes() {
+        let memory = [0x08, 0x3e, 0x12, 0xc3, 0x34, 0x12];
+        assert_eq!(decode(&memory, 0).source(), "DB $08");
+        assert_eq!(decode(&memory, 1).source(), "MVI A,$12");
+        assert_eq!(decode(&memory, 3).source(), "JMP $1234");
+    }
+
+    #[test]
+    fn json_records_describe_each_instruction() {
+        let (memory, range) = load(&PROGRAM);
+        let analysis = analyse(&memory, &range, &[0x100]);
+        assert_eq!(
+            json_record(&decode(&memory, 0x103), &analysis),
+            r#"{"address":259,"bytes":[195,0,32],"mnemonic":"JMP","operands":["$2000"],"target":8192,"label":null,"class":"code","note":null}"#,
+        );
+        assert_eq!(
+            json_record(&decode(&memory, 0x106), &analysis),
+            r#"{"address":262,"bytes":[58,32,1],"mnemonic":"LDA","operands":["$0120"],"target":null,"label":"sub_0106","class":"code","note":null}"#,
+        );
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("a\"b\\c\n\t\u{1}"), r#""a\"b\\c\n\t\u0001""#);
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+
+use assembler::Dialect;
+
+/// What a value is relative to: nothing, the start of the module's code or data segment,
+/// or a symbol defined in another module.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Relocation {
+    Absolute,
+    Code,
+    Data,
+    External(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Value {
+    pub number: i32,
+    pub relocation: Relocation,
+}
+
+impl Value {
+    pub fn absolute(number: i32) -> Value {
+        Value { number, relocation: Relocation::Absolute }
+    }
+}
+
+/// Everything an expression can refer to besides numbers.
+pub struct Context<'a> {
+    pub symbols: &'a BTreeMap<String, u16>,
+    /// What each symbol that isn't absolute is relative to.
+    pub relocations: &'a BTreeMap<String, Relocation>,
+    /// The value of `$`.
+    pub location: u16,
+    pub segment: Relocation,
+    /// The base of numbers written without a suffix.
+    pub radix: u32,
+    pub dialect: Dialect,
+    /// The label local labels (those starting with `.`) belong to.
+    pub scope: &'a str,
+}
+
+/// The name a symbol is stored under: upper case, with local labels qualified by the label
+/// they follow, and ASM80's `$` digit separators removed.
+pub fn canonical(name: &str, dialect: Dialect, scope: &str) -> String {
+    let mut name = name.to_uppercase();
+    if dialect == Dialect::Asm80 && name != "$" {
+        name = name.replace('$', "");
+    }
+    if name.starts_with('.') {
+        name = format!("{}{}", scope, name);
+    }
+    name
+}
+
+fn is_word_character(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"_$?@.".contains(&c)
+}
+
+fn boolean(value: bool) -> i32 {
+    // True is all ones, as Intel and Microsoft both have it.
+    if value { -1 } else { 0 }
+}
+
+fn absolute(value: Value, operator: &str) -> Result<i32, String> {
+    match value.relocation {
+        Relocation::Absolute => Ok(value.number),
+        _ => Err(format!("relocatable value can't be used with {}", operator)),
+    }
+}
+
+/// Applies a binary operator. Only adding to or subtracting from a relocatable value, or
+/// subtracting two in the same segment, gives something the linker can still relocate.
+fn combine(operator: &str, left: Value, right: Value) -> Result<Value, String> {
+    let (left_number, right_number) = (left.number, right.number);
+    let relocation = match (operator, left.relocation, right.relocation) {
+        ("+", relocation, Relocation::Absolute) | ("+", Relocation::Absolute, relocation) => relocation,
+        ("-", relocation, Relocation::Absolute) => relocation,
+        ("-", Relocation::Code, Relocation::Code) | ("-", Relocation::Data, Relocation::Data) => Relocation::Absolute,
+        (_, Relocation::Absolute, Relocation::Absolute) => Relocation::Absolute,
+        _ => return Err(format!("relocatable value can't be used with {}", operator)),
+    };
+    let (left, right) = (left_number, right_number);
+
+    let number = match operator {
+        "|" | "OR" => left | right,
+        "^" | "XOR" => left ^ right,
+        "&" | "AND" => left & right,
+        "EQ" => boolean(left as u16 == right as u16),
+        "NE" => boolean(left as u16 != right as u16),
+        "LE" => boolean(left as u16 <= right as u16),
+        "LT" => boolean((left as u16) < right as u16),
+        "GE" => boolean(left as u16 >= right as u16),
+        "GT" => boolean(left as u16 > right as u16),
+        "<<" | "SHL" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "SHR" => ((left as u16) >> (right & 15)) as i32,
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        _ if right == 0 => return Err("division by zero".to_string()),
+        "/" => left / right,
+        _ => left % right,
+    };
+    Ok(Value { number, relocation })
+}
+
+struct Expression<'a> {
+    text: &'a [u8],
+    position: usize,
+    context: &'a Context<'a>,
+}
+
+impl<'a> Expression<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        while self.position < self.text.len() && self.text[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        self.text.get(self.position).cloned()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.peek();
+        let rest = &self.text[self.position..];
+        if rest.len() < token.len() || !rest[..token.len()].eq_ignore_ascii_case(token.as_bytes()) {
+            return false
+        }
+        // Operators spelt as words mustn't match the start of a longer name.
+        if is_word_character(token.as_bytes()[0]) && rest.get(token.len()).is_some_and(|&c| is_word_character(c)) {
+            return false
+        }
+        self.position += token.len();
+        true
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Value, String> {
+        const LEVELS: [&[&str]; 6] = [
+            &["|", "OR", "^", "XOR"],
+            &["&", "AND"],
+            &[],
+            &["EQ", "NE", "LE", "LT", "GE", "GT"],
+            &["<<", ">>"],
+            &["+", "-"],
+        ];
+        if level == LEVELS.len() {
+            return self.term()
+        }
+        // NOT binds less tightly than comparisons but more than AND.
+        if LEVELS[level].is_empty() {
+            return if self.eat("NOT") { Ok(Value::absolute(!absolute(self.binary(level)?, "NOT")?)) } else { self.binary(level + 1) }
+        }
+
+        let mut value = self.binary(level + 1)?;
+        'operators: loop {
+            for &operator in LEVELS[level] {
+                if self.eat(operator) {
+                    let right = self.binary(level + 1)?;
+                    value = combine(operator, value, right)?;
+                    continue 'operators
+                }
+            }
+            return Ok(value)
+        }
+    }
+
+    fn term(&mut self) -> Result<Value, String> {
+        let mut value = self.unary()?;
+        loop {
+            let operator = ["*", "/", "%", "MOD", "SHL", "SHR"].iter().find(|&&operator| self.eat(operator)).cloned();
+            let operator = match operator {
+                Some(operator) => operator,
+                None => return Ok(value),
+            };
+            let right = self.unary()?;
+            value = combine(operator, value, right)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat("-") {
+            let value = self.unary()?;
+            combine("-", Value::absolute(0), value)
+        } else if self.eat("+") {
+            self.unary()
+        } else if self.eat("~") {
+            Ok(Value::absolute(!absolute(self.unary()?, "~")?))
+        } else if self.eat("HIGH") {
+            Ok(Value::absolute((absolute(self.unary()?, "HIGH")? >> 8) & 0xff))
+        } else if self.eat("LOW") {
+            Ok(Value::absolute(absolute(self.unary()?, "LOW")? & 0xff))
+        } else if self.eat("NUL") {
+            // True when nothing follows, as when a macro argument is left out.
+            let blank = self.peek().is_none();
+            self.position = self.text.len();
+            Ok(Value::absolute(boolean(blank)))
+        } else {
+            self.primary()
+        }
+    }
+
+    /// Reads a quoted string starting at the current position, where a doubled quote
+    /// stands for the quote itself.
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        let quote = self.text[self.position];
+        let mut characters = vec![];
+        self.position += 1;
+        loop {
+            match self.text.get(self.position) {
+                None => return Err("unterminated string".to_string()),
+                Some(&c) if c == quote && self.text.get(self.position + 1) == Some(&quote) => {
+                    characters.push(quote);
+                    self.position += 2;
+                },
+                Some(&c) if c == quote => {
+                    self.position += 1;
+                    return Ok(characters)
+                },
+                Some(&c) => {
+                    characters.push(c);
+                    self.position += 1;
+                },
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(b'(') => {
+                self.position += 1;
+                let value = self.binary(0)?;
+                if !self.eat(")") {
+                    return Err("missing )".to_string())
+                }
+                Ok(value)
+            },
+            Some(quote) if quote == b'\'' || quote == b'"' => {
+                let characters = self.string()?;
+                // Two characters make a word, the first in the high byte.
+                match characters.len() {
+                    0 => Ok(Value::absolute(0)),
+                    1 => Ok(Value::absolute(characters[0] as i32)),
+                    2 => Ok(Value::absolute(((characters[0] as i32) << 8) | characters[1] as i32)),
+                    _ => Err("character constants hold one or two characters".to_string()),
+                }
+            },
+            // M80 writes hex constants as X'1F'.
+            Some(b'X') | Some(b'x') if self.context.dialect == Dialect::M80 && self.text.get(self.position + 1) == Some(&b'\'') => {
+                self.position += 1;
+                let digits = String::from_utf8_lossy(&self.string()?).into_owned();
+                i32::from_str_radix(&digits, 16).map(Value::absolute).map_err(|_| format!("invalid number: X'{}'", digits))
+            },
+            Some(_) => {
+                let start = self.position;
+                while self.position < self.text.len() && is_word_character(self.text[self.position]) {
+                    self.position += 1;
+                }
+                if start == self.position {
+                    return Err(format!("unexpected {}", self.text[start] as char))
+                }
+                let word = String::from_utf8_lossy(&self.text[start..self.position]).into_owned();
+                // M80 lets NAME## refer to a symbol in another module without declaring it.
+                if self.text[self.position..].starts_with(b"##") {
+                    self.position += 2;
+                    let name = canonical(&word, self.context.dialect, self.context.scope);
+                    return Ok(Value { number: 0, relocation: Relocation::External(name) })
+                }
+                self.word(&word)
+            },
+            None => Err("missing operand".to_string()),
+        }
+    }
+
+    fn number(&self, word: &str) -> Result<i32, String> {
+        let parse = |digits: &str, radix| {
+            u32::from_str_radix(digits, radix)
+                .ok()
+                .filter(|&value| value <= 0xffff)
+                .map(|value| value as i32)
+                .ok_or_else(|| format!("invalid number: {}", word))
+        };
+        let radix = self.context.radix;
+        let (digits, suffix) = word.split_at(word.len() - 1);
+
+        // B and D are digits themselves once the radix is high enough.
+        match suffix {
+            "H" => parse(digits, 16),
+            "O" | "Q" => parse(digits, 8),
+            "B" if radix < 12 => parse(digits, 2),
+            "D" if radix < 14 => parse(digits, 10),
+            _ => parse(word, radix),
+        }
+    }
+
+    fn word(&self, word: &str) -> Result<Value, String> {
+        let context = self.context;
+        let original = word;
+        let word = canonical(word, context.dialect, context.scope);
+
+        if word.is_empty() {
+            Err(format!("invalid name: {}", original))
+        } else if word == "$" {
+            Ok(Value { number: context.location as i32, relocation: context.segment.clone() })
+        } else if let Some(digits) = word.strip_prefix("0X") {
+            i32::from_str_radix(digits, 16).map(Value::absolute).map_err(|_| format!("invalid number: {}", word))
+        } else if let Some(digits) = word.strip_prefix('$').filter(|_| context.dialect == Dialect::Native) {
+            i32::from_str_radix(digits, 16).map(Value::absolute).map_err(|_| format!("invalid number: {}", word))
+        } else if word.as_bytes()[0].is_ascii_digit() {
+            self.number(&word).map(Value::absolute)
+        } else {
+            let number = context.symbols.get(&word).ok_or_else(|| format!("undefined symbol: {}", word))?;
+            let relocation = context.relocations.get(&word).cloned().unwrap_or(Relocation::Absolute);
+            Ok(Value { number: *number as i32, relocation })
+        }
+    }
+}
+
+pub fn evaluate(text: &str, context: &Context) -> Result<Value, String> {
+    let mut expression = Expression { text: text.as_bytes(), position: 0, context };
+    let value = expression.binary(0)?;
+    match expression.peek() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected {} in expression", c as char)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate_in(text: &str, dialect: Dialect, radix: u32) -> Result<Value, String> {
+        let mut symbols = BTreeMap::new();
+        symbols.insert("TEN".to_string(), 10);
+        symbols.insert("START".to_string(), 0x100);
+        symbols.insert("MAIN.LOOP".to_string(), 0x104);
+        let mut relocations = BTreeMap::new();
+        relocations.insert("START".to_string(), Relocation::Code);
+        let context = Context { symbols: &symbols, relocations: &relocations, location: 0x200, segment: Relocation::Code, radix, dialect, scope: "MAIN" };
+        evaluate(text, &context)
+    }
+
+    fn number(text: &str) -> i32 {
+        let value = evaluate_in(text, Dialect::Native, 10).unwrap();
+        assert_eq!(value.relocation, Relocation::Absolute, "{}", text);
+        value.number
+    }
+
+    #[test]
+    fn suffixes_and_prefixes_give_the_radix() {
+        assert_eq!(number("1FH"), 0x1f);
+        assert_eq!(number("17O"), 0o17);
+        assert_eq!(number("17q"), 0o17);
+        assert_eq!(number("101B"), 5);
+        assert_eq!(number("99D"), 99);
+        assert_eq!(number("0x1f"), 0x1f);
+        assert_eq!(number("$1f"), 0x1f);
+        assert!(evaluate_in("1FFFFH", Dialect::Native, 10).is_err(), "numbers are 16 bits");
+        assert!(evaluate_in("19O", Dialect::Native, 10).is_err());
+    }
+
+    #[test]
+    fn b_and_d_are_digits_in_a_high_radix() {
+        assert_eq!(evaluate_in("1B", Dialect::Native, 16).unwrap().number, 0x1b);
+        assert_eq!(evaluate_in("1D", Dialect::Native, 16).unwrap().number, 0x1d);
+        assert_eq!(evaluate_in("777", Dialect::Native, 8).unwrap().number, 0o777);
+    }
+
+    #[test]
+    fn dialects_spell_hex_their_own_way() {
+        assert_eq!(evaluate_in("X'1F'", Dialect::M80, 10).unwrap().number, 0x1f);
+        assert_eq!(evaluate_in("0FF$FFH", Dialect::Asm80, 10).unwrap().number, 0xffff);
+        assert_eq!(evaluate_in("T$E$N", Dialect::Asm80, 10).unwrap().number, 10);
+    }
+
+    #[test]
+    fn dollar_is_the_location_in_its_segment() {
+        assert_eq!(evaluate_in("$", Dialect::Native, 10), Ok(Value { number: 0x200, relocation: Relocation::Code }));
+        assert_eq!(evaluate_in("$+3", Dialect::Native, 10), Ok(Value { number: 0x203, relocation: Relocation::Code }));
+        assert_eq!(evaluate_in("$-START", Dialect::Native, 10), Ok(Value::absolute(0x100)));
+    }
+
+    #[test]
+    fn operators_bind_as_intel_has_them() {
+        assert_eq!(number("2+3*4"), 14);
+        assert_eq!(number("(2+3)*4"), 20);
+        assert_eq!(number("1 SHL 4 + 1"), 17);
+        assert_eq!(number("HIGH 1234H + LOW 1234H"), 0x12 + 0x34);
+        assert_eq!(number("NOT 0 AND 0FH"), 0x0f);
+        assert_eq!(number("TEN EQ 10"), -1);
+        assert_eq!(number("TEN GT 10"), 0);
+        assert_eq!(number("17 MOD 5"), 2);
+        assert_eq!(number("-1"), -1);
+        assert_eq!(number("0FFFFH SHR 12"), 15);
+    }
+
+    #[test]
+    fn characters_are_numbers() {
+        assert_eq!(number("'A'"), 0x41);
+        assert_eq!(number("'AB'"), 0x4142);
+        assert_eq!(number("''''"), 0x27);
+        assert_eq!(evaluate_in("'ABC'", Dialect::Native, 10), Err("character constants hold one or two characters".to_string()));
+        assert_eq!(evaluate_in("'A", Dialect::Native, 10), Err("unterminated string".to_string()));
+    }
+
+    #[test]
+    fn local_labels_belong_to_the_scope() {
+        assert_eq!(number(".LOOP"), 0x104);
+        assert_eq!(canonical(".loop", Dialect::Native, "MAIN"), "MAIN.LOOP");
+    }
+
+    #[test]
+    fn relocatable_values_only_move_by_constants() {
+        assert_eq!(evaluate_in("START+2", Dialect::Native, 10), Ok(Value { number: 0x102, relocation: Relocation::Code }));
+        assert_eq!(evaluate_in("START*2", Dialect::Native, 10), Err("relocatable value can't be used with *".to_string()));
+        assert_eq!(evaluate_in("HIGH START", Dialect::Native, 10), Err("relocatable value can't be used with HIGH".to_string()));
+        assert_eq!(evaluate_in("NAME##+1", Dialect::Native, 10), Ok(Value { number: 1, relocation: Relocation::External("NAME".to_string()) }));
+    }
+
+    #[test]
+    fn mistakes_are_explained() {
+        assert_eq!(evaluate_in("1/0", Dialect::Native, 10), Err("division by zero".to_string()));
+        assert_eq!(evaluate_in("NOWHERE", Dialect::Native, 10), Err("undefined symbol: NOWHERE".to_string()));
+        assert_eq!(evaluate_in("(1+2", Dialect::Native, 10), Err("missing )".to_string()));
+        assert_eq!(evaluate_in("1 2", Dialect::Native, 10), Err("unexpected 2 in expression".to_string()));
+        assert_eq!(evaluate_in("", Dialect::Native, 10), Err("missing operand".to_string()));
+        assert_eq!(evaluate_in("$$", Dialect::Asm80, 10), Err("invalid name: $$".to_string()));
+    }
+}
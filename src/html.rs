@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use disassembler::{self, Analysis, Class};
+use symbols::Symbols;
+
+const DATA_BYTES_PER_ROW: usize = 16;
+
+const STYLE: &str = "\
+body { background: #fff; color: #222; }
+a { color: #0645ad; text-decoration: none; }
+a:hover { text-decoration: underline; }
+.label { color: #a31515; font-weight: bold; }
+.comment, .xref { color: #008000; }
+.data { color: #666; }
+:target { background: #ffd; }
+";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn anchor(address: u16) -> String {
+    format!("a{:04x}", address)
+}
+
+/// A link to `address`, or just the text if it has no label or no row of its own to go to.
+fn link(analysis: &Analysis, anchored: &BTreeSet<u16>, address: u16, text: &str) -> String {
+    match analysis.label(address).filter(|_| anchored.contains(&address)) {
+        Some(label) => format!("<a href=\"#{}\" title=\"{}\">{}</a>", anchor(address), escape(label), escape(text)),
+        None => escape(text),
+    }
+}
+
+/// Where the row of data starting at `pc` ends: at the next label, code or comment, or when
+/// the row is full.
+fn data_end(range: &Range<usize>, analysis: &Analysis, symbols: &Symbols, pc: usize) -> usize {
+    let mut end = pc + 1;
+    while end < range.end && end - pc < DATA_BYTES_PER_ROW {
+        let next = end as u16;
+        if analysis.labels.contains_key(&next) || analysis.class(next) == Class::Code || symbols.comments.contains_key(&next) {
+            break
+        }
+        end += 1;
+    }
+    end
+}
+
+/// The addresses the listing starts a row at, which are the only ones with an anchor.
+fn rows(memory: &[u8], range: &Range<usize>, analysis: &Analysis, symbols: &Symbols) -> BTreeSet<u16> {
+    let mut rows = BTreeSet::new();
+    let mut pc = range.start;
+    while pc < range.end {
+        rows.insert(pc as u16);
+        pc = if analysis.class(pc as u16) == Class::Code {
+            pc + disassembler::decode(memory, pc as u16).opcode.size() as usize
+        } else {
+            data_end(range, analysis, symbols, pc)
+        };
+    }
+    rows
+}
+
+/// Every address each labelled address is referred to from.
+fn cross_references(memory: &[u8], analysis: &Analysis) -> BTreeMap<u16, BTreeSet<u16>> {
+    let mut references: BTreeMap<u16, BTreeSet<u16>> = BTreeMap::new();
+    for &address in &analysis.code {
+        let instruction = disassembler::decode(memory, address);
+        let targets = instruction.target().into_iter()
+            .chain(instruction.data_reference())
+            .chain(analysis.indirect.get(&address).into_iter().flatten().cloned());
+        for target in targets {
+            references.entry(target).or_default().insert(address);
+        }
+    }
+    references
+}
+
+/// A static page listing `range`: code as instructions, everything else as hex and ASCII,
+/// with labels, comments from `symbols` and cross-references linked to each other.
+pub fn listing(title: &str, memory: &[u8], range: Range<usize>, analysis: &Analysis, symbols: &Symbols) -> String {
+    let references = cross_references(memory, analysis);
+    let anchored = rows(memory, &range, analysis, symbols);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<pre>\n",
+        escape(title), STYLE,
+    );
+
+    let mut pc = range.start;
+    while pc < range.end {
+        let address = pc as u16;
+
+        if let Some(label) = analysis.label(address) {
+            html.push_str(&format!("\n<span class=\"label\">{}:</span>", escape(label)));
+            if let Some(from) = references.get(&address) {
+                let from: Vec<String> = from.iter().map(|&from| if anchored.contains(&from) {
+                    format!("<a href=\"#{}\">{:04x}</a>", anchor(from), from)
+                } else {
+                    format!("{:04x}", from)
+                }).collect();
+                html.push_str(&format!("<span class=\"xref\">\t; xref {}</span>", from.join(" ")));
+            }
+            html.push('\n');
+        }
+
+        let (row, size) = if analysis.class(address) == Class::Code {
+            let instruction = disassembler::decode(memory, address);
+            let bytes: Vec<String> = instruction.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let mut operands = instruction.operands();
+            if let Some(target) = instruction.target().or_else(|| instruction.data_reference()) {
+                if let Some(operand) = operands.last_mut() {
+                    *operand = link(analysis, &anchored, target, operand);
+                }
+            }
+            let text = if operands.is_empty() {
+                instruction.opcode.mnemonic.to_string()
+            } else {
+                format!("{:<5}{}", instruction.opcode.mnemonic, operands.join(","))
+            };
+
+            let mut row = format!("<span id=\"{}\">{:04x}</span>  {:<9} {}", anchor(address), address, bytes.join(" "), text);
+            if let Some(note) = analysis.note(address) {
+                row.push_str(&format!("<span class=\"comment\">\t; {}</span>", escape(&note)));
+            }
+            (row, instruction.opcode.size() as usize)
+        } else {
+            let end = data_end(&range, analysis, symbols, pc);
+            let bytes = &memory[pc..end];
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = bytes.iter()
+                .map(|&byte| if (0x20..0x7f).contains(&byte) { byte as char } else { '.' })
+                .collect();
+            let row = format!(
+                "<span id=\"{}\">{:04x}</span>  <span class=\"data\">{:<47}  |{}|</span>",
+                anchor(address), address, hex.join(" "), escape(&ascii),
+            );
+            (row, end - pc)
+        };
+
+        html.push_str(&row);
+        if let Some(comment) = symbols.comments.get(&address) {
+            html.push_str(&format!("<span class=\"comment\">\t; {}</span>", escape(comment)));
+        }
+        html.push('\n');
+        pc += size;
+    }
+
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Code that reads a message and loops back on itself.
+    const PROGRAM: [u8; 10] = [
+        0x3a, 0x06, 0x00,       // START: LDA MSG
+        0xc3, 0x00, 0x00,       //        JMP START
+        0x48, 0x69, 0x3c, 0x00, // MSG:   DB 'Hi<',0
+    ];
+
+    fn page(program: &[u8], symbols: &Symbols) -> String {
+        let mut memory = vec![0; 0x10000];
+        memory[..program.len()].copy_from_slice(program);
+        let range = 0..program.len();
+        let mut analysis = disassembler::analyse(&memory, &range, &[0]);
+        symbols.apply(&mut analysis);
+        listing("a <test>", &memory, range, &analysis, symbols)
+    }
+
+    #[test]
+    fn the_page_is_titled_and_closed() {
+        let html = page(&PROGRAM, &Symbols::default());
+        assert!(html.starts_with("<!DOCTYPE html>\n"));
+        assert!(html.contains("<title>a &lt;test&gt;</title>"), "{}", html);
+        assert!(html.ends_with("</pre>\n</body>\n</html>\n"));
+    }
+
+    #[test]
+    fn labels_list_where_they_are_referred_to_from() {
+        let html = page(&PROGRAM, &Symbols::default());
+        assert!(html.contains("<span class=\"label\">loc_0000:</span><span class=\"xref\">\t; xref <a href=\"#a0003\">0003</a></span>\n"), "{}", html);
+        assert!(html.contains("<span class=\"label\">dat_0006:</span><span class=\"xref\">\t; xref <a href=\"#a0000\">0000</a></span>\n"), "{}", html);
+    }
+
+    #[test]
+    fn operands_link_to_their_labels() {
+        let html = page(&PROGRAM, &Symbols::default());
+        assert!(html.contains("<span id=\"a0000\">0000</span>  3a 06 00  LDA  <a href=\"#a0006\" title=\"dat_0006\">$0006</a>\n"), "{}", html);
+        assert!(html.contains("<span id=\"a0003\">0003</span>  c3 00 00  JMP  <a href=\"#a0000\" title=\"loc_0000\">$0000</a>\n"), "{}", html);
+    }
+
+    #[test]
+    fn data_is_shown_as_hex_and_escaped_ascii() {
+        let html = page(&PROGRAM, &Symbols::default());
+        assert!(html.contains(&format!("<span id=\"a0006\">0006</span>  <span class=\"data\">{:<47}  |Hi&lt;.|</span>\n", "48 69 3c 00")), "{}", html);
+    }
+
+    #[test]
+    fn symbols_name_addresses_and_add_comments() {
+        let mut symbols = Symbols::default();
+        symbols.labels.insert(6, "message".to_string());
+        symbols.comments.insert(3, "again & again".to_string());
+        let html = page(&PROGRAM, &symbols);
+        assert!(html.contains("<span class=\"label\">message:</span>"), "{}", html);
+        assert!(html.contains("title=\"message\">$0006</a>"), "{}", html);
+        assert!(html.contains("JMP  <a href=\"#a0000\" title=\"loc_0000\">$0000</a><span class=\"comment\">\t; again &amp; again</span>\n"), "{}", html);
+    }
+
+    #[test]
+    fn addresses_without_a_row_are_not_linked() {
+        // LDA 1 reads from the middle of itself, and JMP 1000H leaves the image.
+        let html = page(&[0x3a, 0x01, 0x00, 0xc3, 0x00, 0x10], &Symbols::default());
+        assert!(html.contains("<span id=\"a0000\">0000</span>  3a 01 00  LDA  $0001\n"), "{}", html);
+        assert!(html.contains("<span id=\"a0003\">0003</span>  c3 00 10  JMP  $1000\n"), "{}", html);
+        assert!(!html.contains("href=\"#a1000\"") && !html.contains("href=\"#a0001\""), "{}", html);
+    }
+}
@@ -0,0 +1,195 @@
+//! A decoder for DEFLATE streams (RFC 1951), the compression nearly every ZIP archive uses.
+
+struct Bits<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+impl<'a> Bits<'a> {
+    /// Reads `count` bits, least significant first.
+    fn bits(&mut self, count: usize) -> Result<u32, String> {
+        let mut value = 0;
+        for index in 0..count {
+            let byte = self.bytes.get(self.bit / 8).ok_or_else(|| "compressed data ends early".to_string())?;
+            value |= ((byte >> (self.bit % 8)) as u32 & 1) << index;
+            self.bit += 1;
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.bit = self.bit.div_ceil(8) * 8;
+    }
+}
+
+/// A canonical Huffman code, kept as how many codes there are of each length and the
+/// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols = vec![];
+        for length in 1..16 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|&(_, &l)| l as usize == length) {
+                symbols.push(symbol as u16);
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+const LENGTH_BASES: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASES: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// The order the lengths of the code length code come in.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn fixed() -> (Huffman, Huffman) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].iter_mut().for_each(|length| *length = 9);
+    lengths[256..280].iter_mut().for_each(|length| *length = 7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+
+    let mut lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[index] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+
+    let mut lengths = vec![];
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| "nothing to repeat in code lengths".to_string())?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend((0..repeat).map(|_| length));
+    }
+    if lengths.len() > literals + distances {
+        return Err("code lengths run past the end".to_string())
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+pub fn inflate(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bits = Bits { bytes, bit: 0 };
+    let mut output = vec![];
+
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let length = bits.bits(16)?;
+                if bits.bits(16)? != !length & 0xffff {
+                    return Err("stored block length doesn't match its complement".to_string())
+                }
+                for _ in 0..length {
+                    output.push(bits.bits(8)? as u8);
+                }
+            },
+            kind @ 1..=2 => {
+                let (literal, distance) = if kind == 1 { fixed() } else { dynamic(&mut bits)? };
+                loop {
+                    let symbol = literal.decode(&mut bits)? as usize;
+                    if symbol < 256 {
+                        output.push(symbol as u8);
+                        continue
+                    }
+                    if symbol == 256 {
+                        break
+                    }
+                    let index = symbol - 257;
+                    if index >= LENGTH_BASES.len() {
+                        return Err(format!("invalid length symbol {}", symbol))
+                    }
+                    let length = LENGTH_BASES[index] as usize + bits.bits(LENGTH_EXTRA[index] as usize)? as usize;
+                    let index = distance.decode(&mut bits)? as usize;
+                    if index >= DISTANCE_BASES.len() {
+                        return Err(format!("invalid distance symbol {}", index))
+                    }
+                    let back = DISTANCE_BASES[index] as usize + bits.bits(DISTANCE_EXTRA[index] as usize)? as usize;
+                    if back > output.len() {
+                        return Err("distance reaches back before the start".to_string())
+                    }
+                    // Copies can overlap what they produce, so go a byte at a time.
+                    for _ in 0..length {
+                        output.push(output[output.len() - back]);
+                    }
+                }
+            },
+            _ => return Err("invalid block type".to_string()),
+        }
+        if last {
+            return Ok(output)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_blocks_are_copied() {
+        assert_eq!(inflate(&[0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o']), Ok(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn fixed_huffman_blocks_decode() {
+        // "hello" three times, the second two as a back reference overlapping itself.
+        assert_eq!(inflate(&[0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00]), Ok(b"hello hello hello".to_vec()));
+    }
+
+    #[test]
+    fn dynamic_huffman_blocks_decode() {
+        let compressed = [
+            0xcd, 0xcb, 0x31, 0x01, 0x00, 0x30, 0x0c, 0x02, 0x30, 0xad, 0x50, 0x68, 0xf1, 0xaf, 0x60, 0xc8, 0xd8, 0x91, 0x33, 0xa0,
+            0x09, 0x7a, 0x38, 0x5b, 0xa7, 0x51, 0x39, 0x56, 0x79, 0xb1, 0x75, 0x7b, 0xac, 0xc9, 0xa5, 0x20, 0xa4, 0xf0, 0xd9, 0x7b,
+        ];
+        assert_eq!(compressed[0] >> 1 & 3, 2, "the block is a dynamic one");
+        let expected: Vec<u8> = (0..200).map(|index: usize| b"abcdefgh"[(index * index + index / 7) % 8]).collect();
+        assert_eq!(inflate(&compressed), Ok(expected));
+    }
+
+    #[test]
+    fn broken_streams_are_errors() {
+        assert_eq!(inflate(&[0x01, 0x05, 0x00, 0xfa, 0xfe]), Err("stored block length doesn't match its complement".to_string()));
+        assert_eq!(inflate(&[0x07]), Err("invalid block type".to_string()));
+        assert_eq!(inflate(&[0xcb, 0x48]), Err("compressed data ends early".to_string()));
+        assert_eq!(inflate(&[]), Err("compressed data ends early".to_string()));
+    }
+}
@@ -0,0 +1,222 @@
+//! The Midway 8080 board Space Invaders runs on.
+
+use std::ops::Range;
+
+use cabinet::Cabinet;
+use cpu::{MemoryMap, State};
+use ports::{Device, Ports};
+use shifter::ShiftRegister;
+use sound::SoundBoard;
+use video::{self, Frame};
+use watchdog::Watchdog;
+
+/// The four 2K ROMs, H, G, F and E from the bottom.
+pub const ROM: Range<usize> = 0x0000..0x2000;
+/// 1K of work RAM from 2000, and the video RAM above it.
+pub const RAM: Range<usize> = 0x2000..0x4000;
+pub const VIDEO_RAM: Range<usize> = 0x2400..0x4000;
+
+/// The CPU runs at 2MHz and the screen is redrawn 60 times a second.
+pub const CLOCK: u64 = 2_000_000;
+pub const CYCLES_PER_FRAME: u64 = CLOCK / 60;
+
+/// The RSTs the board asks for when the beam reaches the middle of the screen, and the
+/// bottom, where vertical blank starts.
+pub const MID_SCREEN: u8 = 1;
+pub const VBLANK: u8 = 2;
+
+/// Writing to port 6 keeps the watchdog from resetting the CPU, which it does after 255
+/// frames without.
+pub const WATCHDOG_PORT: u8 = 6;
+pub const WATCHDOG_TIMEOUT: u64 = 255 * CYCLES_PER_FRAME;
+
+/// Only the low 14 address lines are decoded, so the ROM and RAM repeat every 16K through
+/// the rest of the address space. Writes to ROM go nowhere.
+pub const MEMORY_MAP: MemoryMap = MemoryMap { mask: RAM.end as u16 - 1, rom_end: ROM.end as u16 };
+
+/// The board coming out of reset with `memory` in place, which starts running from 0.
+pub fn boot(memory: &[u8], entry: Option<u16>) -> State {
+    let mut state = State::new(memory, entry.unwrap_or(ROM.start as u16));
+    state.map = MEMORY_MAP;
+    state
+}
+
+/// Raises the board's two video interrupts each frame. They are timed by the CPU's cycle
+/// count rather than the clock on the wall, so a program runs the same way every time.
+#[derive(Default)]
+pub struct FrameTimer {
+    /// How many half frames have had their interrupt taken since reset.
+    signalled: u64,
+    /// How many half frames had gone by when last asked.
+    halves: u64,
+}
+
+impl FrameTimer {
+    // How many half frames have gone by after `cycles`.
+    fn halves(cycles: u64) -> u64 {
+        cycles / CYCLES_PER_FRAME * 2 + (cycles % CYCLES_PER_FRAME >= CYCLES_PER_FRAME / 2) as u64
+    }
+
+    fn restart(halves: u64) -> u8 {
+        if halves % 2 == 1 { MID_SCREEN } else { VBLANK }
+    }
+}
+
+impl Device for FrameTimer {
+    fn name(&self) -> &'static str {
+        "frame timer"
+    }
+
+    fn input(&mut self, _port: u8) -> Option<u8> {
+        None
+    }
+
+    fn output(&mut self, _port: u8, _value: u8) {}
+
+    fn interrupt(&mut self, cycles: u64) -> Option<u8> {
+        self.halves = FrameTimer::halves(cycles);
+        if self.halves <= self.signalled {
+            return None
+        }
+        // The request is held until the CPU takes it, as MAME's HOLD_LINE does, so a program
+        // that has interrupts off for a while gets the latest one when it turns them back on.
+        Some(FrameTimer::restart(self.halves))
+    }
+
+    fn acknowledge(&mut self, restart: u8) {
+        if self.halves > self.signalled && restart == FrameTimer::restart(self.halves) {
+            self.signalled = self.halves;
+        }
+    }
+
+    // A request being held is let go; the next is at the next half frame.
+    fn reset_line(&mut self, cycles: u64) {
+        self.signalled = self.signalled.max(FrameTimer::halves(cycles));
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.signalled.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.len() != 8 {
+            return Err(format!("expected 8 bytes, got {}", bytes.len()))
+        }
+        let mut signalled = [0; 8];
+        signalled.copy_from_slice(bytes);
+        self.signalled = u64::from_le_bytes(signalled);
+        Ok(())
+    }
+}
+
+/// The devices on the board's ports.
+pub fn ports() -> Ports {
+    Ports { devices: vec![
+        Box::new(Cabinet::default()),
+        Box::new(ShiftRegister::default()),
+        Box::new(FrameTimer::default()),
+        Box::new(SoundBoard::default()),
+        Box::new(Watchdog::new(WATCHDOG_PORT, Some(WATCHDOG_TIMEOUT))),
+    ] }
+}
+
+/// What the screen is showing, through the cabinet as `options` say.
+pub fn screen(state: &State, ports: &mut Ports, options: video::Options) -> Frame {
+    let mut frame = video::render(&state.memory[VIDEO_RAM]);
+    if options.cocktail && ports.find::<SoundBoard>().is_some_and(|board| board.flipped()) {
+        frame.flip();
+    }
+    // The overlay is on the glass, so it stays put when the picture turns round.
+    if options.overlay {
+        frame.overlay();
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use machine::{Machine, Stop};
+
+    const HALF: u64 = CYCLES_PER_FRAME / 2;
+
+    #[test]
+    fn the_timer_restarts_at_mid_screen_then_vblank() {
+        // LXI SP,2400H / EI / JMP $, with the handlers EI / JMP 4 at 8 and 10H.
+        let mut memory = vec![0x31, 0x00, 0x24, 0xfb, 0xc3, 0x04, 0x00, 0x00];
+        memory.extend_from_slice(&[0xfb, 0xc3, 0x04, 0x00, 0, 0, 0, 0, 0xfb, 0xc3, 0x04, 0x00]);
+        memory.resize(0x10000, 0);
+        let state = boot(&memory, None);
+        let mut ports = ports();
+        let breakpoints = [0x08, 0x10].iter().cloned().collect::<BTreeSet<u16>>();
+
+        let (state, stop) = Machine::Invaders.run(state, &mut ports, None, &breakpoints, &mut vec![]);
+        assert_eq!(stop, Stop::Breakpoint(0x08));
+        assert!(state.cycles >= HALF && state.cycles < HALF + 10 + 11, "RST 1 at {}", state.cycles);
+        let (state, stop) = Machine::Invaders.run(state, &mut ports, None, &breakpoints, &mut vec![]);
+        assert_eq!(stop, Stop::Breakpoint(0x10));
+        assert!(state.cycles >= CYCLES_PER_FRAME && state.cycles < CYCLES_PER_FRAME + 10 + 11, "RST 2 at {}", state.cycles);
+    }
+
+    #[test]
+    fn requests_are_held_until_taken() {
+        let mut timer = FrameTimer::default();
+        assert_eq!(timer.interrupt(HALF - 1), None);
+        assert_eq!(timer.interrupt(HALF), Some(MID_SCREEN));
+        assert_eq!(timer.interrupt(HALF + 100), Some(MID_SCREEN), "interrupts were off");
+        timer.acknowledge(MID_SCREEN);
+        assert_eq!(timer.interrupt(HALF + 200), None);
+
+        // Left long enough, the request is the latest one.
+        assert_eq!(timer.interrupt(CYCLES_PER_FRAME), Some(VBLANK));
+        assert_eq!(timer.interrupt(CYCLES_PER_FRAME + HALF), Some(MID_SCREEN));
+        timer.acknowledge(VBLANK);
+        assert_eq!(timer.interrupt(CYCLES_PER_FRAME + HALF + 1), Some(MID_SCREEN), "a stale acknowledgement");
+        timer.acknowledge(MID_SCREEN);
+        assert_eq!(timer.interrupt(2 * CYCLES_PER_FRAME - 1), None);
+    }
+
+    #[test]
+    fn a_reset_lets_go_of_the_request() {
+        let mut timer = FrameTimer::default();
+        assert_eq!(timer.interrupt(HALF), Some(MID_SCREEN));
+        timer.reset_line(HALF + 10);
+        assert_eq!(timer.interrupt(HALF + 20), None);
+        assert_eq!(timer.interrupt(CYCLES_PER_FRAME), Some(VBLANK));
+    }
+
+    #[test]
+    fn restored_timers_carry_on_the_same() {
+        let mut timer = FrameTimer::default();
+        timer.interrupt(CYCLES_PER_FRAME);
+        timer.acknowledge(VBLANK);
+        let mut restored = FrameTimer::default();
+        restored.restore(&timer.save()).unwrap();
+
+        for &cycles in &[CYCLES_PER_FRAME + 1, CYCLES_PER_FRAME + HALF, 3 * CYCLES_PER_FRAME] {
+            assert_eq!(restored.interrupt(cycles), timer.interrupt(cycles), "at {}", cycles);
+        }
+        assert_eq!(restored.restore(&[0; 4]), Err("expected 8 bytes, got 4".to_string()));
+    }
+
+    #[test]
+    fn the_cocktail_screen_turns_under_the_overlay() {
+        // One lit pixel on the top row, under nothing but the glass, and under the green
+        // strip along the bottom once it is turned round.
+        let mut memory = vec![0; 0x10000];
+        memory[VIDEO_RAM.start + 100 * 32 + 31] = 0x80;
+        let state = boot(&memory, None);
+        let mut ports = ports();
+        let options = video::Options { overlay: true, cocktail: true };
+
+        let frame = screen(&state, &mut ports, options);
+        assert_eq!(frame.pixels[100], [0xff, 0xff, 0xff], "not flipped until the program asks");
+        ports.output(5, 0x20);
+        let frame = screen(&state, &mut ports, options);
+        assert_eq!(frame.pixels[100], [0; 3]);
+        assert_eq!(frame.pixels[video::HEIGHT * video::WIDTH - 1 - 100], [0x20, 0xff, 0x20], "tinted where it ends up");
+        let frame = screen(&state, &mut ports, video::Options { overlay: false, cocktail: false });
+        assert_eq!(frame.pixels[100], [0xff, 0xff, 0xff], "the upright cabinet doesn't turn");
+    }
+}
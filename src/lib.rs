@@ -0,0 +1,31 @@
+//! An Intel 8080 emulator and the tools around it: an assembler and linker, a
+//! disassembler, image loaders and the Space Invaders machine.
+
+pub mod assembler;
+pub mod cabinet;
+pub mod cfg;
+pub mod checksum;
+pub mod cpu;
+pub mod disassembler;
+pub mod expression;
+pub mod html;
+pub mod inflate;
+pub mod invaders;
+pub mod linker;
+pub mod loader;
+pub mod machine;
+pub mod mixer;
+pub mod opcodes;
+pub mod ports;
+pub mod rel;
+pub mod romset;
+pub mod savestate;
+pub mod shifter;
+pub mod sound;
+pub mod symbols;
+pub mod synth;
+pub mod values;
+pub mod video;
+pub mod watchdog;
+pub mod wav;
+pub mod zip;
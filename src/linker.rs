@@ -0,0 +1,310 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rel::{Address, Chain, Module, Segment};
+
+/// Where one module's segments ended up.
+pub struct Placed {
+    pub name: String,
+    pub code: u16,
+    pub code_size: u16,
+    pub data: u16,
+    pub data_size: u16,
+}
+
+impl Placed {
+    fn base(&self, segment: Segment) -> u16 {
+        match segment {
+            Segment::Absolute => 0,
+            Segment::Code => self.code,
+            Segment::Data => self.data,
+        }
+    }
+
+    fn resolve(&self, address: Address) -> u16 {
+        self.base(address.segment).wrapping_add(address.offset)
+    }
+}
+
+pub struct Link {
+    pub modules: Vec<Placed>,
+    /// Every public symbol and the module that defined it.
+    pub symbols: BTreeMap<String, (u16, String)>,
+    /// The linked program, starting at the lowest address any module wrote to.
+    pub start: u16,
+    pub image: Vec<u8>,
+    pub entry: Option<u16>,
+}
+
+impl Link {
+    /// Where each module was put, followed by the public symbols sorted by name and by value.
+    pub fn map(&self) -> String {
+        let mut map = String::from("Module    Code       Data\n");
+        for placed in &self.modules {
+            let range = |base: u16, size: u16| if size == 0 {
+                String::from("-")
+            } else {
+                format!("{:04x}-{:04x}", base, base.wrapping_add(size - 1))
+            };
+            map.push_str(&format!("{:<8}  {:<9}  {}\n", placed.name, range(placed.code, placed.code_size), range(placed.data, placed.data_size)));
+        }
+        if !self.image.is_empty() {
+            map.push_str(&format!("\nImage: {:04x}-{:04x}\n", self.start, self.start as usize + self.image.len() - 1));
+        }
+        if let Some(entry) = self.entry {
+            map.push_str(&format!("Entry point: {:04x}\n", entry));
+        }
+
+        map.push_str("\nSymbols by name:\n");
+        for (name, &(value, ref module)) in &self.symbols {
+            map.push_str(&format!("{:04x}  {:<8}  {}\n", value, name, module));
+        }
+        map.push_str("\nSymbols by address:\n");
+        let mut by_value: Vec<(&u16, &String)> = self.symbols.iter().map(|(name, (value, _))| (value, name)).collect();
+        by_value.sort();
+        for (value, name) in by_value {
+            map.push_str(&format!("{:04x}  {}\n", value, name));
+        }
+        map
+    }
+}
+
+/// Which library modules are needed to define the externals `modules` refer to, and the
+/// ones those refer to in turn. Only they get linked, as LINK-80 does with a library search.
+fn search(modules: &[Module], libraries: &[Module]) -> Vec<Module> {
+    let mut chosen: Vec<Module> = vec![];
+    let mut used = BTreeSet::new();
+    loop {
+        let all = || modules.iter().chain(chosen.iter());
+        let defined: BTreeSet<&String> = all().flat_map(|module| module.publics.keys()).collect();
+        let undefined: BTreeSet<&String> = all()
+            .flat_map(|module| module.chains.iter())
+            .filter_map(|(_, chain)| match *chain { Chain::External(ref name) => Some(name), _ => None })
+            .filter(|name| !defined.contains(name))
+            .collect();
+
+        let found = libraries.iter().enumerate()
+            .find(|(index, library)| !used.contains(index) && library.publics.keys().any(|name| undefined.contains(name)));
+        match found {
+            Some((index, library)) => {
+                used.insert(index);
+                chosen.push(library.clone());
+            },
+            None => return chosen,
+        }
+    }
+}
+
+/// Links `modules`, plus whatever they need from `libraries`, into one absolute program.
+/// Each module's code follows the one before from `origin`; data areas follow all the code
+/// unless `data` says where they go.
+pub fn link(modules: &[Module], libraries: &[Module], origin: u16, data: Option<u16>) -> Result<Link, String> {
+    let mut modules = modules.to_vec();
+    modules.extend(search(&modules, libraries));
+
+    // Lay out every module's segments.
+    let mut placed = vec![];
+    let mut code = origin as usize;
+    for module in &modules {
+        placed.push(Placed { name: module.name.clone(), code: code as u16, code_size: module.code_size, data: 0, data_size: module.data_size });
+        code += module.code_size as usize;
+    }
+    let mut data = data.map_or(code, |data| data as usize);
+    for placed in &mut placed {
+        placed.data = data as u16;
+        data += placed.data_size as usize;
+    }
+    if code > 0x10000 || data > 0x10000 {
+        return Err("the linked program doesn't fit in 64K".to_string())
+    }
+
+    let mut symbols = BTreeMap::new();
+    for (module, placed) in modules.iter().zip(&placed) {
+        for (name, &address) in &module.publics {
+            if let Some((_, other)) = symbols.insert(name.clone(), (placed.resolve(address), module.name.clone())) {
+                return Err(format!("{} is defined in both {} and {}", name, other, module.name))
+            }
+        }
+    }
+
+    let mut memory = vec![0u8; 0x10000];
+    let mut owner: Vec<Option<usize>> = vec![None; 0x10000];
+    let mut undefined = BTreeSet::new();
+    for (index, (module, placed)) in modules.iter().zip(&placed).enumerate() {
+        for (&address, &byte) in &module.bytes {
+            let at = placed.resolve(address) as usize;
+            if let Some(other) = owner[at] {
+                return Err(format!("{} and {} both load ${:04x}", modules[other].name, module.name, at))
+            }
+            owner[at] = Some(index);
+            memory[at] = byte;
+        }
+
+        let word = |memory: &[u8], at: u16| (memory[at as usize] as u16) | (memory[at.wrapping_add(1) as usize] as u16) << 8;
+        let store = |memory: &mut [u8], at: u16, value: u16| {
+            memory[at as usize] = value as u8;
+            memory[at.wrapping_add(1) as usize] = (value >> 8) as u8;
+        };
+
+        for (&address, &segment) in &module.relocations {
+            let at = placed.resolve(address);
+            let value = word(&memory, at).wrapping_add(placed.base(segment));
+            store(&mut memory, at, value);
+        }
+
+        for &(head, ref chain) in &module.chains {
+            let value = match *chain {
+                Chain::External(ref name) => match symbols.get(name) {
+                    Some(&(value, _)) => value,
+                    None => {
+                        undefined.insert(name.clone());
+                        continue
+                    },
+                },
+                Chain::Location(address) => placed.resolve(address),
+            };
+
+            // The chain runs back through the words that refer to the value, each holding
+            // the address of the one before, until absolute zero.
+            let mut link = head;
+            let mut steps = 0;
+            while link != Address::absolute(0) {
+                steps += 1;
+                if steps > 0x10000 {
+                    return Err(format!("{}: chain at {:04x} never ends", module.name, head.offset))
+                }
+                let raw = (module.bytes.get(&link).cloned().unwrap_or(0) as u16) | (module.bytes.get(&link.next(1)).cloned().unwrap_or(0) as u16) << 8;
+                let segment = module.relocations.get(&link).cloned().unwrap_or(Segment::Absolute);
+                let offset = module.offsets.get(&link).cloned().unwrap_or(0);
+                store(&mut memory, placed.resolve(link), value.wrapping_add(offset as u16));
+                link = Address { segment, offset: raw };
+            }
+        }
+    }
+    if !undefined.is_empty() {
+        let undefined: Vec<String> = undefined.into_iter().collect();
+        return Err(format!("undefined symbols: {}", undefined.join(", ")))
+    }
+
+    let start = owner.iter().position(Option::is_some).unwrap_or(origin as usize);
+    let end = owner.iter().rposition(Option::is_some).map_or(start, |end| end + 1);
+    let entry = modules.iter().zip(&placed).find_map(|(module, placed)| module.entry.map(|entry| placed.resolve(entry)));
+
+    Ok(Link {
+        modules: placed,
+        symbols,
+        start: start as u16,
+        image: memory[start..end].to_vec(),
+        entry,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::{self, Dialect};
+
+    // Calls PRINT twice, the second time three bytes in, so the references to it chain
+    // together and one has an offset.
+    const MAIN: &str = "
+        NAME    MAIN
+        EXTRN   PRINT
+        CSEG
+START:  LXI     H,MSG
+        CALL    PRINT
+        CALL    PRINT+3
+        JMP     START
+        DSEG
+MSG:    DB      'HI',0
+        END     START
+";
+
+    const PRINT: &str = "
+        NAME    PRINT
+        PUBLIC  PRINT
+        CSEG
+PRINT:  NOP
+        NOP
+        NOP
+        RET
+";
+
+    fn module(source: &str) -> Module {
+        assembler::assemble("test", source, Dialect::M80).unwrap().module()
+    }
+
+    #[test]
+    fn externals_are_resolved_through_their_chain() {
+        let link = link(&[module(MAIN), module(PRINT)], &[], 0x100, None).unwrap();
+        assert_eq!(link.start, 0x100);
+        assert_eq!(link.image, [
+            0x21, 0x10, 0x01, 0xcd, 0x0c, 0x01, 0xcd, 0x0f, 0x01, 0xc3, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0xc9,
+            b'H', b'I', 0,
+        ]);
+        assert_eq!(link.entry, Some(0x100));
+        assert_eq!(link.symbols.get("PRINT"), Some(&(0x10c, "PRINT".to_string())));
+    }
+
+    #[test]
+    fn data_can_go_elsewhere() {
+        let link = link(&[module(MAIN), module(PRINT)], &[], 0, Some(0x4000)).unwrap();
+        assert_eq!(&link.image[..3], [0x21, 0x00, 0x40]);
+        assert_eq!(link.image.len(), 0x4003);
+        assert_eq!(&link.image[0x4000..], b"HI\0");
+    }
+
+    #[test]
+    fn only_the_library_modules_needed_are_linked() {
+        let unused = module("\tNAME UNUSED\n\tPUBLIC OTHER\n\tCSEG\nOTHER:\tRET\n");
+        let link = link(&[module(MAIN)], &[unused, module(PRINT)], 0x100, None).unwrap();
+        let names: Vec<&str> = link.modules.iter().map(|placed| placed.name.as_str()).collect();
+        assert_eq!(names, ["MAIN", "PRINT"]);
+        assert_eq!(link.image[4..6], [0x0c, 0x01]);
+    }
+
+    #[test]
+    fn location_chains_are_filled_in_with_the_address() {
+        let mut module = Module { name: "FWD".to_string(), code_size: 6, ..Module::default() };
+        // Two words waiting for the address of code offset 5, the second pointing back to
+        // the first.
+        let code = |offset| Address { segment: Segment::Code, offset };
+        for (offset, &byte) in [0xc3, 0x00, 0x00, 0xc3, 0x01, 0x00].iter().enumerate() {
+            module.bytes.insert(code(offset as u16), byte);
+        }
+        module.relocations.insert(code(4), Segment::Code);
+        module.chains.push((code(4), Chain::Location(code(5))));
+        let link = link(&[module], &[], 0x200, None).unwrap();
+        assert_eq!(link.image, [0xc3, 0x05, 0x02, 0xc3, 0x05, 0x02]);
+    }
+
+    #[test]
+    fn undefined_symbols_are_listed() {
+        assert_eq!(link(&[module(MAIN)], &[], 0, None).err(), Some("undefined symbols: PRINT".to_string()));
+    }
+
+    #[test]
+    fn a_symbol_defined_twice_is_an_error() {
+        assert_eq!(link(&[module(PRINT), module(PRINT)], &[], 0, None).err(), Some("PRINT is defined in both PRINT and PRINT".to_string()));
+    }
+
+    #[test]
+    fn absolute_bytes_may_not_overlap() {
+        let first = module("\tNAME FIRST\n\tASEG\n\tORG 10H\n\tNOP\n");
+        let second = module("\tNAME SECOND\n\tASEG\n\tORG 10H\n\tHLT\n");
+        assert_eq!(link(&[first, second], &[], 0x100, None).err(), Some("FIRST and SECOND both load $0010".to_string()));
+    }
+
+    #[test]
+    fn programs_must_fit_in_memory() {
+        let big = Module { name: "BIG".to_string(), code_size: 0x8000, ..Module::default() };
+        assert_eq!(link(&[big.clone(), big], &[], 0x100, None).err(), Some("the linked program doesn't fit in 64K".to_string()));
+    }
+
+    #[test]
+    fn the_map_lists_modules_and_symbols() {
+        let map = link(&[module(MAIN), module(PRINT)], &[], 0x100, None).unwrap().map();
+        assert!(map.starts_with("Module    Code       Data\nMAIN      0100-010b  0110-0112\nPRINT     010c-010f  -\n"), "{}", map);
+        assert!(map.contains("\nImage: 0100-0112\nEntry point: 0100\n"), "{}", map);
+        assert!(map.contains("\nSymbols by name:\n010c  PRINT     PRINT\n"), "{}", map);
+    }
+}
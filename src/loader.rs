@@ -0,0 +1,431 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::ops::Range;
+
+const MEMORY_SIZE: usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Raw,
+    IntelHex,
+    SRecord,
+}
+
+impl Format {
+    pub fn named(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "raw" | "bin" => Some(Format::Raw),
+            "hex" | "ihex" => Some(Format::IntelHex),
+            "srec" | "s19" => Some(Format::SRecord),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the contents: text files of `:` or `S` records are HEX and
+    /// S-records, anything else is a raw image.
+    pub fn detect(bytes: &[u8]) -> Format {
+        let text = bytes.iter().skip_while(|byte| byte.is_ascii_whitespace());
+        let first: Vec<u8> = text.take(2).cloned().collect();
+        let printable = bytes.iter().all(|&byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace());
+        match first.as_slice() {
+            [b':', _] if printable => Format::IntelHex,
+            [b'S', digit] if printable && digit.is_ascii_digit() => Format::SRecord,
+            _ => Format::Raw,
+        }
+    }
+}
+
+/// Bytes to be put at an address.
+pub struct Block {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// What a file holds, before it is put in memory.
+pub struct Image {
+    pub blocks: Vec<Block>,
+    /// The start address the file gives, if any.
+    pub entry: Option<u16>,
+}
+
+fn hex_bytes(text: &str, line: usize) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("line {}: invalid hex digits", line))
+    }
+    Ok((0..text.len()).step_by(2).map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap()).collect())
+}
+
+fn intel_hex(text: &str, base: usize) -> Result<Image, String> {
+    let mut image = Image { blocks: vec![], entry: None };
+    // Extended address records move every later data record by this much.
+    let mut offset = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        let record = line.strip_prefix(':').ok_or_else(|| format!("line {}: record doesn't start with ':'", number))?;
+        let record = hex_bytes(record, number)?;
+        if record.len() < 5 || record.len() != record[0] as usize + 5 {
+            return Err(format!("line {}: record length doesn't match its contents", number))
+        }
+        if record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(format!("line {}: checksum mismatch", number))
+        }
+
+        let address = (record[1] as usize) << 8 | record[2] as usize;
+        let data = &record[4..record.len() - 1];
+        let word = || (data[0] as usize) << 8 | data[1] as usize;
+        match record[3] {
+            0x00 => image.blocks.push(Block { address: base + offset + address, bytes: data.to_vec() }),
+            0x01 => return Ok(image),
+            0x02 if data.len() == 2 => offset = word() << 4,
+            0x04 if data.len() == 2 => offset = word() << 16,
+            0x03 if data.len() == 4 => image.entry = Some(((data[2] as u16) << 8 | data[3] as u16).wrapping_add(((data[0] as u16) << 8 | data[1] as u16) << 4)),
+            0x05 if data.len() == 4 => image.entry = Some((data[2] as u16) << 8 | data[3] as u16),
+            kind => return Err(format!("line {}: unsupported record type {:02x}", number, kind)),
+        }
+    }
+
+    Ok(image)
+}
+
+fn s_record(text: &str, base: usize) -> Result<Image, String> {
+    let mut image = Image { blocks: vec![], entry: None };
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        if line.len() < 4 || !line.starts_with('S') {
+            return Err(format!("line {}: record doesn't start with 'S'", number))
+        }
+        let kind = line.as_bytes()[1];
+        let record = hex_bytes(&line[2..], number)?;
+        if record.is_empty() || record.len() != record[0] as usize + 1 {
+            return Err(format!("line {}: record length doesn't match its contents", number))
+        }
+        if record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0xff {
+            return Err(format!("line {}: checksum mismatch", number))
+        }
+
+        let address_size = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err(format!("line {}: unsupported record type S{}", number, kind as char)),
+        };
+        if record.len() < address_size + 2 {
+            return Err(format!("line {}: record too short", number))
+        }
+        let address = record[1..1 + address_size].iter().fold(0usize, |address, &byte| address << 8 | byte as usize);
+        let data = &record[1 + address_size..record.len() - 1];
+
+        match kind {
+            b'1' | b'2' | b'3' => image.blocks.push(Block { address: base + address, bytes: data.to_vec() }),
+            b'7' | b'8' | b'9' => {
+                if address >= MEMORY_SIZE {
+                    return Err(format!("line {}: start address ${:x} is beyond 64K", number, address))
+                }
+                image.entry = Some(address as u16);
+            },
+            // Headers and record counts.
+            _ => {},
+        }
+    }
+
+    Ok(image)
+}
+
+/// Reads an image in `format`, putting it `base` bytes further up memory than it says.
+/// Raw images have no addresses of their own, so they start at `base`.
+pub fn parse(bytes: &[u8], format: Format, base: u16) -> Result<Image, String> {
+    let base = base as usize;
+    match format {
+        Format::Raw => Ok(Image { blocks: vec![Block { address: base, bytes: bytes.to_vec() }], entry: None }),
+        Format::IntelHex => intel_hex(&String::from_utf8_lossy(bytes), base),
+        Format::SRecord => s_record(&String::from_utf8_lossy(bytes), base),
+    }
+}
+
+/// The most data bytes an Intel HEX record can hold.
+pub const MAX_RECORD_LENGTH: usize = 255;
+
+fn hex_record(kind: u8, address: u16, data: &[u8]) -> String {
+    let mut record = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+    record.extend_from_slice(data);
+    let checksum = record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)).wrapping_neg();
+    record.push(checksum);
+    let digits: Vec<String> = record.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}\n", digits.concat())
+}
+
+/// Writes `blocks` as Intel HEX, with at most `record_length` bytes in each data record,
+/// and a start address record if `entry` is given. The record length must be 1 to
+/// `MAX_RECORD_LENGTH`.
+pub fn write_intel_hex(blocks: &[Block], record_length: usize, entry: Option<u16>) -> Result<String, String> {
+    if !(1..=MAX_RECORD_LENGTH).contains(&record_length) {
+        return Err(format!("record length must be 1 to {}: {}", MAX_RECORD_LENGTH, record_length))
+    }
+    let mut hex = String::new();
+    let mut blocks: Vec<&Block> = blocks.iter().filter(|block| !block.bytes.is_empty()).collect();
+    blocks.sort_by_key(|block| block.address);
+
+    // Blocks that follow straight on from each other are written as one run of records, so
+    // that the records only come up short where there is a gap.
+    let mut runs: Vec<(usize, Vec<u8>)> = vec![];
+    for block in blocks {
+        match runs.last_mut() {
+            Some((address, bytes)) if *address + bytes.len() == block.address => bytes.extend_from_slice(&block.bytes),
+            _ => runs.push((block.address, block.bytes.clone())),
+        }
+    }
+
+    for (address, bytes) in runs {
+        for (index, data) in bytes.chunks(record_length).enumerate() {
+            hex.push_str(&hex_record(0x00, (address + index * record_length) as u16, data));
+        }
+    }
+    if let Some(entry) = entry {
+        hex.push_str(&hex_record(0x03, 0, &[0, 0, (entry >> 8) as u8, entry as u8]));
+    }
+    hex.push_str(&hex_record(0x01, 0, &[]));
+    Ok(hex)
+}
+
+/// The 8080's address space, keeping track of which parts have been loaded from where so
+/// that images that would overwrite each other are caught.
+pub struct Memory {
+    pub bytes: Vec<u8>,
+    pub loaded: Vec<(Range<usize>, String)>,
+    /// The start address of the first image that gave one.
+    pub entry: Option<u16>,
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory { bytes: vec![0; MEMORY_SIZE], loaded: vec![], entry: None }
+    }
+}
+
+impl Memory {
+    /// Puts `image` in memory, named `name` in error messages, and returns the ranges it
+    /// covers.
+    pub fn load(&mut self, name: &str, image: Image) -> Result<Vec<Range<usize>>, String> {
+        let mut ranges: Vec<Range<usize>> = vec![];
+        for block in image.blocks.into_iter().filter(|block| !block.bytes.is_empty()) {
+            let range = block.address..block.address + block.bytes.len();
+            if range.end > MEMORY_SIZE {
+                return Err(format!("{} doesn't fit in 64K: it reaches ${:x}", name, range.end - 1))
+            }
+            if let Some((loaded, other)) = self.loaded.iter().find(|(loaded, _)| loaded.start < range.end && range.start < loaded.end) {
+                return Err(format!("{} overlaps {} at ${:04x}", name, other, loaded.start.max(range.start)))
+            }
+
+            self.bytes[range.clone()].copy_from_slice(&block.bytes);
+            self.loaded.push((range.clone(), name.to_string()));
+            // Records are usually in order, so neighbouring ones are merged as they come.
+            match ranges.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => ranges.push(range),
+            }
+        }
+        self.entry = self.entry.or(image.entry);
+        Ok(ranges)
+    }
+
+    /// Reads, parses and loads the file at `path`, detecting its format unless given.
+    pub fn load_file(&mut self, path: &str, format: Option<Format>, base: u16) -> Result<Vec<Range<usize>>, String> {
+        let mut bytes = vec![];
+        File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)).map_err(|error| format!("{}: {}", path, error))?;
+        let format = format.unwrap_or_else(|| Format::detect(&bytes));
+        let image = parse(&bytes, format, base).map_err(|message| format!("{}: {}", path, message))?;
+        self.load(path, image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(image: &Image) -> Vec<(usize, Vec<u8>)> {
+        image.blocks.iter().map(|block| (block.address, block.bytes.clone())).collect()
+    }
+
+    fn hex(records: &[(u8, u16, &[u8])]) -> Vec<u8> {
+        records.iter().map(|&(kind, address, data)| hex_record(kind, address, data)).collect::<String>().into_bytes()
+    }
+
+    // An S-record with its count and checksum worked out.
+    fn s(kind: u8, address: &[u8], data: &[u8]) -> String {
+        let mut record = vec![(address.len() + data.len() + 1) as u8];
+        record.extend_from_slice(address);
+        record.extend_from_slice(data);
+        let checksum = !record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        record.push(checksum);
+        let digits: Vec<String> = record.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("S{}{}\n", kind, digits.concat())
+    }
+
+    #[test]
+    fn formats_are_detected_from_the_contents() {
+        assert_eq!(Format::detect(b"\n:00000001FF\n"), Format::IntelHex);
+        assert_eq!(Format::detect(b"S9030000FC\n"), Format::SRecord);
+        assert_eq!(Format::detect(b":\x00\x01"), Format::Raw);
+        assert_eq!(Format::detect(&[0xc3, 0x00, 0x01]), Format::Raw);
+    }
+
+    #[test]
+    fn raw_images_start_at_the_base() {
+        let image = parse(&[1, 2, 3], Format::Raw, 0x100).unwrap();
+        assert_eq!(blocks(&image), [(0x100, vec![1, 2, 3])]);
+        assert_eq!(image.entry, None);
+    }
+
+    #[test]
+    fn hex_data_records_are_moved_by_the_base() {
+        let image = parse(&hex(&[(0x00, 0x0010, &[1, 2]), (0x00, 0x0100, &[3]), (0x01, 0, &[])]), Format::IntelHex, 0x1000).unwrap();
+        assert_eq!(blocks(&image), [(0x1010, vec![1, 2]), (0x1100, vec![3])]);
+    }
+
+    #[test]
+    fn hex_records_after_the_end_are_ignored() {
+        let image = parse(&hex(&[(0x00, 0, &[1]), (0x01, 0, &[]), (0x00, 1, &[2])]), Format::IntelHex, 0).unwrap();
+        assert_eq!(blocks(&image), [(0, vec![1])]);
+    }
+
+    #[test]
+    fn extended_addresses_move_later_records() {
+        let image = parse(&hex(&[(0x02, 0, &[0x10, 0x00]), (0x00, 0x0020, &[1]), (0x04, 0, &[0x00, 0x01]), (0x00, 0x0030, &[2])]), Format::IntelHex, 0).unwrap();
+        assert_eq!(blocks(&image), [(0x10020, vec![1]), (0x10030, vec![2])]);
+    }
+
+    #[test]
+    fn start_records_give_the_entry() {
+        let segment = parse(&hex(&[(0x03, 0, &[0x00, 0x10, 0x00, 0x20])]), Format::IntelHex, 0).unwrap();
+        assert_eq!(segment.entry, Some(0x0120));
+        let linear = parse(&hex(&[(0x05, 0, &[0x00, 0x00, 0x12, 0x34])]), Format::IntelHex, 0).unwrap();
+        assert_eq!(linear.entry, Some(0x1234));
+    }
+
+    #[test]
+    fn broken_hex_records_name_their_line() {
+        let error = |text: &str| parse(text.as_bytes(), Format::IntelHex, 0).err().unwrap();
+        assert_eq!(error(":0100000001FE\n:0100000001FF\n"), "line 2: checksum mismatch");
+        assert_eq!(error(":0200000001FD\n"), "line 1: record length doesn't match its contents");
+        assert_eq!(error("\n0100000001FE\n"), "line 2: record doesn't start with ':'");
+        assert_eq!(error(":01000000GGFE\n"), "line 1: invalid hex digits");
+        assert_eq!(error(":00000006FA\n"), "line 1: unsupported record type 06");
+    }
+
+    #[test]
+    fn s_records_of_every_address_size_load() {
+        let text = [s(0, &[0, 0], b"HDR"), s(1, &[0x01, 0x00], &[1]), s(2, &[0x00, 0x02, 0x00], &[2]), s(3, &[0, 0, 0x03, 0x00], &[3]), s(5, &[0, 3], &[])].concat();
+        let image = parse(text.as_bytes(), Format::SRecord, 0x10).unwrap();
+        assert_eq!(blocks(&image), [(0x110, vec![1]), (0x210, vec![2]), (0x310, vec![3])]);
+        assert_eq!(image.entry, None);
+    }
+
+    #[test]
+    fn s_record_terminators_give_the_entry() {
+        for (kind, address) in [(9, vec![0x12, 0x34]), (8, vec![0x00, 0x12, 0x34]), (7, vec![0, 0, 0x12, 0x34])] {
+            let image = parse(s(kind, &address, &[]).as_bytes(), Format::SRecord, 0).unwrap();
+            assert_eq!(image.entry, Some(0x1234), "S{}", kind);
+        }
+        assert_eq!(parse(s(8, &[1, 0, 0], &[]).as_bytes(), Format::SRecord, 0).err(), Some("line 1: start address $10000 is beyond 64K".to_string()));
+    }
+
+    #[test]
+    fn broken_s_records_name_their_line() {
+        let error = |text: &str| parse(text.as_bytes(), Format::SRecord, 0).err().unwrap();
+        assert_eq!(error("S10400000100\n"), "line 1: checksum mismatch");
+        assert_eq!(error("S10500000100\n"), "line 1: record length doesn't match its contents");
+        assert_eq!(error(&format!("{}X1\n", s(1, &[0, 0], &[1]))), "line 2: record doesn't start with 'S'");
+        assert_eq!(error(&s(4, &[0, 0], &[])), "line 1: unsupported record type S4");
+        assert_eq!(error(&s(3, &[0, 0], &[])), "line 1: record too short");
+    }
+
+    #[test]
+    fn loading_merges_neighbouring_records() {
+        let mut memory = Memory::default();
+        let image = parse(&hex(&[(0x00, 0x10, &[1, 2]), (0x00, 0x12, &[3]), (0x00, 0x20, &[4]), (0x05, 0, &[0, 0, 0, 0x10])]), Format::IntelHex, 0).unwrap();
+        assert_eq!(memory.load("a.hex", image), Ok(vec![0x10..0x13, 0x20..0x21]));
+        assert_eq!(memory.bytes[0x10..0x13], [1, 2, 3]);
+        assert_eq!(memory.entry, Some(0x10));
+    }
+
+    #[test]
+    fn images_may_not_overlap() {
+        let mut memory = Memory::default();
+        memory.load("first", parse(&[0; 0x10], Format::Raw, 0x100).unwrap()).unwrap();
+        assert_eq!(memory.load("second", parse(&[0; 0x10], Format::Raw, 0x108).unwrap()), Err("second overlaps first at $0108".to_string()));
+        assert!(memory.load("third", parse(&[0; 0x10], Format::Raw, 0x110).unwrap()).is_ok(), "touching isn't overlapping");
+    }
+
+    #[test]
+    fn images_must_fit_in_64k() {
+        let mut memory = Memory::default();
+        assert_eq!(memory.load("big", parse(&[0; 0x10], Format::Raw, 0xfff8).unwrap()), Err("big doesn't fit in 64K: it reaches $10007".to_string()));
+        let extended = parse(&hex(&[(0x04, 0, &[0x00, 0x01]), (0x00, 0, &[1])]), Format::IntelHex, 0).unwrap();
+        assert_eq!(memory.load("high.hex", extended), Err("high.hex doesn't fit in 64K: it reaches $10000".to_string()));
+    }
+
+    #[test]
+    fn the_first_entry_given_wins() {
+        let mut memory = Memory::default();
+        memory.load("a", parse(&hex(&[(0x05, 0, &[0, 0, 0, 1])]), Format::IntelHex, 0).unwrap()).unwrap();
+        memory.load("b", parse(&hex(&[(0x05, 0, &[0, 0, 0, 2])]), Format::IntelHex, 0).unwrap()).unwrap();
+        assert_eq!(memory.entry, Some(1));
+    }
+
+    #[test]
+    fn hex_records_are_split_at_the_record_length() {
+        let hex = write_intel_hex(&[Block { address: 0x100, bytes: vec![1, 2, 3, 4, 5] }], 2, None).unwrap();
+        assert_eq!(hex, ":020100000102FA\n:020102000304F4\n:0101040005F5\n:00000001FF\n");
+    }
+
+    #[test]
+    fn written_hex_parses_back_to_the_same_bytes() {
+        let written = [
+            Block { address: 0x2000, bytes: (0..40).collect() },
+            // Straight after the first, so it carries on the same run of records.
+            Block { address: 0x2028, bytes: vec![0xaa; 3] },
+            // After a gap.
+            Block { address: 0x3000, bytes: vec![0x55; 17] },
+            Block { address: 0x4000, bytes: vec![] },
+        ];
+        let hex = write_intel_hex(&written, 16, Some(0x2000)).unwrap();
+        let image = parse(hex.as_bytes(), Format::IntelHex, 0).unwrap();
+        assert_eq!(image.entry, Some(0x2000));
+
+        let mut memory = Memory::default();
+        assert_eq!(memory.load("dump", image), Ok(vec![0x2000..0x202b, 0x3000..0x3011]));
+        assert_eq!(memory.bytes[0x2000..0x2028], (0..40).collect::<Vec<u8>>()[..]);
+        assert_eq!(memory.bytes[0x2028..0x202b], [0xaa; 3]);
+        assert_eq!(memory.bytes[0x3000..0x3011], [0x55; 17]);
+
+        // Records only come up short at the end of a run.
+        let lengths: Vec<&str> = hex.lines().map(|line| &line[1..3]).collect();
+        assert_eq!(lengths, ["10", "10", "0B", "10", "01", "04", "00"]);
+    }
+
+    #[test]
+    fn blocks_are_written_in_address_order() {
+        let hex = write_intel_hex(&[Block { address: 0x10, bytes: vec![2] }, Block { address: 0, bytes: vec![1] }], MAX_RECORD_LENGTH, None).unwrap();
+        let image = parse(hex.as_bytes(), Format::IntelHex, 0).unwrap();
+        assert_eq!(blocks(&image), [(0, vec![1]), (0x10, vec![2])]);
+    }
+
+    #[test]
+    fn record_lengths_a_record_cant_hold_are_refused() {
+        let blocks = [Block { address: 0, bytes: vec![0; 300] }];
+        assert_eq!(write_intel_hex(&blocks, 0, None), Err("record length must be 1 to 255: 0".to_string()));
+        assert_eq!(write_intel_hex(&blocks, 256, None), Err("record length must be 1 to 255: 256".to_string()));
+        let hex = write_intel_hex(&blocks, MAX_RECORD_LENGTH, None).unwrap();
+        assert_eq!(hex.lines().map(|line| &line[1..3]).collect::<Vec<_>>(), ["FF", "2D", "00"]);
+    }
+}
@@ -0,0 +1,270 @@
+//! The systems a program can be run on: the CPU and whatever is wired up around it.
+
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use cpu::{self, MemoryMap, State};
+use invaders;
+use ports::Ports;
+use romset::{self, Rom};
+use video::{self, Frame};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Machine {
+    /// The Midway Space Invaders board.
+    Invaders,
+    /// 64K of RAM with just enough of CP/M's BDOS to run CPU test programs.
+    Cpm,
+}
+
+pub const MACHINES: [Machine; 2] = [Machine::Invaders, Machine::Cpm];
+
+// The CP/M machine's BDOS entry point and the top of its memory, which programs find from
+// the jump at the entry point.
+const BDOS: u16 = 0x0005;
+const BDOS_TOP: u16 = 0xfe00;
+
+/// Why a machine stopped running.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stop {
+    /// The program finished, as CP/M programs do by jumping to 0.
+    Exit,
+    /// The CPU couldn't carry on.
+    Fault(String),
+    CycleLimit,
+    Breakpoint(u16),
+    /// The watchdog wasn't kept happy and reset the CPU, which carries on from 0.
+    Watchdog,
+}
+
+impl Machine {
+    pub fn named(name: &str) -> Option<Machine> {
+        MACHINES.iter().cloned().find(|machine| machine.name() == name.to_lowercase())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Machine::Invaders => "invaders",
+            Machine::Cpm => "cpm",
+        }
+    }
+
+    /// The chips a ROM set for the machine should hold.
+    pub fn roms(&self) -> &'static [Rom] {
+        match *self {
+            Machine::Invaders => &romset::INVADERS,
+            Machine::Cpm => &[],
+        }
+    }
+
+    /// What to run when no image is given.
+    pub fn default_image(&self) -> Option<&'static str> {
+        match *self {
+            Machine::Invaders => Some("invaders.rom"),
+            Machine::Cpm => None,
+        }
+    }
+
+    /// Where raw images are loaded unless told otherwise.
+    pub fn origin(&self) -> u16 {
+        match *self {
+            Machine::Invaders => 0x0000,
+            Machine::Cpm => 0x0100,
+        }
+    }
+
+    pub fn memory_map(&self) -> MemoryMap {
+        match *self {
+            Machine::Invaders => invaders::MEMORY_MAP,
+            Machine::Cpm => cpu::FLAT,
+        }
+    }
+
+    /// What the machine's screen is showing, if it has one.
+    pub fn screen(&self, state: &State, ports: &mut Ports, options: video::Options) -> Option<Frame> {
+        match *self {
+            Machine::Invaders => Some(invaders::screen(state, ports, options)),
+            Machine::Cpm => None,
+        }
+    }
+
+    /// How many CPU cycles the machine takes to draw a frame, if it has a screen.
+    pub fn cycles_per_frame(&self) -> Option<u64> {
+        match *self {
+            Machine::Invaders => Some(invaders::CYCLES_PER_FRAME),
+            Machine::Cpm => None,
+        }
+    }
+
+    /// The devices on the machine's ports, as they come out of reset.
+    pub fn ports(&self) -> Ports {
+        match *self {
+            Machine::Invaders => invaders::ports(),
+            Machine::Cpm => Ports::default(),
+        }
+    }
+
+    /// A CPU about to run `memory`, from `entry` if it is given or where the machine starts.
+    pub fn boot(&self, memory: &[u8], entry: Option<u16>) -> State {
+        match *self {
+            Machine::Invaders => invaders::boot(memory, entry),
+            Machine::Cpm => {
+                let mut state = State::new(memory, entry.unwrap_or(self.origin()));
+                state.memory[BDOS as usize..BDOS as usize + 3].copy_from_slice(&[0xc3, BDOS_TOP as u8, (BDOS_TOP >> 8) as u8]);
+                // Returning from the program goes to 0, which ends it.
+                state.sp = BDOS_TOP - 2;
+                state.memory[state.sp as usize] = 0;
+                state.memory[state.sp as usize + 1] = 0;
+                state
+            },
+        }
+    }
+
+    /// Runs one instruction, or handles whatever the machine does instead at this address.
+    pub fn tick(&self, mut state: State, ports: &mut Ports, console: &mut dyn Write) -> (State, Option<Stop>) {
+        if *self == Machine::Cpm {
+            if state.pc == 0 {
+                return (state, Some(Stop::Exit))
+            }
+            if state.pc == BDOS {
+                let stop = bdos(&mut state, console);
+                return (state, stop)
+            }
+        }
+
+        if ports.reset(state.cycles) {
+            cpu::reset(&mut state);
+            ports.reset_all(state.cycles);
+            return (state, Some(Stop::Watchdog))
+        }
+        if let Some(restart) = ports.interrupt(state.cycles) {
+            if state.interrupts {
+                ports.acknowledge(restart);
+                cpu::interrupt(&mut state, restart);
+                return (state, None)
+            }
+        }
+
+        let state = cpu::step(state, ports);
+        let stop = state.stopped.clone().map(Stop::Fault);
+        (state, stop)
+    }
+
+    /// Runs until the program stops, reaches a breakpoint or `limit` cycles have passed.
+    pub fn run(&self, mut state: State, ports: &mut Ports, limit: Option<u64>, breakpoints: &BTreeSet<u16>, console: &mut dyn Write) -> (State, Stop) {
+        let mut first = true;
+        loop {
+            if limit.is_some_and(|limit| state.cycles >= limit) {
+                return (state, Stop::CycleLimit)
+            }
+            // A breakpoint where we start from has already been stopped at.
+            if !first && breakpoints.contains(&state.pc) {
+                let pc = state.pc;
+                return (state, Stop::Breakpoint(pc))
+            }
+            first = false;
+
+            let (next, stop) = self.tick(state, ports, console);
+            state = next;
+            if let Some(stop) = stop {
+                return (state, stop)
+            }
+        }
+    }
+}
+
+// The console calls test programs make, followed by a return to the caller.
+fn bdos(state: &mut State, console: &mut dyn Write) -> Option<Stop> {
+    let written = match state.c {
+        0 => return Some(Stop::Exit),
+        2 => console.write_all(&[state.e]),
+        9 => {
+            let text: Vec<u8> = state.memory[state.de() as usize..].iter().cloned().take_while(|&c| c != b'$').collect();
+            console.write_all(&text)
+        },
+        _ => Ok(()),
+    };
+    if let Err(error) = written.and_then(|_| console.flush()) {
+        return Some(Stop::Fault(error.to_string()))
+    }
+
+    let sp = state.sp as usize;
+    state.pc = (state.memory[(sp + 1) & 0xffff] as u16) << 8 | state.memory[sp] as u16;
+    state.sp = state.sp.wrapping_add(2);
+    state.cycles += 10;
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpm(program: &[u8]) -> State {
+        let mut memory = vec![0; 0x10000];
+        memory[0x100..0x100 + program.len()].copy_from_slice(program);
+        Machine::Cpm.boot(&memory, None)
+    }
+
+    #[test]
+    fn machines_are_found_by_name() {
+        assert_eq!(Machine::named("CPM"), Some(Machine::Cpm));
+        assert_eq!(Machine::named("invaders"), Some(Machine::Invaders));
+        assert_eq!(Machine::named("pong"), None);
+    }
+
+    #[test]
+    fn cpm_programs_end_by_returning() {
+        let state = cpm(&[0x00, 0xc3, 0x00, 0x00]);
+        let (state, stop) = Machine::Cpm.run(state, &mut Ports::default(), None, &BTreeSet::new(), &mut vec![]);
+        assert_eq!(stop, Stop::Exit);
+        assert_eq!(state.cycles, 14);
+    }
+
+    #[test]
+    fn the_bdos_prints_and_returns() {
+        let mut state = cpm(b"HELLO$");
+        let mut console = vec![];
+        // As if the program had called 5 from 0x0200 with C = 9 and DE pointing at the text.
+        state.sp -= 2;
+        state.memory[state.sp as usize..state.sp as usize + 2].copy_from_slice(&[0x00, 0x02]);
+        state.pc = BDOS;
+        state.c = 9;
+        state.d = 0x01;
+        let (state, stop) = Machine::Cpm.tick(state, &mut Ports::default(), &mut console);
+        assert_eq!(stop, None);
+        assert_eq!(console, b"HELLO");
+        assert_eq!((state.pc, state.sp), (0x0200, BDOS_TOP - 2));
+
+        let mut state = state;
+        state.pc = BDOS;
+        state.c = 2;
+        state.e = b'!';
+        state.sp -= 2;
+        let (_, stop) = Machine::Cpm.tick(state, &mut Ports::default(), &mut console);
+        assert_eq!((stop, console.as_slice()), (None, &b"HELLO!"[..]));
+    }
+
+    #[test]
+    fn runs_stop_at_the_cycle_limit() {
+        let state = cpm(&[0xc3, 0x00, 0x01]);
+        let (state, stop) = Machine::Cpm.run(state, &mut Ports::default(), Some(100), &BTreeSet::new(), &mut vec![]);
+        assert_eq!(stop, Stop::CycleLimit);
+        assert_eq!(state.cycles, 100);
+    }
+
+    #[test]
+    fn runs_stop_at_breakpoints_but_not_where_they_start() {
+        let state = cpm(&[0x00, 0x00, 0xc3, 0x00, 0x01]);
+        let breakpoints = [0x100, 0x102].iter().cloned().collect();
+        let (state, stop) = Machine::Cpm.run(state, &mut Ports::default(), None, &breakpoints, &mut vec![]);
+        assert_eq!(stop, Stop::Breakpoint(0x102));
+        let (_, stop) = Machine::Cpm.run(state, &mut Ports::default(), None, &breakpoints, &mut vec![]);
+        assert_eq!(stop, Stop::Breakpoint(0x100));
+    }
+
+    #[test]
+    fn faults_stop_the_run() {
+        let (_, stop) = Machine::Cpm.run(cpm(&[0x76]), &mut Ports::default(), None, &BTreeSet::new(), &mut vec![]);
+        assert_eq!(stop, Stop::Fault("0100: HLT isn't implemented".to_string()));
+    }
+}
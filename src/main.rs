@@ -1,636 +1,936 @@
+extern crate rs8080;
+
+mod debugger;
+
+use std::collections::BTreeSet;
 use std::env;
-use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
+use std::ops::Range;
+use std::path::Path;
+use std::process;
+
+use rs8080::{assembler, cabinet, cfg, checksum, cpu, disassembler, html, invaders, linker, loader, machine, mixer, ports, rel, romset, savestate, sound, symbols, video, watchdog, wav};
+use rs8080::machine::{Machine, Stop};
+
+fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn parse_format(value: &str) -> std::io::Result<loader::Format> {
+    loader::Format::named(value).ok_or_else(|| invalid_input(format!("unknown image format: {}", value)))
+}
 
-struct State {
-    b: u8,
-    pc: u16,
-    sp: u16,
-    memory: [u8; 0x10000], // 16k
+// Addresses are hex, written as `0x1234`, `$1234` or `1234h`.
+fn parse_address(value: &str) -> std::io::Result<u16> {
+    let digits = value.strip_prefix("0x")
+        .or_else(|| value.strip_prefix('$'))
+        .or_else(|| value.strip_suffix(|c| c == 'h' || c == 'H'))
+        .unwrap_or(value);
+
+    u16::from_str_radix(digits, 16).map_err(|_| invalid_input(format!("invalid address: {}", value)))
 }
 
-impl fmt::Debug for State {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:04x} b: {:02x}, sp: {:02x}", self.pc, self.b, self.sp)
+// A range of memory is `START-END`, including END, or `START+LENGTH`.
+fn parse_range(value: &str) -> std::io::Result<Range<usize>> {
+    if let Some((start, length)) = value.split_once('+') {
+        let start = parse_address(start)? as usize;
+        let length = parse_address(length)? as usize;
+        if length == 0 || start + length > 0x10000 {
+            return Err(invalid_input(format!("invalid range: {}", value)))
+        }
+        return Ok(start..start + length)
+    }
+    let (start, end) = value.split_once('-').ok_or_else(|| invalid_input(format!("invalid range: {}", value)))?;
+    let (start, end) = (parse_address(start)? as usize, parse_address(end)? as usize);
+    if end < start {
+        return Err(invalid_input(format!("invalid range: {}", value)))
     }
+    Ok(start..end + 1)
 }
 
-fn disassemble_1(instruction: &'static str) -> u16 {
-    println!("\t{instruction}", instruction = instruction);
-    1
+fn parse_record_length(value: &str) -> std::io::Result<usize> {
+    match value.parse() {
+        Ok(length) if (1..=loader::MAX_RECORD_LENGTH).contains(&length) => Ok(length),
+        _ => Err(invalid_input(format!("record length must be 1 to {}: {}", loader::MAX_RECORD_LENGTH, value))),
+    }
 }
 
-fn disassemble_2(instruction: &'static str, byte_1: u8) -> u16 {
-    println!("{byte_1:02x}\t{instruction}\t${byte_1:02x}", instruction = instruction, byte_1 = byte_1);
-    2
+fn is_hex_path(path: &str) -> bool {
+    let extension = Path::new(path).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+    matches!(extension.as_deref(), Some("hex") | Some("ihx"))
 }
 
-fn disassemble_3(instruction: &'static str, byte_1: u8, byte_2: u8) -> u16 {
-    println!("{byte_1:02x} {byte_2:02x}\t{instruction}\t${byte_2:02x}{byte_1:02x}", instruction = instruction, byte_1 = byte_1, byte_2 = byte_2);
-    3
+// Writes `bytes` from `address` to `path`, as Intel HEX if its name ends in .hex or .ihx and
+// as they are otherwise.
+fn write_dump(path: &str, address: usize, bytes: &[u8], record_length: usize) -> std::io::Result<()> {
+    if is_hex_path(path) {
+        let block = loader::Block { address, bytes: bytes.to_vec() };
+        return File::create(path)?.write_all(loader::write_intel_hex(&[block], record_length, None).map_err(invalid_input)?.as_bytes())
+    }
+    File::create(path)?.write_all(bytes)
 }
 
-fn disassemble_not_implemented() -> u16 {
-    println!("\t???");
-    0
+// Loads a single image, or a set of `roms` if `path` is a directory or a ZIP archive. Bad
+// dumps in a set are reported but still loaded; missing ones are an error.
+fn load_image(memory: &mut loader::Memory, path: &str, format: Option<loader::Format>, base: u16, roms: &[romset::Rom]) -> std::io::Result<(Vec<Range<usize>>, Option<romset::Report>)> {
+    if !romset::is_romset(path) {
+        return Ok((memory.load_file(path, format, base).map_err(invalid_data)?, None))
+    }
+    if roms.is_empty() {
+        return Err(invalid_input(format!("{}: this machine doesn't use ROM sets", path)))
+    }
+
+    let report = romset::load(path, roms, memory).map_err(invalid_data)?;
+    if !report.complete() {
+        return Err(invalid_data(format!("{} is incomplete:\n{}", path, report.describe())))
+    }
+    if !report.verified() {
+        eprint!("warning: {} has bad dumps:\n{}", path, report.describe());
+    }
+    let ranges = report.roms.iter().map(|(rom, _)| rom.address as usize..rom.address as usize + rom.size).collect();
+    Ok((ranges, Some(report)))
 }
 
-#[allow(dead_code)]
-fn disassemble(pc: u16, state: &State) -> u16 {
-    let pc = pc as usize;
-    let byte_1 = state.memory[pc];
-    let byte_2 = *state.memory.get(pc + 1).unwrap_or(&0);
-    let byte_3 = *state.memory.get(pc + 2).unwrap_or(&0);
-
-    print!("{:04x} {:02x} ", pc, byte_1);
-
-    let op_bytes = match byte_1 {
-        0x00 => { disassemble_1("NOP") },
-        0x01 => { disassemble_3("LXI B", byte_2, byte_3) },
-        0x02 => { disassemble_1("STAX B") },
-        0x03 => { disassemble_1("INX B") },
-        0x04 => { disassemble_1("INR B") },
-        0x05 => { disassemble_1("DCR B") },
-        0x06 => { disassemble_2("MVI B", byte_2) },
-        0x07 => { disassemble_1("RLC") },
-        0x08 => { disassemble_1("-") },
-        0x09 => { disassemble_1("DAD B") },
-        0x0a => { disassemble_1("LDAX B") },
-        0x0b => { disassemble_1("DCX B") },
-        0x0c => { disassemble_1("INR C") },
-        0x0d => { disassemble_1("DCR C") },
-        0x0e => { disassemble_2("MVI C", byte_2) },
-        0x0f => { disassemble_1("RRC") },
-        0x10 => { disassemble_1("-") },
-        0x11 => { disassemble_3("LXI D", byte_2, byte_3) },
-        0x12 => { disassemble_1("STAX D") },
-        0x13 => { disassemble_1("INX D") },
-        0x14 => { disassemble_1("INR D") },
-        0x15 => { disassemble_1("DCR D") },
-        0x16 => { disassemble_2("MVI D", byte_2) },
-        0x17 => { disassemble_not_implemented() },
-        0x18 => { disassemble_1("-") },
-        0x19 => { disassemble_1("DAD D") },
-        0x1a => { disassemble_1("LDAX D") },
-        0x1b => { disassemble_1("DCX D") },
-        0x1c => { disassemble_1("INR E") },
-        0x1d => { disassemble_1("DCR E") },
-        0x1e => { disassemble_2("MVI E", byte_2) },
-        0x1f => { disassemble_1("RAR") },
-        0x20 => { disassemble_1("RIM") },
-        0x21 => { disassemble_3("LXI H", byte_2, byte_3) },
-        0x22 => { disassemble_3("SHLD", byte_2, byte_3) },
-        0x23 => { disassemble_1("INX H") },
-        0x24 => { disassemble_1("INR H") },
-        0x25 => { disassemble_1("DCR H") },
-        0x26 => { disassemble_2("MVI H", byte_2) },
-        0x27 => { disassemble_1("DAA") },
-        0x28 => { disassemble_1("-") },
-        0x29 => { disassemble_1("DAD H") },
-        0x2a => { disassemble_3("LHLD", byte_2, byte_3) },
-        0x2b => { disassemble_1("DCX H") },
-        0x2c => { disassemble_1("INR L") },
-        0x2d => { disassemble_not_implemented() },
-        0x2e => { disassemble_2("MVI L", byte_2) },
-        0x2f => { disassemble_1("CMA") },
-        0x30 => { disassemble_1("SIM") },
-        0x31 => { disassemble_3("LXI SP", byte_2, byte_3) },
-        0x32 => { disassemble_3("STA A", byte_2, byte_3) },
-        0x33 => { disassemble_not_implemented() },
-        0x34 => { disassemble_1("INR M") },
-        0x35 => { disassemble_1("DCR M") },
-        0x36 => { disassemble_2("MVI M", byte_2) },
-        0x37 => { disassemble_1("STC") },
-        0x38 => { disassemble_1("-") },
-        0x39 => { disassemble_1("DAD SP") },
-        0x3a => { disassemble_3("LDA A", byte_2, byte_3) },
-        0x3b => { disassemble_not_implemented() },
-        0x3c => { disassemble_1("INR A") },
-        0x3d => { disassemble_1("DCR A") },
-        0x3e => { disassemble_2("MVI A", byte_2) },
-        0x3f => { disassemble_1("CMC") },
-        0x40 => { disassemble_1("MOV B,B") },
-        0x41 => { disassemble_1("MOV B,C") },
-        0x42 => { disassemble_1("MOV B,D") },
-        0x43 => { disassemble_1("MOV B,E") },
-        0x44 => { disassemble_1("MOV B,H") },
-        0x45 => { disassemble_1("MOV B,L") },
-        0x46 => { disassemble_1("MOV B,F") },
-        0x47 => { disassemble_1("MOV B,A") },
-        0x48 => { disassemble_1("MOV C,B") },
-        0x49 => { disassemble_1("MOV C,C") },
-        0x4a => { disassemble_1("MOV C,D") },
-        0x4b => { disassemble_1("MOV C,E") },
-        0x4c => { disassemble_1("MOV C,H") },
-        0x4d => { disassemble_1("MOV C,L") },
-        0x4e => { disassemble_1("MOV C,M") },
-        0x4f => { disassemble_1("MOV C,A") },
-        0x50 => { disassemble_1("MOV D,B") },
-        0x51 => { disassemble_1("MOV D,C") },
-        0x52 => { disassemble_not_implemented() },
-        0x53 => { disassemble_not_implemented() },
-        0x54 => { disassemble_1("MOV D,H") },
-        0x55 => { disassemble_not_implemented() },
-        0x56 => { disassemble_1("MOV D,M") },
-        0x57 => { disassemble_1("MOV D,A") },
-        0x58 => { disassemble_not_implemented() },
-        0x59 => { disassemble_1("MOV E,C") },
-        0x5a => { disassemble_not_implemented() },
-        0x5b => { disassemble_1("MOV E,E") },
-        0x5c => { disassemble_not_implemented() },
-        0x5d => { disassemble_not_implemented() },
-        0x5e => { disassemble_1("MOV E,M") },
-        0x5f => { disassemble_1("MOV E,A") },
-        0x60 => { disassemble_1("MOV H,B") },
-        0x61 => { disassemble_1("MOV H,C") },
-        0x62 => { disassemble_1("MOV H,D") },
-        0x63 => { disassemble_1("MOV H,E") },
-        0x64 => { disassemble_1("MOV H,H") },
-        0x65 => { disassemble_1("MOV H,L") },
-        0x66 => { disassemble_1("MOV H,M") },
-        0x67 => { disassemble_1("MOV H,A") },
-        0x68 => { disassemble_1("MOV L,B") },
-        0x69 => { disassemble_1("MOV L,C") },
-        0x6a => { disassemble_not_implemented() },
-        0x6b => { disassemble_not_implemented() },
-        0x6c => { disassemble_1("MOV L,H") },
-        0x6d => { disassemble_1("MOV L,L") },
-        0x6e => { disassemble_1("MOV L,M") },
-        0x6f => { disassemble_1("MOV L,A") },
-        0x70 => { disassemble_1("MOV M,B") },
-        0x71 => { disassemble_1("MOV M,C") },
-        0x72 => { disassemble_1("MOV M,D") },
-        0x73 => { disassemble_1("MOV M,E") },
-        0x74 => { disassemble_1("MOV M,H") },
-        0x75 => { disassemble_not_implemented() },
-        0x76 => { disassemble_1("HLT") },
-        0x77 => { disassemble_1("MOV M,A") },
-        0x78 => { disassemble_1("MOV A,B") },
-        0x79 => { disassemble_1("MOV A,C") },
-        0x7a => { disassemble_1("MOV A,D") },
-        0x7b => { disassemble_1("MOV A,E") },
-        0x7c => { disassemble_1("MOV A,H") },
-        0x7d => { disassemble_1("MOV A,L") },
-        0x7e => { disassemble_1("MOV A,M") },
-        0x7f => { disassemble_1("MOV A,A") },
-        0x80 => { disassemble_1("ADD B") },
-        0x81 => { disassemble_1("ADD C") },
-        0x82 => { disassemble_1("ADD D") },
-        0x83 => { disassemble_1("ADD E") },
-        0x84 => { disassemble_1("ADD H") },
-        0x85 => { disassemble_1("ADD L") },
-        0x86 => { disassemble_1("ADD M") },
-        0x87 => { disassemble_not_implemented() },
-        0x88 => { disassemble_1("ADC B") },
-        0x89 => { disassemble_not_implemented() },
-        0x8a => { disassemble_1("ADC D") },
-        0x8b => { disassemble_1("ADC E") },
-        0x8c => { disassemble_not_implemented() },
-        0x8d => { disassemble_not_implemented() },
-        0x8e => { disassemble_1("ADC M") },
-        0x8f => { disassemble_not_implemented() },
-        0x90 => { disassemble_1("SUB B") },
-        0x91 => { disassemble_not_implemented() },
-        0x92 => { disassemble_not_implemented() },
-        0x93 => { disassemble_not_implemented() },
-        0x94 => { disassemble_1("SUB H") },
-        0x95 => { disassemble_not_implemented() },
-        0x96 => { disassemble_not_implemented() },
-        0x97 => { disassemble_1("SUB A") },
-        0x98 => { disassemble_1("SBB B") },
-        0x99 => { disassemble_1("SBB C") },
-        0x9a => { disassemble_1("SBB D") },
-        0x9b => { disassemble_1("SBB E") },
-        0x9c => { disassemble_not_implemented() },
-        0x9d => { disassemble_1("SBB L") },
-        0x9e => { disassemble_1("SBB M") },
-        0x9f => { disassemble_not_implemented() },
-        0xa0 => { disassemble_1("ANA B") },
-        0xa1 => { disassemble_not_implemented() },
-        0xa2 => { disassemble_not_implemented() },
-        0xa3 => { disassemble_1("ANA E") },
-        0xa4 => { disassemble_not_implemented() },
-        0xa5 => { disassemble_not_implemented() },
-        0xa6 => { disassemble_1("ANA M") },
-        0xa7 => { disassemble_1("ANA A") },
-        0xa8 => { disassemble_1("ANA B") },
-        0xa9 => { disassemble_not_implemented() },
-        0xaa => { disassemble_1("XRA D") },
-        0xab => { disassemble_not_implemented() },
-        0xac => { disassemble_not_implemented() },
-        0xad => { disassemble_not_implemented() },
-        0xae => { disassemble_not_implemented() },
-        0xaf => { disassemble_1("XRA A") },
-        0xb0 => { disassemble_1("ORA B") },
-        0xb1 => { disassemble_not_implemented() },
-        0xb2 => { disassemble_not_implemented() },
-        0xb3 => { disassemble_1("ORA E") },
-        0xb4 => { disassemble_1("ORA H") },
-        0xb5 => { disassemble_not_implemented() },
-        0xb6 => { disassemble_1("ORA M") },
-        0xb7 => { disassemble_not_implemented() },
-        0xb8 => { disassemble_1("CPM B") },
-        0xb9 => { disassemble_not_implemented() },
-        0xba => { disassemble_not_implemented() },
-        0xbb => { disassemble_1("CMP E") },
-        0xbc => { disassemble_1("CMP H") },
-        0xbd => { disassemble_not_implemented() },
-        0xbe => { disassemble_1("CMP M") },
-        0xbf => { disassemble_not_implemented() },
-        0xc0 => { disassemble_1("RNZ") },
-        0xc1 => { disassemble_1("POP B") },
-        0xc2 => { disassemble_3("JNZ", byte_2, byte_3) },
-        0xc3 => { disassemble_3("JMP", byte_2, byte_3) },
-        0xc4 => { disassemble_3("CNZ", byte_2, byte_3) },
-        0xc5 => { disassemble_1("PUSH B") },
-        0xc6 => { disassemble_2("ADI", byte_2) },
-        0xc7 => { disassemble_not_implemented() },
-        0xc8 => { disassemble_1("RZ") },
-        0xc9 => { disassemble_1("RET") },
-        0xca => { disassemble_3("JZ", byte_2, byte_3) },
-        0xcb => { disassemble_not_implemented() },
-        0xcc => { disassemble_3("CZ", byte_2, byte_3) },
-        0xcd => { disassemble_3("CALL", byte_2, byte_3) },
-        0xce => { disassemble_not_implemented() },
-        0xcf => { disassemble_not_implemented() },
-        0xd0 => { disassemble_1("RNC") },
-        0xd1 => { disassemble_1("POP D") },
-        0xd2 => { disassemble_3("JNC", byte_2, byte_3) },
-        0xd3 => { disassemble_2("OUT", byte_2) },
-        0xd4 => { disassemble_3("CNC", byte_2, byte_3) },
-        0xd5 => { disassemble_1("PUSH D") },
-        0xd6 => { disassemble_2("SUI", byte_2) },
-        0xd7 => { disassemble_not_implemented() },
-        0xd8 => { disassemble_1("RC") },
-        0xd9 => { disassemble_not_implemented() },
-        0xda => { disassemble_3("JC", byte_2, byte_3) },
-        0xdb => { disassemble_2("IN", byte_2) },
-        0xdc => { disassemble_not_implemented() },
-        0xdd => { disassemble_not_implemented() },
-        0xde => { disassemble_2("SBI", byte_2) },
-        0xdf => { disassemble_not_implemented() },
-        0xe0 => { disassemble_1("RPO") },
-        0xe1 => { disassemble_1("POP H") },
-        0xe2 => { disassemble_3("JPO", byte_2, byte_3) },
-        0xe3 => { disassemble_1("XTHL") },
-        0xe4 => { disassemble_not_implemented() },
-        0xe5 => { disassemble_1("PUSH H") },
-        0xe6 => { disassemble_2("ANI", byte_2) },
-        0xe7 => { disassemble_not_implemented() },
-        0xe8 => { disassemble_not_implemented() },
-        0xe9 => { disassemble_1("PCHL") },
-        0xea => { disassemble_not_implemented() },
-        0xeb => { disassemble_1("XCHG") },
-        0xec => { disassemble_3("CPE", byte_2, byte_3) },
-        0xed => { disassemble_not_implemented() },
-        0xee => { disassemble_2("XRI", byte_2) },
-        0xef => { disassemble_not_implemented() },
-        0xf0 => { disassemble_1("RP") },
-        0xf1 => { disassemble_1("POP PSW") },
-        0xf2 => { disassemble_not_implemented() },
-        0xf3 => { disassemble_not_implemented() },
-        0xf4 => { disassemble_not_implemented() },
-        0xf5 => { disassemble_1("PUSH PSW") },
-        0xf6 => { disassemble_2("ORI", byte_2) },
-        0xf7 => { disassemble_not_implemented() },
-        0xf8 => { disassemble_1("RM") },
-        0xf9 => { disassemble_not_implemented() },
-        0xfa => { disassemble_3("JM", byte_2, byte_3) },
-        0xfb => { disassemble_1("EI") },
-        0xfc => { disassemble_3("CM", byte_2, byte_3) },
-        0xfd => { disassemble_not_implemented() },
-        0xfe => { disassemble_2("CPI", byte_2) },
-        0xff => { disassemble_1("RST 7") },
-           _ => unreachable!()
-    };
+fn parse_machine(value: &str) -> std::io::Result<Machine> {
+    Machine::named(value).ok_or_else(|| invalid_input(format!("unknown machine: {}", value)))
+}
 
-    op_bytes
+fn parse_cycles(value: &str) -> std::io::Result<u64> {
+    value.parse().map_err(|_| invalid_input(format!("invalid cycle count: {}", value)))
 }
 
-fn read_16(buffer: &[u8; 0x10000], pc: usize) -> u16 {
-    ((buffer[pc + 2] as u16) << 8) | (buffer[pc + 1] as u16)
+// Sets the cabinet's DIP switches from each --dip setting and --dips file, in the order they
+// were given, so later ones win. Switches they don't mention are left as they were.
+fn set_dips(machine: Machine, ports: &mut ports::Ports, dips: &[(String, String)]) -> std::io::Result<()> {
+    if dips.is_empty() {
+        return Ok(())
+    }
+    let cabinet = ports.find::<cabinet::Cabinet>().ok_or_else(|| invalid_input(format!("the {} machine has no DIP switches", machine.name())))?;
+    for (option, value) in dips {
+        match option.as_str() {
+            "--dip" => cabinet.dips.assign(value).map_err(invalid_input)?,
+            _ => cabinet.dips.read(value).map_err(invalid_data)?,
+        }
+    }
+    Ok(())
+}
+
+// A watchdog timeout is a number of cycles, or off.
+fn parse_timeout(value: &str) -> std::io::Result<Option<u64>> {
+    match value {
+        "off" => Ok(None),
+        _ => value.parse().ok().filter(|&timeout| timeout > 0).map(Some).ok_or_else(|| invalid_input(format!("invalid watchdog timeout: {}", value))),
+    }
+}
+
+// Sets how long the board's watchdog waits to be written to, if --watchdog was given.
+fn set_watchdog(machine: Machine, ports: &mut ports::Ports, timeout: Option<Option<u64>>) -> std::io::Result<()> {
+    if let Some(timeout) = timeout {
+        ports.find::<watchdog::Watchdog>().ok_or_else(|| invalid_input(format!("the {} machine has no watchdog", machine.name())))?.timeout = timeout;
+    }
+    Ok(())
 }
 
-fn not_implemented(state: &mut State, pc: u16) {
-    println!("{:?}", state);
-    disassemble(pc, &state);
-    state.pc = 0;
+/// An image loaded by run, debug or info, and the parts of memory it filled.
+struct Loaded {
+    path: String,
+    ranges: Vec<Range<usize>>,
+    report: Option<romset::Report>,
 }
 
-fn step(mut state: State) -> State {
-    let pc = state.pc as usize;
-    let op_code = state.memory[pc];
+/// The machine and images given to run, debug and info. Each image is loaded with the
+/// --format and --base given before it.
+struct Images {
+    machine: Machine,
+    format: Option<loader::Format>,
+    base: Option<u16>,
+    paths: Vec<(String, Option<loader::Format>, Option<u16>)>,
+}
 
-    if state.pc == (0x10000 - 1) as u16 {
-        println!("PC out of range.");
-        state.pc = 0; return state
+impl Images {
+    fn new() -> Images {
+        Images { machine: Machine::Invaders, format: None, base: None, paths: vec![] }
     }
 
-    state.pc += 1;
+    /// Takes `arg` if it is an image or one of the options about them, and says whether it did.
+    fn option<'a>(&mut self, arg: &str, value: &mut dyn FnMut() -> std::io::Result<&'a String>) -> std::io::Result<bool> {
+        match arg {
+            "--machine" => self.machine = parse_machine(value()?)?,
+            "--format" => self.format = Some(parse_format(value()?)?),
+            "--base" => self.base = Some(parse_address(value()?)?),
+            _ if arg.starts_with('-') => return Ok(false),
+            _ => self.paths.push((arg.to_string(), self.format, self.base)),
+        }
+        Ok(true)
+    }
 
-    match op_code {
-        0x00 => { },
-        0x01 => not_implemented(&mut state, pc as u16),
-        0x02 => not_implemented(&mut state, pc as u16),
-        0x03 => not_implemented(&mut state, pc as u16),
-        0x04 => not_implemented(&mut state, pc as u16),
-        0x05 => not_implemented(&mut state, pc as u16),
-        0x06 => {
-            state.b = state.memory[pc + 1];
-            state.pc += 1;
-        },
-        0x07 => not_implemented(&mut state, pc as u16),
-        0x08 => not_implemented(&mut state, pc as u16),
-        0x09 => not_implemented(&mut state, pc as u16),
-        0x0a => not_implemented(&mut state, pc as u16),
-        0x0b => not_implemented(&mut state, pc as u16),
-        0x0c => not_implemented(&mut state, pc as u16),
-        0x0d => not_implemented(&mut state, pc as u16),
-        0x0e => not_implemented(&mut state, pc as u16),
-        0x0f => not_implemented(&mut state, pc as u16),
-        0x10 => not_implemented(&mut state, pc as u16),
-        0x11 => not_implemented(&mut state, pc as u16),
-        0x12 => not_implemented(&mut state, pc as u16),
-        0x13 => not_implemented(&mut state, pc as u16),
-        0x14 => not_implemented(&mut state, pc as u16),
-        0x15 => not_implemented(&mut state, pc as u16),
-        0x16 => not_implemented(&mut state, pc as u16),
-        0x17 => not_implemented(&mut state, pc as u16),
-        0x18 => not_implemented(&mut state, pc as u16),
-        0x19 => not_implemented(&mut state, pc as u16),
-        0x1a => not_implemented(&mut state, pc as u16),
-        0x1b => not_implemented(&mut state, pc as u16),
-        0x1c => not_implemented(&mut state, pc as u16),
-        0x1d => not_implemented(&mut state, pc as u16),
-        0x1e => not_implemented(&mut state, pc as u16),
-        0x1f => not_implemented(&mut state, pc as u16),
-        0x20 => not_implemented(&mut state, pc as u16),
-        0x21 => not_implemented(&mut state, pc as u16),
-        0x22 => not_implemented(&mut state, pc as u16),
-        0x23 => not_implemented(&mut state, pc as u16),
-        0x24 => not_implemented(&mut state, pc as u16),
-        0x25 => not_implemented(&mut state, pc as u16),
-        0x26 => not_implemented(&mut state, pc as u16),
-        0x27 => not_implemented(&mut state, pc as u16),
-        0x28 => not_implemented(&mut state, pc as u16),
-        0x29 => not_implemented(&mut state, pc as u16),
-        0x2a => not_implemented(&mut state, pc as u16),
-        0x2b => not_implemented(&mut state, pc as u16),
-        0x2c => not_implemented(&mut state, pc as u16),
-        0x2d => not_implemented(&mut state, pc as u16),
-        0x2e => not_implemented(&mut state, pc as u16),
-        0x2f => not_implemented(&mut state, pc as u16),
-        0x30 => not_implemented(&mut state, pc as u16),
-        0x31 => {
-            state.sp = read_16(&state.memory, pc as usize);
-            state.pc += 2;
-        },
-        0x32 => not_implemented(&mut state, pc as u16),
-        0x33 => not_implemented(&mut state, pc as u16),
-        0x34 => not_implemented(&mut state, pc as u16),
-        0x35 => not_implemented(&mut state, pc as u16),
-        0x36 => not_implemented(&mut state, pc as u16),
-        0x37 => not_implemented(&mut state, pc as u16),
-        0x38 => not_implemented(&mut state, pc as u16),
-        0x39 => not_implemented(&mut state, pc as u16),
-        0x3a => not_implemented(&mut state, pc as u16),
-        0x3b => not_implemented(&mut state, pc as u16),
-        0x3c => not_implemented(&mut state, pc as u16),
-        0x3d => not_implemented(&mut state, pc as u16),
-        0x3e => not_implemented(&mut state, pc as u16),
-        0x3f => not_implemented(&mut state, pc as u16),
-        0x40 => not_implemented(&mut state, pc as u16),
-        0x41 => not_implemented(&mut state, pc as u16),
-        0x42 => not_implemented(&mut state, pc as u16),
-        0x43 => not_implemented(&mut state, pc as u16),
-        0x44 => not_implemented(&mut state, pc as u16),
-        0x45 => not_implemented(&mut state, pc as u16),
-        0x46 => not_implemented(&mut state, pc as u16),
-        0x47 => not_implemented(&mut state, pc as u16),
-        0x48 => not_implemented(&mut state, pc as u16),
-        0x49 => not_implemented(&mut state, pc as u16),
-        0x4a => not_implemented(&mut state, pc as u16),
-        0x4b => not_implemented(&mut state, pc as u16),
-        0x4c => not_implemented(&mut state, pc as u16),
-        0x4d => not_implemented(&mut state, pc as u16),
-        0x4e => not_implemented(&mut state, pc as u16),
-        0x4f => not_implemented(&mut state, pc as u16),
-        0x50 => not_implemented(&mut state, pc as u16),
-        0x51 => not_implemented(&mut state, pc as u16),
-        0x52 => not_implemented(&mut state, pc as u16),
-        0x53 => not_implemented(&mut state, pc as u16),
-        0x54 => not_implemented(&mut state, pc as u16),
-        0x55 => not_implemented(&mut state, pc as u16),
-        0x56 => not_implemented(&mut state, pc as u16),
-        0x57 => not_implemented(&mut state, pc as u16),
-        0x58 => not_implemented(&mut state, pc as u16),
-        0x59 => not_implemented(&mut state, pc as u16),
-        0x5a => not_implemented(&mut state, pc as u16),
-        0x5b => not_implemented(&mut state, pc as u16),
-        0x5c => not_implemented(&mut state, pc as u16),
-        0x5d => not_implemented(&mut state, pc as u16),
-        0x5e => not_implemented(&mut state, pc as u16),
-        0x5f => not_implemented(&mut state, pc as u16),
-        0x60 => not_implemented(&mut state, pc as u16),
-        0x61 => not_implemented(&mut state, pc as u16),
-        0x62 => not_implemented(&mut state, pc as u16),
-        0x63 => not_implemented(&mut state, pc as u16),
-        0x64 => not_implemented(&mut state, pc as u16),
-        0x65 => not_implemented(&mut state, pc as u16),
-        0x66 => not_implemented(&mut state, pc as u16),
-        0x67 => not_implemented(&mut state, pc as u16),
-        0x68 => not_implemented(&mut state, pc as u16),
-        0x69 => not_implemented(&mut state, pc as u16),
-        0x6a => not_implemented(&mut state, pc as u16),
-        0x6b => not_implemented(&mut state, pc as u16),
-        0x6c => not_implemented(&mut state, pc as u16),
-        0x6d => not_implemented(&mut state, pc as u16),
-        0x6e => not_implemented(&mut state, pc as u16),
-        0x6f => not_implemented(&mut state, pc as u16),
-        0x70 => not_implemented(&mut state, pc as u16),
-        0x71 => not_implemented(&mut state, pc as u16),
-        0x72 => not_implemented(&mut state, pc as u16),
-        0x73 => not_implemented(&mut state, pc as u16),
-        0x74 => not_implemented(&mut state, pc as u16),
-        0x75 => not_implemented(&mut state, pc as u16),
-        0x76 => not_implemented(&mut state, pc as u16),
-        0x77 => not_implemented(&mut state, pc as u16),
-        0x78 => not_implemented(&mut state, pc as u16),
-        0x79 => not_implemented(&mut state, pc as u16),
-        0x7a => not_implemented(&mut state, pc as u16),
-        0x7b => not_implemented(&mut state, pc as u16),
-        0x7c => not_implemented(&mut state, pc as u16),
-        0x7d => not_implemented(&mut state, pc as u16),
-        0x7e => not_implemented(&mut state, pc as u16),
-        0x7f => not_implemented(&mut state, pc as u16),
-        0x80 => not_implemented(&mut state, pc as u16),
-        0x81 => not_implemented(&mut state, pc as u16),
-        0x82 => not_implemented(&mut state, pc as u16),
-        0x83 => not_implemented(&mut state, pc as u16),
-        0x84 => not_implemented(&mut state, pc as u16),
-        0x85 => not_implemented(&mut state, pc as u16),
-        0x86 => not_implemented(&mut state, pc as u16),
-        0x87 => not_implemented(&mut state, pc as u16),
-        0x88 => not_implemented(&mut state, pc as u16),
-        0x89 => not_implemented(&mut state, pc as u16),
-        0x8a => not_implemented(&mut state, pc as u16),
-        0x8b => not_implemented(&mut state, pc as u16),
-        0x8c => not_implemented(&mut state, pc as u16),
-        0x8d => not_implemented(&mut state, pc as u16),
-        0x8e => not_implemented(&mut state, pc as u16),
-        0x8f => not_implemented(&mut state, pc as u16),
-        0x90 => not_implemented(&mut state, pc as u16),
-        0x91 => not_implemented(&mut state, pc as u16),
-        0x92 => not_implemented(&mut state, pc as u16),
-        0x93 => not_implemented(&mut state, pc as u16),
-        0x94 => not_implemented(&mut state, pc as u16),
-        0x95 => not_implemented(&mut state, pc as u16),
-        0x96 => not_implemented(&mut state, pc as u16),
-        0x97 => not_implemented(&mut state, pc as u16),
-        0x98 => not_implemented(&mut state, pc as u16),
-        0x99 => not_implemented(&mut state, pc as u16),
-        0x9a => not_implemented(&mut state, pc as u16),
-        0x9b => not_implemented(&mut state, pc as u16),
-        0x9c => not_implemented(&mut state, pc as u16),
-        0x9d => not_implemented(&mut state, pc as u16),
-        0x9e => not_implemented(&mut state, pc as u16),
-        0x9f => not_implemented(&mut state, pc as u16),
-        0xa0 => not_implemented(&mut state, pc as u16),
-        0xa1 => not_implemented(&mut state, pc as u16),
-        0xa2 => not_implemented(&mut state, pc as u16),
-        0xa3 => not_implemented(&mut state, pc as u16),
-        0xa4 => not_implemented(&mut state, pc as u16),
-        0xa5 => not_implemented(&mut state, pc as u16),
-        0xa6 => not_implemented(&mut state, pc as u16),
-        0xa7 => not_implemented(&mut state, pc as u16),
-        0xa8 => not_implemented(&mut state, pc as u16),
-        0xa9 => not_implemented(&mut state, pc as u16),
-        0xaa => not_implemented(&mut state, pc as u16),
-        0xab => not_implemented(&mut state, pc as u16),
-        0xac => not_implemented(&mut state, pc as u16),
-        0xad => not_implemented(&mut state, pc as u16),
-        0xae => not_implemented(&mut state, pc as u16),
-        0xaf => not_implemented(&mut state, pc as u16),
-        0xb0 => not_implemented(&mut state, pc as u16),
-        0xb1 => not_implemented(&mut state, pc as u16),
-        0xb2 => not_implemented(&mut state, pc as u16),
-        0xb3 => not_implemented(&mut state, pc as u16),
-        0xb4 => not_implemented(&mut state, pc as u16),
-        0xb5 => not_implemented(&mut state, pc as u16),
-        0xb6 => not_implemented(&mut state, pc as u16),
-        0xb7 => not_implemented(&mut state, pc as u16),
-        0xb8 => not_implemented(&mut state, pc as u16),
-        0xb9 => not_implemented(&mut state, pc as u16),
-        0xba => not_implemented(&mut state, pc as u16),
-        0xbb => not_implemented(&mut state, pc as u16),
-        0xbc => not_implemented(&mut state, pc as u16),
-        0xbd => not_implemented(&mut state, pc as u16),
-        0xbe => not_implemented(&mut state, pc as u16),
-        0xbf => not_implemented(&mut state, pc as u16),
-        0xc0 => not_implemented(&mut state, pc as u16),
-        0xc1 => not_implemented(&mut state, pc as u16),
-        0xc2 => not_implemented(&mut state, pc as u16),
-        0xc3 => {
-            state.pc = read_16(&state.memory, pc as usize);
+    fn load(&self) -> std::io::Result<(loader::Memory, Vec<Loaded>)> {
+        let mut paths = self.paths.clone();
+        if paths.is_empty() {
+            let default = self.machine.default_image()
+                .ok_or_else(|| invalid_input(format!("the {} machine needs an image", self.machine.name())))?;
+            paths.push((default.to_string(), self.format, self.base));
+        }
+
+        let mut memory = loader::Memory::default();
+        let mut loaded = vec![];
+        for (path, format, base) in paths {
+            let (ranges, report) = load_image(&mut memory, &path, format, base.unwrap_or(self.machine.origin()), self.machine.roms())?;
+            loaded.push(Loaded { path, ranges, report });
+        }
+        Ok((memory, loaded))
+    }
+}
+
+fn disassemble(args: &[String]) -> std::io::Result<()> {
+    let mut path = None;
+    let mut machine = Machine::Invaders;
+    let mut org = 0;
+    let mut format = None;
+    let mut start = None;
+    let mut end = None;
+    let mut entry = None;
+    let mut json = false;
+    let mut dot = false;
+    let mut html = false;
+    let mut source = false;
+    let mut symbols = symbols::Symbols::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| invalid_input(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "--org" => org = parse_address(value()?)?,
+            "--machine" => machine = parse_machine(value()?)?,
+            "--format" => format = Some(parse_format(value()?)?),
+            "--start" => start = Some(parse_address(value()?)?),
+            "--end" => end = Some(parse_address(value()?)?),
+            "--entry" => entry = Some(parse_address(value()?)?),
+            "--json" => json = true,
+            "--dot" => dot = true,
+            "--html" => html = true,
+            "--source" => source = true,
+            "--symbols" => symbols = symbols::Symbols::read(value()?)?,
+            "-h" | "--help" => return help(Some("disasm")),
+            _ if arg.starts_with('-') => return Err(invalid_input(format!("unknown option: {}", arg))),
+            _ => path = Some(arg.clone()),
+        }
+    }
+    let path = path.or_else(|| machine.default_image().map(str::to_string))
+        .ok_or_else(|| invalid_input("disasm needs an image".to_string()))?;
+
+    let mut image = loader::Memory::default();
+    let (ranges, _) = load_image(&mut image, &path, format, org, machine.roms())?;
+    let loaded = ranges.iter().map(|range| range.start).min().unwrap_or(org as usize)..ranges.iter().map(|range| range.end).max().unwrap_or(org as usize);
+    // Images that say where they start are analysed from there rather than from the bottom.
+    let org = image.entry.unwrap_or(loaded.start as u16);
+    let memory = image.bytes;
+
+    // --end is inclusive, so a single address can be given as both start and end.
+    let range = start.map_or(loaded.start, |start| start as usize)..end.map_or(loaded.end, |end| end as usize + 1);
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if dot {
+        let routines = match entry {
+            Some(entry) => vec![entry],
+            None => disassembler::analyse(&memory, &loaded, &[org]).subroutines.into_iter().collect(),
+        };
+        for routine in routines {
+            let name = symbols.labels.get(&routine).cloned().unwrap_or_else(|| format!("sub_{:04x}", routine));
+            write!(out, "{}", cfg::dot(&name, &cfg::blocks(&memory, &loaded, routine)))?;
+        }
+        return Ok(())
+    }
+
+    let (mut analysis, instructions) = match entry {
+        Some(entry) => {
+            let analysis = disassembler::analyse_routine(&memory, &loaded, entry);
+            let instructions = disassembler::reached(&memory, &analysis);
+            (analysis, instructions)
         },
-        0xc4 => not_implemented(&mut state, pc as u16),
-        0xc5 => not_implemented(&mut state, pc as u16),
-        0xc6 => not_implemented(&mut state, pc as u16),
-        0xc7 => not_implemented(&mut state, pc as u16),
-        0xc8 => not_implemented(&mut state, pc as u16),
-        0xc9 => not_implemented(&mut state, pc as u16),
-        0xca => not_implemented(&mut state, pc as u16),
-        0xcb => not_implemented(&mut state, pc as u16),
-        0xcc => not_implemented(&mut state, pc as u16),
-        0xcd => not_implemented(&mut state, pc as u16),
-        0xce => not_implemented(&mut state, pc as u16),
-        0xcf => not_implemented(&mut state, pc as u16),
-        0xd0 => not_implemented(&mut state, pc as u16),
-        0xd1 => not_implemented(&mut state, pc as u16),
-        0xd2 => not_implemented(&mut state, pc as u16),
-        0xd3 => not_implemented(&mut state, pc as u16),
-        0xd4 => not_implemented(&mut state, pc as u16),
-        0xd5 => not_implemented(&mut state, pc as u16),
-        0xd6 => not_implemented(&mut state, pc as u16),
-        0xd7 => not_implemented(&mut state, pc as u16),
-        0xd8 => not_implemented(&mut state, pc as u16),
-        0xd9 => not_implemented(&mut state, pc as u16),
-        0xda => not_implemented(&mut state, pc as u16),
-        0xdb => not_implemented(&mut state, pc as u16),
-        0xdc => not_implemented(&mut state, pc as u16),
-        0xdd => not_implemented(&mut state, pc as u16),
-        0xde => not_implemented(&mut state, pc as u16),
-        0xdf => not_implemented(&mut state, pc as u16),
-        0xe0 => not_implemented(&mut state, pc as u16),
-        0xe1 => not_implemented(&mut state, pc as u16),
-        0xe2 => not_implemented(&mut state, pc as u16),
-        0xe3 => not_implemented(&mut state, pc as u16),
-        0xe4 => not_implemented(&mut state, pc as u16),
-        0xe5 => not_implemented(&mut state, pc as u16),
-        0xe6 => not_implemented(&mut state, pc as u16),
-        0xe7 => not_implemented(&mut state, pc as u16),
-        0xe8 => not_implemented(&mut state, pc as u16),
-        0xe9 => not_implemented(&mut state, pc as u16),
-        0xea => not_implemented(&mut state, pc as u16),
-        0xeb => not_implemented(&mut state, pc as u16),
-        0xec => not_implemented(&mut state, pc as u16),
-        0xed => not_implemented(&mut state, pc as u16),
-        0xee => not_implemented(&mut state, pc as u16),
-        0xef => not_implemented(&mut state, pc as u16),
-        0xf0 => not_implemented(&mut state, pc as u16),
-        0xf1 => not_implemented(&mut state, pc as u16),
-        0xf2 => not_implemented(&mut state, pc as u16),
-        0xf3 => not_implemented(&mut state, pc as u16),
-        0xf4 => not_implemented(&mut state, pc as u16),
-        0xf5 => not_implemented(&mut state, pc as u16),
-        0xf6 => not_implemented(&mut state, pc as u16),
-        0xf7 => not_implemented(&mut state, pc as u16),
-        0xf8 => not_implemented(&mut state, pc as u16),
-        0xf9 => not_implemented(&mut state, pc as u16),
-        0xfa => not_implemented(&mut state, pc as u16),
-        0xfb => not_implemented(&mut state, pc as u16),
-        0xfc => not_implemented(&mut state, pc as u16),
-        0xfd => not_implemented(&mut state, pc as u16),
-        0xfe => not_implemented(&mut state, pc as u16),
-        0xff => not_implemented(&mut state, pc as u16),
-        _   => { state.pc = 0 },
-    }
-
-    state
+        None => (disassembler::analyse(&memory, &loaded, &[org]), disassembler::sweep(&memory, range.clone())),
+    };
+    symbols.apply(&mut analysis);
+
+    if html {
+        write!(out, "{}", html::listing(&path, &memory, range, &analysis, &symbols))?;
+        return Ok(())
+    }
+
+    if source {
+        writeln!(out, "\tORG ${:04x}", range.start)?;
+    }
+
+    for instruction in &instructions {
+        if source {
+            if let Some(label) = analysis.label(instruction.address) {
+                writeln!(out, "{}:", label)?;
+            }
+            writeln!(out, "\t{}", instruction.source())?;
+        } else if json {
+            writeln!(out, "{}", disassembler::json_record(instruction, &analysis))?;
+        } else if let Some(note) = analysis.note(instruction.address) {
+            writeln!(out, "{}\t; {}", instruction.text(), note)?;
+        } else {
+            writeln!(out, "{}", instruction.text())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn asm(args: &[String]) -> std::io::Result<()> {
+    let mut source_path = None;
+    let mut output_path = None;
+    let mut listing_path = None;
+    let mut dialect = None;
+    let mut relocatable = false;
+    let mut hex = false;
+    let mut record_length = 16;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output_path = Some(args.next().ok_or_else(|| invalid_input("-o needs a value".to_string()))?.clone()),
+            "-l" => listing_path = Some(args.next().ok_or_else(|| invalid_input("-l needs a value".to_string()))?.clone()),
+            "--rel" => relocatable = true,
+            "--hex" => hex = true,
+            "--record-length" => record_length = parse_record_length(args.next().ok_or_else(|| invalid_input("--record-length needs a value".to_string()))?)?,
+            "-h" | "--help" => return help(Some("asm")),
+            "--dialect" => {
+                let name = args.next().ok_or_else(|| invalid_input("--dialect needs a value".to_string()))?;
+                dialect = Some(assembler::Dialect::named(name).ok_or_else(|| invalid_input(format!("unknown dialect: {}", name)))?);
+            },
+            _ if arg.starts_with('-') => return Err(invalid_input(format!("unknown option: {}", arg))),
+            _ => source_path = Some(arg.clone()),
+        }
+    }
+
+    let source_path = source_path.ok_or_else(|| invalid_input("asm needs a source file".to_string()))?;
+    let extension = if relocatable { "rel" } else if hex { "hex" } else { "bin" };
+    let output_path = output_path.unwrap_or_else(|| Path::new(&source_path).with_extension(extension).to_string_lossy().into_owned());
+
+    // Without --dialect, M80's .MAC and ISIS's .SRC extensions say which one a file is in.
+    let extension = Path::new(&source_path).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+    let dialect = dialect.unwrap_or(match extension.as_deref() {
+        Some("mac") => assembler::Dialect::M80,
+        Some("src") => assembler::Dialect::Asm80,
+        _ => assembler::Dialect::Native,
+    });
+
+    let assembly = assembler::assemble_file(&source_path, dialect)
+        .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string()))?;
+    if relocatable {
+        File::create(&output_path)?.write_all(&rel::write(&assembly.module()))?;
+    } else if assembly.relocatable() {
+        return Err(invalid_input(format!("{} is a relocatable module: assemble it with --rel and link it", source_path)))
+    } else if hex || is_hex_path(&output_path) {
+        // HEX records carry their own addresses, so gaps between ORGs aren't filled in.
+        let blocks: Vec<loader::Block> = assembly.chunks.iter()
+            .map(|chunk| loader::Block { address: chunk.address as usize, bytes: chunk.bytes.clone() })
+            .collect();
+        let entry = assembly.entry.as_ref().map(|&(entry, _)| entry);
+        File::create(&output_path)?.write_all(loader::write_intel_hex(&blocks, record_length, entry).map_err(invalid_input)?.as_bytes())?;
+    } else {
+        let (_, image) = assembly.image();
+        File::create(&output_path)?.write_all(&image)?;
+    }
+
+    if let Some(listing_path) = listing_path {
+        File::create(&listing_path)?.write_all(assembly.listing().as_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn read_modules(path: &str) -> std::io::Result<Vec<rel::Module>> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+    rel::read(&bytes).map_err(|message| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path, message)))
+}
+
+fn link(args: &[String]) -> std::io::Result<()> {
+    let mut modules = vec![];
+    let mut libraries = vec![];
+    let mut output_path = None;
+    let mut map_path = None;
+    let mut origin = 0;
+    let mut data = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| invalid_input(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "-o" => output_path = Some(value()?.clone()),
+            "-m" => map_path = Some(value()?.clone()),
+            "--org" => origin = parse_address(value()?)?,
+            "--data" => data = Some(parse_address(value()?)?),
+            "--lib" => libraries.extend(read_modules(value()?)?),
+            "-h" | "--help" => return help(Some("link")),
+            _ if arg.starts_with('-') => return Err(invalid_input(format!("unknown option: {}", arg))),
+            _ => {
+                output_path = output_path.or_else(|| Some(Path::new(arg).with_extension("bin").to_string_lossy().into_owned()));
+                modules.extend(read_modules(arg)?);
+            },
+        }
+    }
+    if modules.is_empty() {
+        return Err(invalid_input("link needs at least one module".to_string()))
+    }
+
+    let linked = linker::link(&modules, &libraries, origin, data).map_err(invalid_input)?;
+    File::create(output_path.unwrap())?.write_all(&linked.image)?;
+    if let Some(map_path) = map_path {
+        File::create(&map_path)?.write_all(linked.map().as_bytes())?;
+    }
+
+    Ok(())
+}
+
+const USAGE: &str = "\
+usage: rs8080 COMMAND [OPTIONS]
+
+commands:
+  run      run an image on a machine
+  debug    step through an image on a machine
+  test     run CP/M CPU test programs and report which pass
+  info     show what an image or ROM set holds
+  disasm   disassemble an image
+  asm      assemble a source file
+  link     link relocatable modules
+
+rs8080 help COMMAND, or rs8080 COMMAND --help, describes a command.
+";
+
+// Options for the commands that load images onto a machine.
+const IMAGE_OPTIONS: &str = "\
+  --machine NAME   invaders (the default) or cpm
+  --format FORMAT  raw, hex or srec, for the images after it; detected unless given
+  --base ADDR      where the images after it are loaded; the machine's origin unless given
+
+IMAGE is a file, or for invaders a directory or ZIP of the ROM set. Without one, invaders
+runs invaders.rom.
+";
+
+// Options for setting up the invaders cabinet and board.
+const CABINET_OPTIONS: &str = "\
+  --dip KEY=VALUE  set a DIP switch: lives 3 to 6, bonus 1000 or 1500, coin-info on or off
+  --dips FILE      set the DIP switches from KEY = VALUE lines in FILE
+  --overlay        colour screenshots as the upright cabinet's overlay did
+  --cocktail       turn screenshots round when the program flips the screen, as the
+                   cocktail cabinet does for player 2
+  --watchdog CYCLES|off
+                   reset the CPU when the program hasn't written to port 6 for CYCLES,
+                   255 frames unless given
+";
+
+fn help(command: Option<&str>) -> std::io::Result<()> {
+    let text = match command {
+        None => USAGE.to_string(),
+        Some("run") => format!("\
+usage: rs8080 run [OPTIONS] [IMAGE]...
+
+  --cycles COUNT        stop after COUNT more CPU cycles
+  --frames COUNT        stop after COUNT more frames, at 60 a second
+  --load-state FILE     resume from a save state instead of loading images
+  --save-state FILE     save the machine's state to FILE once stopped
+  --screenshot FILE     write the screen to FILE once stopped, as PNG if FILE ends in .png
+                        and PPM otherwise
+  --screenshots N PATTERN
+                        write the screen every N frames, to PATTERN with %d replaced by
+                        the frame number
+  --dump RANGE FILE     write memory from RANGE to FILE once stopped, as Intel HEX if FILE
+                        ends in .hex or .ihx; RANGE is START-END or START+LENGTH
+  --record-length N     bytes per HEX record, 16 unless given
+  --inputs FILE         hold down controls as FILE says: each line is a frame number and
+                        the controls held from then on, out of coin, start1, start2, tilt,
+                        left1, right1, fire1, left2, right2 and fire2
+  --sound-log FILE      write each sound starting and stopping to FILE, a line each of the
+                        cycle count, the sound and on or off
+  --wav FILE            record the sound to FILE once stopped, mixed from the samples,
+                        with sounds that have none synthesized
+  --samples DIR         take samples from DIR, named after the sounds, like shot.wav, or
+                        0.wav to 9.wav as in MAME's sample set
+  --sample SOUND=FILE   play FILE for SOUND: ufo, shot, player-death, invader-hit,
+                        extended-play, fleet1 to fleet4 or ufo-hit
+  --sample-rate RATE    samples a second to record at, 44100 unless given
+  --watchdog-fail       stop with an error when the watchdog resets the CPU, rather than
+                        warning and carrying on
+{}{}", CABINET_OPTIONS, IMAGE_OPTIONS),
+        Some("debug") => format!("\
+usage: rs8080 debug [OPTIONS] [IMAGE]...
+
+Reads monitor commands from standard input; h lists them.
+
+  --cycles COUNT     stop continuing after COUNT more CPU cycles
+  --load-state FILE  resume from a save state instead of loading images
+{}{}", CABINET_OPTIONS, IMAGE_OPTIONS),
+        Some("test") => "\
+usage: rs8080 test [OPTIONS] PROGRAM...
+
+Runs each CP/M .COM program from 0100 on the cpm machine. A program passes if it exits and
+prints neither ERROR nor FAIL.
+
+  --cycles COUNT   fail a program that runs for more than COUNT CPU cycles
+  --expect TEXT    also require each program to print TEXT
+  --verbose        show what each program prints
+".to_string(),
+        Some("info") => format!("\
+usage: rs8080 info [OPTIONS] [IMAGE]...
+
+  --json           write the information as JSON
+{}", IMAGE_OPTIONS),
+        Some("disasm") | Some("disassemble") => "\
+usage: rs8080 disasm [OPTIONS] [IMAGE]
+
+  --machine NAME   machine whose ROM set and default image to use
+  --format FORMAT  raw, hex or srec; detected unless given
+  --org ADDR       where a raw image is loaded
+  --start ADDR     first address to disassemble
+  --end ADDR       last address to disassemble
+  --entry ADDR     trace the routine at ADDR
+  --symbols FILE   names for addresses
+  --json           one JSON record an instruction
+  --dot            control-flow graphs in Graphviz DOT
+  --html           an HTML listing
+  --source         source the assembler turns back into the same bytes
+".to_string(),
+        Some("asm") => "\
+usage: rs8080 asm [OPTIONS] SOURCE
+
+  -o FILE             output file; SOURCE with a .bin, .hex or .rel extension unless given
+  -l FILE             write a listing to FILE
+  --dialect DIALECT   native, m80 or asm80; .mac files are m80 and .src asm80 unless given
+  --rel               write a relocatable module to link
+  --hex               write Intel HEX, as when FILE ends in .hex
+  --record-length N   bytes per HEX record, 16 unless given
+".to_string(),
+        Some("link") => "\
+usage: rs8080 link [OPTIONS] MODULE...
+
+  -o FILE       output file; the first MODULE with a .bin extension unless given
+  -m FILE       write a link map to FILE
+  --org ADDR    where the code starts, 0 unless given
+  --data ADDR   where the data starts, after the code unless given
+  --lib FILE    a library to take modules from as needed
+".to_string(),
+        Some(command) => return Err(invalid_input(format!("unknown command: {}", command))),
+    };
+    std::io::stdout().lock().write_all(text.as_bytes())
 }
 
-fn main() -> std::io::Result<()> {
-    let args: Vec<_> = env::args().collect();
-    let mut file = File::open("invaders.rom")?;
-    let mut state = State {
-        b: 0,
-        pc: 0,
-        sp: 0,
-        memory: ([0; 0x10000]),
+// The machine and CPU that run and debug start with: booted from the images, or resumed
+// from a save state.
+fn start(images: &Images, load_state: Option<String>) -> std::io::Result<(Machine, cpu::State, ports::Ports)> {
+    let path = match load_state {
+        Some(path) => path,
+        None => {
+            let (memory, _) = images.load()?;
+            return Ok((images.machine, images.machine.boot(&memory.bytes, memory.entry), images.machine.ports()))
+        },
     };
+    if !images.paths.is_empty() {
+        return Err(invalid_input("images can't be loaded as well as a save state".to_string()))
+    }
+
+    let snapshot = savestate::read_file(&path).map_err(invalid_data)?;
+    let mut ports = snapshot.machine.ports();
+    ports.restore(&snapshot.devices).map_err(|error| invalid_data(format!("{}: {}", path, error)))?;
+    Ok((snapshot.machine, snapshot.state, ports))
+}
 
-    let mut buffer = vec![];
-    file.read_to_end(&mut buffer)?;
+fn run(args: &[String]) -> std::io::Result<()> {
+    let mut images = Images::new();
+    let mut limit = None;
+    let mut dumps = vec![];
+    let mut record_length = 16;
+    let mut frames = None;
+    let mut load_state = None;
+    let mut save_state = None;
+    let mut screenshot = None;
+    let mut screenshots = None;
+    let mut dips = vec![];
+    let mut script = None;
+    let mut sound_log = None;
+    let mut recording = None;
+    let mut rate = 44100;
+    let mut samples = vec![];
+    let mut screen = video::Options::default();
+    let mut watchdog = None;
+    let mut watchdog_fail = false;
 
-    let mut i = 0;
-    while i < buffer.len() {
-        state.memory[i] = buffer[i];
-        i += 1
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| invalid_input(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "--cycles" => limit = Some(parse_cycles(value()?)?),
+            "--watchdog" => watchdog = Some(parse_timeout(value()?)?),
+            "--watchdog-fail" => watchdog_fail = true,
+            "--frames" => frames = Some(parse_cycles(value()?)?),
+            "--load-state" => load_state = Some(value()?.clone()),
+            "--save-state" => save_state = Some(value()?.clone()),
+            "--screenshot" => screenshot = Some(value()?.clone()),
+            "--screenshots" => {
+                let every = value()?.parse().ok().filter(|&every| every > 0).ok_or_else(|| invalid_input("--screenshots needs a frame count".to_string()))?;
+                screenshots = Some((every, value()?.clone()));
+            },
+            "--dump" => {
+                let range = parse_range(value()?)?;
+                dumps.push((range, value()?.clone()));
+            },
+            "--record-length" => record_length = parse_record_length(value()?)?,
+            "--dip" | "--dips" => dips.push((arg.clone(), value()?.clone())),
+            "--overlay" => screen.overlay = true,
+            "--cocktail" => screen.cocktail = true,
+            "--sound-log" => sound_log = Some(value()?.clone()),
+            "--wav" => recording = Some(value()?.clone()),
+            "--sample-rate" => rate = value()?.parse().ok().filter(|&rate| (1000..=192000).contains(&rate)).ok_or_else(|| invalid_input("--sample-rate needs a rate from 1000 to 192000".to_string()))?,
+            "--samples" | "--sample" => samples.push((arg.clone(), value()?.clone())),
+            "--inputs" => script = Some(cabinet::read_script(value()?).map_err(invalid_data)?),
+            "-h" | "--help" => return help(Some("run")),
+            _ if images.option(arg, &mut value)? => {},
+            _ => return Err(invalid_input(format!("unknown option: {}", arg))),
+        }
     }
 
-    if args.get(1) == Some(&"disassemble".to_string()) {
-        while state.pc < state.memory.len() as u16 {
-            let op_bytes = disassemble(state.pc, &state);
-            if op_bytes == 0 { break }
-            state.pc += op_bytes;
+    let (machine, mut state, mut ports) = start(&images, load_state)?;
+    set_dips(machine, &mut ports, &dips)?;
+    set_watchdog(machine, &mut ports, watchdog)?;
+    let cycles_per_frame = machine.cycles_per_frame();
+    if (screenshot.is_some() || screenshots.is_some() || frames.is_some()) && cycles_per_frame.is_none() {
+        return Err(invalid_input(format!("the {} machine has no screen", machine.name())))
+    }
+    if script.is_some() && ports.find::<cabinet::Cabinet>().is_none() {
+        return Err(invalid_input(format!("the {} machine has no controls", machine.name())))
+    }
+    let mut mixer = mixer::Mixer::new(rate, invaders::CLOCK);
+    for (option, value) in &samples {
+        match option.as_str() {
+            "--samples" => mixer.read_dir(value).map_err(invalid_data)?,
+            _ => {
+                let (name, path) = value.split_once('=').ok_or_else(|| invalid_input(format!("--sample needs SOUND=FILE: {}", value)))?;
+                let sound = sound::Sound::named(name).ok_or_else(|| invalid_input(format!("unknown sound: {}", name)))?;
+                mixer.insert(sound, &wav::Wave::read_file(path).map_err(invalid_data)?);
+            },
         }
+    }
+    // The sound board is listened to from here on, for a log or a recording.
+    let start_cycles = state.cycles;
+    let sounds = if sound_log.is_some() || recording.is_some() {
+        let board = ports.find::<sound::SoundBoard>().ok_or_else(|| invalid_input(format!("the {} machine has no sound", machine.name())))?;
+        Some((board.latched(), board.subscribe()))
     } else {
-        loop {
-            state = step(state);
-            if state.pc == 0 { break }
+        None
+    };
+    // A run of frames ends at the start of vertical blank, as the last one is finished.
+    let frames_end = frames.zip(cycles_per_frame).map(|(frames, cycles)| (state.cycles / cycles + frames) * cycles);
+    let limit = match (limit.map(|count| state.cycles + count), frames_end) {
+        (Some(limit), Some(frames_end)) => Some(limit.min(frames_end)),
+        (limit, frames_end) => limit.or(frames_end),
+    };
+
+    let stdout = std::io::stdout();
+    let mut console = stdout.lock();
+    let stop = loop {
+        if let (Some(cycles), Some(script)) = (cycles_per_frame, script.as_ref()) {
+            ports.find::<cabinet::Cabinet>().unwrap().inputs = cabinet::scripted(script, state.cycles / cycles);
+        }
+        // With screenshots to take or inputs to change, the machine runs a frame at a time.
+        let frame_end = cycles_per_frame.filter(|_| screenshots.is_some() || script.is_some()).map(|cycles| (state.cycles / cycles + 1) * cycles);
+        let until = match (limit, frame_end) {
+            (Some(limit), Some(frame_end)) => Some(limit.min(frame_end)),
+            (limit, frame_end) => limit.or(frame_end),
+        };
+        let (next, stop) = machine.run(state, &mut ports, until, &BTreeSet::new(), &mut console);
+        state = next;
+        if stop == Stop::Watchdog && !watchdog_fail {
+            eprintln!("warning: the watchdog reset the CPU at {} cycles", state.cycles);
+            continue
+        }
+        if stop != Stop::CycleLimit || limit.is_some_and(|limit| state.cycles >= limit) {
+            break stop
+        }
+
+        if let (Some(cycles), Some((every, ref pattern))) = (cycles_per_frame, screenshots.as_ref()) {
+            let frame = state.cycles / cycles;
+            if frame % every == 0 {
+                machine.screen(&state, &mut ports, screen).unwrap().write(&pattern.replace("%d", &frame.to_string())).map_err(invalid_data)?;
+            }
+        }
+    };
+
+    if let Some(path) = screenshot {
+        machine.screen(&state, &mut ports, screen).unwrap().write(&path).map_err(invalid_data)?;
+    }
+    if let Some(path) = save_state {
+        savestate::write_file(&path, machine, &state, &ports.save()).map_err(invalid_data)?;
+    }
+    if let Some((latched, events)) = sounds {
+        let events: Vec<sound::Event> = events.try_iter().collect();
+        if let Some(path) = sound_log {
+            let log: String = events.iter().map(|event| format!("{}\n", event)).collect();
+            File::create(path)?.write_all(log.as_bytes())?;
+        }
+        if let Some(path) = recording {
+            mixer.synthesize();
+            mixer.render(&latched, &events, start_cycles, state.cycles).write_file(&path).map_err(invalid_data)?;
+        }
+    }
+    // Memory is dumped as it was left when the program stopped, read through the machine's
+    // memory map so that mirrors show what they mirror.
+    for (range, path) in dumps {
+        let bytes: Vec<u8> = range.clone().map(|address| state.read(address as u16)).collect();
+        write_dump(&path, range.start, &bytes, record_length)?;
+    }
+
+    match stop {
+        Stop::Fault(message) => {
+            eprintln!("{:?}", state);
+            Err(std::io::Error::other(message))
+        },
+        Stop::Watchdog => Err(std::io::Error::other(format!("the watchdog reset the CPU at {} cycles", state.cycles))),
+        _ => Ok(()),
+    }
+}
+
+fn debug(args: &[String]) -> std::io::Result<()> {
+    let mut images = Images::new();
+    let mut limit = None;
+    let mut load_state = None;
+    let mut dips = vec![];
+    let mut screen = video::Options::default();
+    let mut watchdog = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| invalid_input(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "--cycles" => limit = Some(parse_cycles(value()?)?),
+            "--watchdog" => watchdog = Some(parse_timeout(value()?)?),
+            "--load-state" => load_state = Some(value()?.clone()),
+            "--dip" | "--dips" => dips.push((arg.clone(), value()?.clone())),
+            "--overlay" => screen.overlay = true,
+            "--cocktail" => screen.cocktail = true,
+            "-h" | "--help" => return help(Some("debug")),
+            _ if images.option(arg, &mut value)? => {},
+            _ => return Err(invalid_input(format!("unknown option: {}", arg))),
+        }
+    }
+
+    let (machine, state, mut ports) = start(&images, load_state)?;
+    set_dips(machine, &mut ports, &dips)?;
+    set_watchdog(machine, &mut ports, watchdog)?;
+    let limit = limit.map(|count| state.cycles + count);
+    let (stdin, stdout) = (std::io::stdin(), std::io::stdout());
+    debugger::debug(machine, state, ports, limit, screen, &mut stdin.lock(), &mut stdout.lock())
+}
+
+fn test(args: &[String]) -> std::io::Result<()> {
+    let mut programs = vec![];
+    let mut limit = None;
+    let mut expect = None;
+    let mut verbose = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| invalid_input(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "--cycles" => limit = Some(parse_cycles(value()?)?),
+            "--expect" => expect = Some(value()?.clone()),
+            "--verbose" => verbose = true,
+            "-h" | "--help" => return help(Some("test")),
+            _ if arg.starts_with('-') => return Err(invalid_input(format!("unknown option: {}", arg))),
+            _ => programs.push(arg.clone()),
+        }
+    }
+    if programs.is_empty() {
+        return Err(invalid_input("test needs at least one program".to_string()))
+    }
+
+    let machine = Machine::Cpm;
+    let mut failed = 0;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for path in &programs {
+        let mut memory = loader::Memory::default();
+        memory.load_file(path, None, machine.origin()).map_err(invalid_data)?;
+        let mut console = vec![];
+        let (state, stop) = machine.run(machine.boot(&memory.bytes, None), &mut machine.ports(), limit, &BTreeSet::new(), &mut console);
+        let output = String::from_utf8_lossy(&console).into_owned();
+
+        let upper = output.to_uppercase();
+        let failure = match stop {
+            Stop::Exit if upper.contains("ERROR") || upper.contains("FAIL") => Some("reported a failure".to_string()),
+            Stop::Exit if expect.as_ref().is_some_and(|expect| !output.contains(expect.as_str())) => Some("didn't print what was expected".to_string()),
+            Stop::Exit => None,
+            Stop::Fault(message) => Some(message),
+            Stop::CycleLimit => Some(format!("still running after {} cycles", state.cycles)),
+            Stop::Breakpoint(_) | Stop::Watchdog => unreachable!(),
+        };
+        match failure {
+            None => writeln!(out, "PASS  {}  {} cycles", path, state.cycles)?,
+            Some(ref reason) => writeln!(out, "FAIL  {}  {}", path, reason)?,
+        }
+        if verbose || failure.is_some() && !output.is_empty() {
+            writeln!(out, "{}", output.trim_end())?;
+        }
+        if failure.is_some() {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(std::io::Error::other(format!("{} of {} programs failed", failed, programs.len())))
+    }
+    Ok(())
+}
+
+fn info(args: &[String]) -> std::io::Result<()> {
+    let mut images = Images::new();
+    let mut json = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| invalid_input(format!("{} needs a value", arg)));
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => return help(Some("info")),
+            _ if images.option(arg, &mut value)? => {},
+            _ => return Err(invalid_input(format!("unknown option: {}", arg))),
+        }
+    }
+
+    let (memory, loaded) = images.load()?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let checksums = |range: &Range<usize>| (checksum::crc32(&memory.bytes[range.clone()]), checksum::hex(&checksum::sha1(&memory.bytes[range.clone()])));
+
+    if json {
+        let mut records = vec![];
+        for image in &loaded {
+            let ranges: Vec<String> = image.ranges.iter().map(|range| {
+                let (crc32, sha1) = checksums(range);
+                format!("{{\"start\":{},\"end\":{},\"crc32\":\"{:08x}\",\"sha1\":\"{}\"}}", range.start, range.end - 1, crc32, sha1)
+            }).collect();
+            let roms: Vec<String> = image.report.iter().flat_map(|report| report.roms.iter()).map(|(rom, status)| {
+                let status = if *status == romset::Status::Good { "ok" } else if report_loaded(status) { "bad" } else { "missing" };
+                format!("{{\"name\":{},\"status\":\"{}\"}}", disassembler::json_string(rom.name), status)
+            }).collect();
+            records.push(format!("{{\"path\":{},\"ranges\":[{}],\"roms\":[{}]}}", disassembler::json_string(&image.path), ranges.join(","), roms.join(",")));
         }
+        let entry = memory.entry.map_or("null".to_string(), |entry| entry.to_string());
+        writeln!(out, "{{\"machine\":\"{}\",\"entry\":{},\"images\":[{}]}}", images.machine.name(), entry, records.join(","))?;
+        return Ok(())
     }
 
+    writeln!(out, "Machine: {}", images.machine.name())?;
+    if let Some(entry) = memory.entry {
+        writeln!(out, "Entry point: {:04x}", entry)?;
+    }
+    for image in &loaded {
+        writeln!(out, "\n{}", image.path)?;
+        for range in &image.ranges {
+            let (crc32, sha1) = checksums(range);
+            writeln!(out, "  {:04x}-{:04x}  {:>5} bytes  crc32 {:08x}  sha1 {}", range.start, range.end - 1, range.len(), crc32, sha1)?;
+        }
+        if let Some(ref report) = image.report {
+            for line in report.describe().lines() {
+                writeln!(out, "  {}", line)?;
+            }
+        }
+    }
     Ok(())
 }
+
+// Whether a ROM with `status` made it into memory.
+fn report_loaded(status: &romset::Status) -> bool {
+    matches!(*status, romset::Status::Good | romset::Status::BadDump { .. })
+}
+
+fn command(args: &[String]) -> std::io::Result<()> {
+    let rest = args.get(1..).unwrap_or(&[]);
+    match args.first().map(String::as_str) {
+        Some("run") => run(rest),
+        Some("debug") => debug(rest),
+        Some("test") => test(rest),
+        Some("info") => info(rest),
+        Some("disasm") | Some("disassemble") => disassemble(rest),
+        Some("asm") => asm(rest),
+        Some("link") => link(rest),
+        Some("help") => help(rest.first().map(String::as_str)),
+        Some("-h") | Some("--help") => help(None),
+        // Images given straight after the program name are run, as they always have been.
+        _ => run(args),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match command(&args) {
+        // Output piped into something like head that stopped reading has done its job.
+        Err(ref error) if error.kind() == std::io::ErrorKind::BrokenPipe => {},
+        Err(error) => {
+            eprintln!("rs8080: {}", error);
+            // Mistakes in how the program was invoked get a different code from failures.
+            process::exit(if error.kind() == std::io::ErrorKind::InvalidInput { 2 } else { 1 });
+        },
+        Ok(()) => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // A directory of its own for each test, as they run at once.
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("rs8080-{}-{}", name, process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn arguments(directory: &Path, args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.replace("{}", &directory.to_string_lossy())).collect()
+    }
+
+    #[test]
+    fn dumps_read_mirrored_ram() {
+        let directory = directory("dump");
+        // A loop at 0, with a byte in RAM to find through its mirror.
+        let mut image = vec![0; 0x2001];
+        image[..3].copy_from_slice(&[0xc3, 0x00, 0x00]);
+        image[0x2000] = 0x5a;
+        fs::write(directory.join("image.bin"), image).unwrap();
+
+        let args = arguments(&directory, &["{}/image.bin", "--cycles", "100", "--dump", "6000-6001", "{}/mirror.bin", "--dump", "2000+2", "{}/ram.hex"]);
+        let result = run(&args);
+        let (mirror, ram) = (fs::read(directory.join("mirror.bin")), fs::read_to_string(directory.join("ram.hex")));
+        fs::remove_dir_all(&directory).unwrap();
+
+        result.unwrap();
+        assert_eq!(mirror.unwrap(), [0x5a, 0x00]);
+        assert_eq!(ram.unwrap(), ":022000005A0084\n:00000001FF\n");
+    }
+
+    #[test]
+    fn watchdog_resets_fail_the_run_when_asked() {
+        let directory = directory("watchdog");
+        // A loop at 0 that never writes to the watchdog.
+        fs::write(directory.join("image.bin"), [0xc3, 0x00, 0x00]).unwrap();
+
+        let warned = run(&arguments(&directory, &["{}/image.bin", "--cycles", "1000", "--watchdog", "100"]));
+        let failed = run(&arguments(&directory, &["{}/image.bin", "--cycles", "1000", "--watchdog", "100", "--watchdog-fail"]));
+        fs::remove_dir_all(&directory).unwrap();
+
+        warned.unwrap();
+        assert_eq!(failed.map_err(|error| error.to_string()), Err("the watchdog reset the CPU at 100 cycles".to_string()));
+    }
+}
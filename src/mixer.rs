@@ -0,0 +1,181 @@
+//! Recording the sound board offline, by mixing a sample of each sound as the events say.
+//! Sounds with no sample to hand are synthesized.
+
+use std::path::Path;
+
+use sound::{Event, Sound, SOUNDS};
+use synth;
+use wav::Wave;
+
+/// The names MAME's invaders sample set gives each sound's file.
+const MAME_NAMES: [(Sound, &str); 10] = [
+    (Sound::Ufo, "0"), (Sound::Shot, "1"), (Sound::PlayerDeath, "2"), (Sound::InvaderHit, "3"),
+    (Sound::Fleet(1), "4"), (Sound::Fleet(2), "5"), (Sound::Fleet(3), "6"), (Sound::Fleet(4), "7"),
+    (Sound::UfoHit, "8"), (Sound::ExtendedPlay, "9"),
+];
+
+/// A sound being played, `at` samples in.
+struct Voice {
+    sound: Sound,
+    at: usize,
+}
+
+/// A sample for each sound, at the rate of the recording.
+pub struct Mixer {
+    pub rate: u32,
+    /// The CPU cycles in a second, which events are timed in.
+    pub clock: u64,
+    samples: Vec<(Sound, Vec<f32>)>,
+}
+
+impl Mixer {
+    pub fn new(rate: u32, clock: u64) -> Mixer {
+        Mixer { rate, clock, samples: vec![] }
+    }
+
+    /// Plays `wave` for `sound`, in place of any sample it had.
+    pub fn insert(&mut self, sound: Sound, wave: &Wave) {
+        self.samples.retain(|&(had, _)| had != sound);
+        self.samples.push((sound, wave.resample(self.rate).samples));
+    }
+
+    /// Makes up a sample, from a copy of its circuit, for each sound that has none.
+    pub fn synthesize(&mut self) {
+        for &sound in &SOUNDS {
+            if self.sample(sound).is_none() {
+                self.samples.push((sound, synth::synthesize(sound, self.rate)));
+            }
+        }
+    }
+
+    fn sample(&self, sound: Sound) -> Option<&[f32]> {
+        self.samples.iter().find(|&&(had, _)| had == sound).map(|(_, samples)| samples.as_slice())
+    }
+
+    /// Takes a sample for each sound from the directory at `path`, named after the sound,
+    /// like shot.wav or fleet1.wav, or as MAME names them, 0.wav to 9.wav.
+    pub fn read_dir(&mut self, path: &str) -> Result<(), String> {
+        let mut found = false;
+        for &sound in &SOUNDS {
+            let mame = MAME_NAMES.iter().find(|&&(named, _)| named == sound).map(|&(_, name)| name).unwrap();
+            let file = [sound.to_string(), mame.to_string()].iter()
+                .map(|name| Path::new(path).join(format!("{}.wav", name)))
+                .find(|file| file.is_file());
+            if let Some(file) = file {
+                self.insert(sound, &Wave::read_file(&file.to_string_lossy())?);
+                found = true;
+            }
+        }
+        if !found {
+            return Err(format!("{}: no samples found", path))
+        }
+        Ok(())
+    }
+
+    /// The sound from `start` to `end` cycles, played as `events` say with the sounds in
+    /// `latched` already switched on. Each sound starts over when it is switched on and plays
+    /// to its end, except the UFO, which loops until it is switched off. Nothing is heard
+    /// while the amplifier is off.
+    pub fn render(&self, latched: &[Sound], events: &[Event], start: u64, end: u64) -> Wave {
+        let position = |cycles: u64| (cycles.saturating_sub(start) as u128 * self.rate as u128 / self.clock as u128) as usize;
+        let mut samples = vec![0.0; position(end)];
+        let mut amplifier = latched.contains(&Sound::Amplifier);
+        // A sound switched on before the recording started is heard only if it loops.
+        let mut voices: Vec<Voice> = latched.iter().filter(|&&sound| sound == Sound::Ufo).map(|&sound| Voice { sound, at: 0 }).collect();
+
+        let mut events = events.iter().peekable();
+        for (index, output) in samples.iter_mut().enumerate() {
+            while let Some(event) = events.next_if(|event| position(event.cycles) <= index) {
+                match (event.sound, event.on) {
+                    (Sound::Amplifier, on) => amplifier = on,
+                    (sound, true) => {
+                        voices.retain(|voice| voice.sound != sound);
+                        voices.push(Voice { sound, at: 0 });
+                    },
+                    (Sound::Ufo, false) => voices.retain(|voice| voice.sound != Sound::Ufo),
+                    (_, false) => {},
+                }
+            }
+
+            let mut mixed = 0.0;
+            voices.retain_mut(|voice| {
+                let sample = match self.sample(voice.sound) {
+                    Some(sample) if !sample.is_empty() => sample,
+                    _ => return false,
+                };
+                if voice.at == sample.len() {
+                    if voice.sound != Sound::Ufo {
+                        return false
+                    }
+                    voice.at = 0;
+                }
+                mixed += sample[voice.at];
+                voice.at += 1;
+                true
+            });
+            if amplifier {
+                *output = mixed;
+            }
+        }
+        Wave { rate: self.rate, samples }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A mixer a sample a cycle, so events land on the sample they are timed for.
+    fn mixer() -> Mixer {
+        let mut mixer = Mixer::new(10, 10);
+        mixer.insert(Sound::Ufo, &Wave { rate: 10, samples: vec![0.1, 0.2, 0.3] });
+        mixer.insert(Sound::Shot, &Wave { rate: 10, samples: vec![0.5, 0.5] });
+        mixer
+    }
+
+    fn event(cycles: u64, sound: Sound, on: bool) -> Event {
+        Event { cycles, sound, on }
+    }
+
+    #[test]
+    fn the_ufo_loops_until_switched_off() {
+        let events = [event(0, Sound::Ufo, true), event(7, Sound::Ufo, false)];
+        let wave = mixer().render(&[Sound::Amplifier], &events, 0, 9);
+        assert_eq!(wave.samples, [0.1, 0.2, 0.3, 0.1, 0.2, 0.3, 0.1, 0.0, 0.0]);
+
+        // Already on when the recording starts, it plays from its beginning.
+        let wave = mixer().render(&[Sound::Amplifier, Sound::Ufo], &[], 100, 104);
+        assert_eq!(wave.samples, [0.1, 0.2, 0.3, 0.1]);
+    }
+
+    #[test]
+    fn other_sounds_play_once_and_mix() {
+        let events = [event(0, Sound::Ufo, true), event(1, Sound::Shot, true), event(2, Sound::Shot, false)];
+        let wave = mixer().render(&[Sound::Amplifier, Sound::Shot], &events, 0, 5);
+        assert_eq!(wave.samples, [0.1, 0.7, 0.8, 0.1, 0.2]);
+    }
+
+    #[test]
+    fn nothing_is_heard_with_the_amplifier_off() {
+        let events = [event(0, Sound::Ufo, true), event(2, Sound::Amplifier, true), event(4, Sound::Amplifier, false)];
+        let wave = mixer().render(&[], &events, 0, 6);
+        assert_eq!(wave.samples, [0.0, 0.0, 0.3, 0.1, 0.0, 0.0], "the sound carries on underneath");
+    }
+
+    #[test]
+    fn recordings_are_timed_by_the_clock() {
+        let mixer = Mixer::new(4, 2_000_000);
+        let wave = mixer.render(&[], &[], 1_000_000, 3_000_000);
+        assert_eq!((wave.rate, wave.samples.len()), (4, 4), "a second");
+    }
+
+    #[test]
+    fn sounds_without_samples_are_synthesized() {
+        let mut mixer = mixer();
+        mixer.synthesize();
+        assert_eq!(mixer.sample(Sound::Shot), Some(&[0.5, 0.5][..]), "samples to hand are kept");
+        for &sound in SOUNDS.iter() {
+            assert!(mixer.sample(sound).is_some(), "{}", sound);
+        }
+    }
+}
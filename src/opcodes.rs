@@ -0,0 +1,339 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Implied,
+    Byte,
+    Word,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    Next,
+    Jump,
+    ConditionalJump,
+    Call,
+    ConditionalCall,
+    Return,
+    ConditionalReturn,
+    Restart,
+    Indirect,
+    Halt,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub registers: &'static str,
+    pub operand: Operand,
+    /// Clock cycles taken to execute, or not to take the branch for a conditional call
+    /// or return.
+    pub cycles: u8,
+}
+
+impl Opcode {
+    pub fn size(&self) -> u16 {
+        match self.operand {
+            Operand::Implied => 1,
+            Operand::Byte => 2,
+            Operand::Word => 3,
+        }
+    }
+
+    /// Clock cycles taken when a conditional call or return does branch.
+    pub fn taken_cycles(&self) -> u8 {
+        match self.flow() {
+            Flow::ConditionalCall | Flow::ConditionalReturn => self.cycles + 6,
+            _ => self.cycles,
+        }
+    }
+
+    pub fn flow(&self) -> Flow {
+        match self.mnemonic {
+            "JMP" => Flow::Jump,
+            "JNZ" | "JZ" | "JNC" | "JC" | "JPO" | "JPE" | "JP" | "JM" => Flow::ConditionalJump,
+            "CALL" => Flow::Call,
+            "CNZ" | "CZ" | "CNC" | "CC" | "CPO" | "CPE" | "CP" | "CM" => Flow::ConditionalCall,
+            "RET" => Flow::Return,
+            "RNZ" | "RZ" | "RNC" | "RC" | "RPO" | "RPE" | "RP" | "RM" => Flow::ConditionalReturn,
+            "RST" => Flow::Restart,
+            "PCHL" => Flow::Indirect,
+            "HLT" => Flow::Halt,
+            _ => Flow::Next,
+        }
+    }
+
+    // Word operands of these instructions are addresses of data rather than code.
+    pub fn references_data(&self) -> bool {
+        match self.mnemonic {
+            "LDA" | "STA" | "LHLD" | "SHLD" => true,
+            "LXI" => self.registers != "SP",
+            _ => false,
+        }
+    }
+}
+
+fn entry(mnemonic: &'static str, registers: &'static str, operand: Operand, cycles: u8) -> Opcode {
+    Opcode { mnemonic, registers, operand, cycles }
+}
+
+pub fn opcode(op_code: u8) -> Opcode {
+    use self::Operand::*;
+
+    match op_code {
+        0x00 => entry("NOP", "", Implied, 4),
+        0x01 => entry("LXI", "B", Word, 10),
+        0x02 => entry("STAX", "B", Implied, 7),
+        0x03 => entry("INX", "B", Implied, 5),
+        0x04 => entry("INR", "B", Implied, 5),
+        0x05 => entry("DCR", "B", Implied, 5),
+        0x06 => entry("MVI", "B", Byte, 7),
+        0x07 => entry("RLC", "", Implied, 4),
+        0x08 => entry("-", "", Implied, 4),
+        0x09 => entry("DAD", "B", Implied, 10),
+        0x0a => entry("LDAX", "B", Implied, 7),
+        0x0b => entry("DCX", "B", Implied, 5),
+        0x0c => entry("INR", "C", Implied, 5),
+        0x0d => entry("DCR", "C", Implied, 5),
+        0x0e => entry("MVI", "C", Byte, 7),
+        0x0f => entry("RRC", "", Implied, 4),
+        0x10 => entry("-", "", Implied, 4),
+        0x11 => entry("LXI", "D", Word, 10),
+        0x12 => entry("STAX", "D", Implied, 7),
+        0x13 => entry("INX", "D", Implied, 5),
+        0x14 => entry("INR", "D", Implied, 5),
+        0x15 => entry("DCR", "D", Implied, 5),
+        0x16 => entry("MVI", "D", Byte, 7),
+        0x17 => entry("RAL", "", Implied, 4),
+        0x18 => entry("-", "", Implied, 4),
+        0x19 => entry("DAD", "D", Implied, 10),
+        0x1a => entry("LDAX", "D", Implied, 7),
+        0x1b => entry("DCX", "D", Implied, 5),
+        0x1c => entry("INR", "E", Implied, 5),
+        0x1d => entry("DCR", "E", Implied, 5),
+        0x1e => entry("MVI", "E", Byte, 7),
+        0x1f => entry("RAR", "", Implied, 4),
+        0x20 => entry("-", "", Implied, 4),
+        0x21 => entry("LXI", "H", Word, 10),
+        0x22 => entry("SHLD", "", Word, 16),
+        0x23 => entry("INX", "H", Implied, 5),
+        0x24 => entry("INR", "H", Implied, 5),
+        0x25 => entry("DCR", "H", Implied, 5),
+        0x26 => entry("MVI", "H", Byte, 7),
+        0x27 => entry("DAA", "", Implied, 4),
+        0x28 => entry("-", "", Implied, 4),
+        0x29 => entry("DAD", "H", Implied, 10),
+        0x2a => entry("LHLD", "", Word, 16),
+        0x2b => entry("DCX", "H", Implied, 5),
+        0x2c => entry("INR", "L", Implied, 5),
+        0x2d => entry("DCR", "L", Implied, 5),
+        0x2e => entry("MVI", "L", Byte, 7),
+        0x2f => entry("CMA", "", Implied, 4),
+        0x30 => entry("-", "", Implied, 4),
+        0x31 => entry("LXI", "SP", Word, 10),
+        0x32 => entry("STA", "", Word, 13),
+        0x33 => entry("INX", "SP", Implied, 5),
+        0x34 => entry("INR", "M", Implied, 10),
+        0x35 => entry("DCR", "M", Implied, 10),
+        0x36 => entry("MVI", "M", Byte, 10),
+        0x37 => entry("STC", "", Implied, 4),
+        0x38 => entry("-", "", Implied, 4),
+        0x39 => entry("DAD", "SP", Implied, 10),
+        0x3a => entry("LDA", "", Word, 13),
+        0x3b => entry("DCX", "SP", Implied, 5),
+        0x3c => entry("INR", "A", Implied, 5),
+        0x3d => entry("DCR", "A", Implied, 5),
+        0x3e => entry("MVI", "A", Byte, 7),
+        0x3f => entry("CMC", "", Implied, 4),
+        0x40 => entry("MOV", "B,B", Implied, 5),
+        0x41 => entry("MOV", "B,C", Implied, 5),
+        0x42 => entry("MOV", "B,D", Implied, 5),
+        0x43 => entry("MOV", "B,E", Implied, 5),
+        0x44 => entry("MOV", "B,H", Implied, 5),
+        0x45 => entry("MOV", "B,L", Implied, 5),
+        0x46 => entry("MOV", "B,M", Implied, 7),
+        0x47 => entry("MOV", "B,A", Implied, 5),
+        0x48 => entry("MOV", "C,B", Implied, 5),
+        0x49 => entry("MOV", "C,C", Implied, 5),
+        0x4a => entry("MOV", "C,D", Implied, 5),
+        0x4b => entry("MOV", "C,E", Implied, 5),
+        0x4c => entry("MOV", "C,H", Implied, 5),
+        0x4d => entry("MOV", "C,L", Implied, 5),
+        0x4e => entry("MOV", "C,M", Implied, 7),
+        0x4f => entry("MOV", "C,A", Implied, 5),
+        0x50 => entry("MOV", "D,B", Implied, 5),
+        0x51 => entry("MOV", "D,C", Implied, 5),
+        0x52 => entry("MOV", "D,D", Implied, 5),
+        0x53 => entry("MOV", "D,E", Implied, 5),
+        0x54 => entry("MOV", "D,H", Implied, 5),
+        0x55 => entry("MOV", "D,L", Implied, 5),
+        0x56 => entry("MOV", "D,M", Implied, 7),
+        0x57 => entry("MOV", "D,A", Implied, 5),
+        0x58 => entry("MOV", "E,B", Implied, 5),
+        0x59 => entry("MOV", "E,C", Implied, 5),
+        0x5a => entry("MOV", "E,D", Implied, 5),
+        0x5b => entry("MOV", "E,E", Implied, 5),
+        0x5c => entry("MOV", "E,H", Implied, 5),
+        0x5d => entry("MOV", "E,L", Implied, 5),
+        0x5e => entry("MOV", "E,M", Implied, 7),
+        0x5f => entry("MOV", "E,A", Implied, 5),
+        0x60 => entry("MOV", "H,B", Implied, 5),
+        0x61 => entry("MOV", "H,C", Implied, 5),
+        0x62 => entry("MOV", "H,D", Implied, 5),
+        0x63 => entry("MOV", "H,E", Implied, 5),
+        0x64 => entry("MOV", "H,H", Implied, 5),
+        0x65 => entry("MOV", "H,L", Implied, 5),
+        0x66 => entry("MOV", "H,M", Implied, 7),
+        0x67 => entry("MOV", "H,A", Implied, 5),
+        0x68 => entry("MOV", "L,B", Implied, 5),
+        0x69 => entry("MOV", "L,C", Implied, 5),
+        0x6a => entry("MOV", "L,D", Implied, 5),
+        0x6b => entry("MOV", "L,E", Implied, 5),
+        0x6c => entry("MOV", "L,H", Implied, 5),
+        0x6d => entry("MOV", "L,L", Implied, 5),
+        0x6e => entry("MOV", "L,M", Implied, 7),
+        0x6f => entry("MOV", "L,A", Implied, 5),
+        0x70 => entry("MOV", "M,B", Implied, 7),
+        0x71 => entry("MOV", "M,C", Implied, 7),
+        0x72 => entry("MOV", "M,D", Implied, 7),
+        0x73 => entry("MOV", "M,E", Implied, 7),
+        0x74 => entry("MOV", "M,H", Implied, 7),
+        0x75 => entry("MOV", "M,L", Implied, 7),
+        0x76 => entry("HLT", "", Implied, 7),
+        0x77 => entry("MOV", "M,A", Implied, 7),
+        0x78 => entry("MOV", "A,B", Implied, 5),
+        0x79 => entry("MOV", "A,C", Implied, 5),
+        0x7a => entry("MOV", "A,D", Implied, 5),
+        0x7b => entry("MOV", "A,E", Implied, 5),
+        0x7c => entry("MOV", "A,H", Implied, 5),
+        0x7d => entry("MOV", "A,L", Implied, 5),
+        0x7e => entry("MOV", "A,M", Implied, 7),
+        0x7f => entry("MOV", "A,A", Implied, 5),
+        0x80 => entry("ADD", "B", Implied, 4),
+        0x81 => entry("ADD", "C", Implied, 4),
+        0x82 => entry("ADD", "D", Implied, 4),
+        0x83 => entry("ADD", "E", Implied, 4),
+        0x84 => entry("ADD", "H", Implied, 4),
+        0x85 => entry("ADD", "L", Implied, 4),
+        0x86 => entry("ADD", "M", Implied, 7),
+        0x87 => entry("ADD", "A", Implied, 4),
+        0x88 => entry("ADC", "B", Implied, 4),
+        0x89 => entry("ADC", "C", Implied, 4),
+        0x8a => entry("ADC", "D", Implied, 4),
+        0x8b => entry("ADC", "E", Implied, 4),
+        0x8c => entry("ADC", "H", Implied, 4),
+        0x8d => entry("ADC", "L", Implied, 4),
+        0x8e => entry("ADC", "M", Implied, 7),
+        0x8f => entry("ADC", "A", Implied, 4),
+        0x90 => entry("SUB", "B", Implied, 4),
+        0x91 => entry("SUB", "C", Implied, 4),
+        0x92 => entry("SUB", "D", Implied, 4),
+        0x93 => entry("SUB", "E", Implied, 4),
+        0x94 => entry("SUB", "H", Implied, 4),
+        0x95 => entry("SUB", "L", Implied, 4),
+        0x96 => entry("SUB", "M", Implied, 7),
+        0x97 => entry("SUB", "A", Implied, 4),
+        0x98 => entry("SBB", "B", Implied, 4),
+        0x99 => entry("SBB", "C", Implied, 4),
+        0x9a => entry("SBB", "D", Implied, 4),
+        0x9b => entry("SBB", "E", Implied, 4),
+        0x9c => entry("SBB", "H", Implied, 4),
+        0x9d => entry("SBB", "L", Implied, 4),
+        0x9e => entry("SBB", "M", Implied, 7),
+        0x9f => entry("SBB", "A", Implied, 4),
+        0xa0 => entry("ANA", "B", Implied, 4),
+        0xa1 => entry("ANA", "C", Implied, 4),
+        0xa2 => entry("ANA", "D", Implied, 4),
+        0xa3 => entry("ANA", "E", Implied, 4),
+        0xa4 => entry("ANA", "H", Implied, 4),
+        0xa5 => entry("ANA", "L", Implied, 4),
+        0xa6 => entry("ANA", "M", Implied, 7),
+        0xa7 => entry("ANA", "A", Implied, 4),
+        0xa8 => entry("XRA", "B", Implied, 4),
+        0xa9 => entry("XRA", "C", Implied, 4),
+        0xaa => entry("XRA", "D", Implied, 4),
+        0xab => entry("XRA", "E", Implied, 4),
+        0xac => entry("XRA", "H", Implied, 4),
+        0xad => entry("XRA", "L", Implied, 4),
+        0xae => entry("XRA", "M", Implied, 7),
+        0xaf => entry("XRA", "A", Implied, 4),
+        0xb0 => entry("ORA", "B", Implied, 4),
+        0xb1 => entry("ORA", "C", Implied, 4),
+        0xb2 => entry("ORA", "D", Implied, 4),
+        0xb3 => entry("ORA", "E", Implied, 4),
+        0xb4 => entry("ORA", "H", Implied, 4),
+        0xb5 => entry("ORA", "L", Implied, 4),
+        0xb6 => entry("ORA", "M", Implied, 7),
+        0xb7 => entry("ORA", "A", Implied, 4),
+        0xb8 => entry("CMP", "B", Implied, 4),
+        0xb9 => entry("CMP", "C", Implied, 4),
+        0xba => entry("CMP", "D", Implied, 4),
+        0xbb => entry("CMP", "E", Implied, 4),
+        0xbc => entry("CMP", "H", Implied, 4),
+        0xbd => entry("CMP", "L", Implied, 4),
+        0xbe => entry("CMP", "M", Implied, 7),
+        0xbf => entry("CMP", "A", Implied, 4),
+        0xc0 => entry("RNZ", "", Implied, 5),
+        0xc1 => entry("POP", "B", Implied, 10),
+        0xc2 => entry("JNZ", "", Word, 10),
+        0xc3 => entry("JMP", "", Word, 10),
+        0xc4 => entry("CNZ", "", Word, 11),
+        0xc5 => entry("PUSH", "B", Implied, 11),
+        0xc6 => entry("ADI", "", Byte, 7),
+        0xc7 => entry("RST", "0", Implied, 11),
+        0xc8 => entry("RZ", "", Implied, 5),
+        0xc9 => entry("RET", "", Implied, 10),
+        0xca => entry("JZ", "", Word, 10),
+        0xcb => entry("-", "", Implied, 4),
+        0xcc => entry("CZ", "", Word, 11),
+        0xcd => entry("CALL", "", Word, 17),
+        0xce => entry("ACI", "", Byte, 7),
+        0xcf => entry("RST", "1", Implied, 11),
+        0xd0 => entry("RNC", "", Implied, 5),
+        0xd1 => entry("POP", "D", Implied, 10),
+        0xd2 => entry("JNC", "", Word, 10),
+        0xd3 => entry("OUT", "", Byte, 10),
+        0xd4 => entry("CNC", "", Word, 11),
+        0xd5 => entry("PUSH", "D", Implied, 11),
+        0xd6 => entry("SUI", "", Byte, 7),
+        0xd7 => entry("RST", "2", Implied, 11),
+        0xd8 => entry("RC", "", Implied, 5),
+        0xd9 => entry("-", "", Implied, 4),
+        0xda => entry("JC", "", Word, 10),
+        0xdb => entry("IN", "", Byte, 10),
+        0xdc => entry("CC", "", Word, 11),
+        0xdd => entry("-", "", Implied, 4),
+        0xde => entry("SBI", "", Byte, 7),
+        0xdf => entry("RST", "3", Implied, 11),
+        0xe0 => entry("RPO", "", Implied, 5),
+        0xe1 => entry("POP", "H", Implied, 10),
+        0xe2 => entry("JPO", "", Word, 10),
+        0xe3 => entry("XTHL", "", Implied, 18),
+        0xe4 => entry("CPO", "", Word, 11),
+        0xe5 => entry("PUSH", "H", Implied, 11),
+        0xe6 => entry("ANI", "", Byte, 7),
+        0xe7 => entry("RST", "4", Implied, 11),
+        0xe8 => entry("RPE", "", Implied, 5),
+        0xe9 => entry("PCHL", "", Implied, 5),
+        0xea => entry("JPE", "", Word, 10),
+        0xeb => entry("XCHG", "", Implied, 4),
+        0xec => entry("CPE", "", Word, 11),
+        0xed => entry("-", "", Implied, 4),
+        0xee => entry("XRI", "", Byte, 7),
+        0xef => entry("RST", "5", Implied, 11),
+        0xf0 => entry("RP", "", Implied, 5),
+        0xf1 => entry("POP", "PSW", Implied, 10),
+        0xf2 => entry("JP", "", Word, 10),
+        0xf3 => entry("DI", "", Implied, 4),
+        0xf4 => entry("CP", "", Word, 11),
+        0xf5 => entry("PUSH", "PSW", Implied, 11),
+        0xf6 => entry("ORI", "", Byte, 7),
+        0xf7 => entry("RST", "6", Implied, 11),
+        0xf8 => entry("RM", "", Implied, 5),
+        0xf9 => entry("SPHL", "", Implied, 5),
+        0xfa => entry("JM", "", Word, 10),
+        0xfb => entry("EI", "", Implied, 4),
+        0xfc => entry("CM", "", Word, 11),
+        0xfd => entry("-", "", Implied, 4),
+        0xfe => entry("CPI", "", Byte, 7),
+        0xff => entry("RST", "7", Implied, 11),
+    }
+}
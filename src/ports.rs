@@ -0,0 +1,117 @@
+//! The devices wired to the CPU's I/O ports, which IN and OUT talk to.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+
+/// What IN reads from a port nothing answers: the data bus floats high.
+const OPEN_BUS: u8 = 0xff;
+
+pub trait Device: Any {
+    /// What the device is called, which is also its section in a save state.
+    fn name(&self) -> &'static str;
+
+    /// The value for IN from `port`, if the device answers it.
+    fn input(&mut self, port: u8) -> Option<u8>;
+
+    /// OUT to `port`, which the device ignores unless it listens there.
+    fn output(&mut self, port: u8, value: u8);
+
+    /// The RST the device asks for, now that the CPU has run for `cycles`. Asked before every
+    /// instruction, whether or not interrupts are enabled.
+    fn interrupt(&mut self, _cycles: u64) -> Option<u8> {
+        None
+    }
+
+    /// The CPU has taken the interrupt for `restart`, so a device holding its request
+    /// can let go.
+    fn acknowledge(&mut self, _restart: u8) {}
+
+    /// Whether the device pulls the CPU's reset line, now that it has run for `cycles`. Asked
+    /// before every instruction, like `interrupt`.
+    fn reset(&mut self, _cycles: u64) -> bool {
+        false
+    }
+
+    /// The reset line has been pulled at `cycles`, which resets the rest of the board along
+    /// with the CPU. Devices go back to how they were at power on.
+    fn reset_line(&mut self, _cycles: u64) {}
+
+    fn save(&self) -> Vec<u8>;
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), String>;
+}
+
+#[derive(Default)]
+pub struct Ports {
+    pub devices: Vec<Box<dyn Device>>,
+}
+
+impl Ports {
+    /// The device of type `T`, so its owner can get at more than the ports show.
+    pub fn find<T: Device>(&mut self) -> Option<&mut T> {
+        self.devices.iter_mut().find_map(|device| (device.as_mut() as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    pub fn input(&mut self, port: u8) -> u8 {
+        self.devices.iter_mut().find_map(|device| device.input(port)).unwrap_or(OPEN_BUS)
+    }
+
+    pub fn output(&mut self, port: u8, value: u8) {
+        for device in &mut self.devices {
+            device.output(port, value);
+        }
+    }
+
+    /// The first interrupt any device asks for.
+    pub fn interrupt(&mut self, cycles: u64) -> Option<u8> {
+        // Every device is asked, so that each can keep its own time.
+        let mut first = None;
+        for device in &mut self.devices {
+            let wanted = device.interrupt(cycles);
+            first = first.or(wanted);
+        }
+        first
+    }
+
+    /// Tells every device the CPU has taken the interrupt for `restart`.
+    pub fn acknowledge(&mut self, restart: u8) {
+        for device in &mut self.devices {
+            device.acknowledge(restart);
+        }
+    }
+
+    /// Resets every device, as the CPU is reset at `cycles`.
+    pub fn reset_all(&mut self, cycles: u64) {
+        for device in &mut self.devices {
+            device.reset_line(cycles);
+        }
+    }
+
+    /// Whether any device resets the CPU.
+    pub fn reset(&mut self, cycles: u64) -> bool {
+        let mut reset = false;
+        for device in &mut self.devices {
+            reset |= device.reset(cycles);
+        }
+        reset
+    }
+
+    /// Each device's state, for a save state.
+    pub fn save(&self) -> Vec<(&'static str, Vec<u8>)> {
+        self.devices.iter().map(|device| (device.name(), device.save())).collect()
+    }
+
+    /// Puts back the state saved for each device. Devices missing from `sections` were added
+    /// since it was saved, so they are left as they are.
+    pub fn restore(&mut self, sections: &BTreeMap<String, Vec<u8>>) -> Result<(), String> {
+        if let Some(name) = sections.keys().find(|name| !self.devices.iter().any(|device| device.name() == name.as_str())) {
+            return Err(format!("there is no {} device to restore", name))
+        }
+        for device in &mut self.devices {
+            if let Some(bytes) = sections.get(device.name()) {
+                device.restore(bytes).map_err(|error| format!("{}: {}", device.name(), error))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+//! Microsoft's relocatable object format, as written by M80 and read by LINK-80. A file is
+//! a stream of bits, most significant first, made of items:
+//!
+//! ```text
+//! 0 bbbbbbbb                        an absolute byte
+//! 1 01 llllllll hhhhhhhh            a word relative to the code segment
+//! 1 10 llllllll hhhhhhhh            a word relative to the data segment
+//! 1 11 llllllll hhhhhhhh            a word relative to a COMMON block
+//! 1 00 cccc [A field] [B field]     a special link item, with control code c
+//! ```
+//!
+//! where an A field is a two bit segment followed by a word and a B field is a three bit
+//! length followed by that many characters.
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Segment {
+    Absolute,
+    Code,
+    Data,
+}
+
+/// A place in a module, before it is known where the linker will put its segments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub segment: Segment,
+    pub offset: u16,
+}
+
+impl Address {
+    pub fn absolute(offset: u16) -> Address {
+        Address { segment: Segment::Absolute, offset }
+    }
+
+    pub fn next(&self, count: u16) -> Address {
+        Address { segment: self.segment, offset: self.offset.wrapping_add(count) }
+    }
+}
+
+/// What the words along a chain are filled in with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Chain {
+    External(String),
+    Location(Address),
+}
+
+/// One assembled module, as the linker sees it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub code_size: u16,
+    pub data_size: u16,
+    pub bytes: BTreeMap<Address, u8>,
+    /// Words holding an address in one of the module's segments, which need the segment's
+    /// final base adding to them.
+    pub relocations: BTreeMap<Address, Segment>,
+    /// Words to fill in once a value is known, by the last word in each chain. Each word in a
+    /// chain holds the address of the one before, and the first holds absolute zero.
+    pub chains: Vec<(Address, Chain)>,
+    /// Amounts to add to the external symbol referred to by the word at an address.
+    pub offsets: BTreeMap<Address, i32>,
+    pub publics: BTreeMap<String, Address>,
+    pub entry: Option<Address>,
+}
+
+// Special link items.
+const ENTRY_SYMBOL: u32 = 0;
+const SELECT_COMMON: u32 = 1;
+const PROGRAM_NAME: u32 = 2;
+const LIBRARY_SEARCH: u32 = 3;
+const EXTENSION: u32 = 4;
+const COMMON_SIZE: u32 = 5;
+const CHAIN_EXTERNAL: u32 = 6;
+const ENTRY_POINT: u32 = 7;
+const EXTERNAL_MINUS: u32 = 8;
+const EXTERNAL_PLUS: u32 = 9;
+const DATA_SIZE: u32 = 10;
+const SET_LOCATION: u32 = 11;
+const CHAIN_ADDRESS: u32 = 12;
+const PROGRAM_SIZE: u32 = 13;
+const END_PROGRAM: u32 = 14;
+const END_FILE: u32 = 15;
+
+/// Names longer than this are cut short; LINK-80 only ever compares this many characters.
+pub const NAME_LENGTH: usize = 7;
+
+fn segment_bits(segment: Segment) -> u32 {
+    match segment {
+        Segment::Absolute => 0,
+        Segment::Code => 1,
+        Segment::Data => 2,
+    }
+}
+
+pub fn name(name: &str) -> String {
+    name.to_uppercase().chars().take(NAME_LENGTH).collect()
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl Writer {
+    fn bits(&mut self, value: u32, count: usize) {
+        for bit in (0..count).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if value >> bit & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+
+    fn word(&mut self, value: u16) {
+        self.bits(value as u32 & 0xff, 8);
+        self.bits(value as u32 >> 8, 8);
+    }
+
+    fn special(&mut self, control: u32, a: Option<Address>, b: Option<&str>) {
+        self.bits(0b100, 3);
+        self.bits(control, 4);
+        if let Some(address) = a {
+            self.bits(segment_bits(address.segment), 2);
+            self.word(address.offset);
+        }
+        if let Some(text) = b {
+            let text = name(text);
+            self.bits(text.len() as u32, 3);
+            for c in text.bytes() {
+                self.bits(c as u32, 8);
+            }
+        }
+    }
+
+    fn align(&mut self) {
+        self.bits = self.bytes.len() * 8;
+    }
+}
+
+pub fn write(module: &Module) -> Vec<u8> {
+    let mut writer = Writer { bytes: vec![], bits: 0 };
+
+    // Entry symbols come first so that a library search can see what the module defines
+    // without reading all of it.
+    for name in module.publics.keys() {
+        writer.special(ENTRY_SYMBOL, None, Some(name));
+    }
+    writer.special(PROGRAM_NAME, None, Some(&module.name));
+    writer.special(DATA_SIZE, Some(Address::absolute(module.data_size)), None);
+    writer.special(PROGRAM_SIZE, Some(Address { segment: Segment::Code, offset: module.code_size }), None);
+
+    let mut location = None;
+    let mut skip = false;
+    for (&address, &byte) in &module.bytes {
+        if skip {
+            skip = false;
+            continue
+        }
+        if location != Some(address) {
+            writer.special(SET_LOCATION, Some(address), None);
+        }
+        if let Some(&offset) = module.offsets.get(&address) {
+            let control = if offset < 0 { EXTERNAL_MINUS } else { EXTERNAL_PLUS };
+            writer.special(control, Some(Address::absolute(offset.unsigned_abs() as u16)), None);
+        }
+
+        let high = module.bytes.get(&address.next(1)).cloned().unwrap_or(0);
+        match module.relocations.get(&address) {
+            Some(&segment) => {
+                writer.bits(0b100 | segment_bits(segment), 3);
+                writer.word(((high as u16) << 8) | byte as u16);
+                skip = true;
+                location = Some(address.next(2));
+            },
+            None => {
+                writer.bits(0, 1);
+                writer.bits(byte as u32, 8);
+                location = Some(address.next(1));
+            },
+        }
+    }
+
+    for (name, &address) in &module.publics {
+        writer.special(ENTRY_POINT, Some(address), Some(name));
+    }
+    for &(head, ref chain) in &module.chains {
+        match *chain {
+            Chain::External(ref name) => writer.special(CHAIN_EXTERNAL, Some(head), Some(name)),
+            Chain::Location(target) => {
+                writer.special(SET_LOCATION, Some(target), None);
+                writer.special(CHAIN_ADDRESS, Some(head), None);
+            },
+        }
+    }
+
+    writer.special(END_PROGRAM, Some(module.entry.unwrap_or(Address::absolute(0))), None);
+    writer.align();
+    writer.special(END_FILE, None, None);
+    writer.align();
+    writer.bytes
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bits(&mut self, count: usize) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self.bytes.get(self.bit / 8).ok_or_else(|| "unexpected end of file".to_string())?;
+            value = value << 1 | (byte >> (7 - self.bit % 8) & 1) as u32;
+            self.bit += 1;
+        }
+        Ok(value)
+    }
+
+    fn word(&mut self) -> Result<u16, String> {
+        let low = self.bits(8)?;
+        let high = self.bits(8)?;
+        Ok((high << 8 | low) as u16)
+    }
+
+    fn address(&mut self) -> Result<Address, String> {
+        let segment = match self.bits(2)? {
+            0 => Segment::Absolute,
+            1 => Segment::Code,
+            2 => Segment::Data,
+            _ => return Err("COMMON blocks aren't supported".to_string()),
+        };
+        Ok(Address { segment, offset: self.word()? })
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let length = self.bits(3)?;
+        let mut name = String::new();
+        for _ in 0..length {
+            name.push((self.bits(8)? & 0x7f) as u8 as char);
+        }
+        Ok(name)
+    }
+
+    fn align(&mut self) {
+        self.bit = self.bit.div_ceil(8) * 8;
+    }
+}
+
+/// Reads every module in a REL file. Libraries are just several modules one after another.
+pub fn read(bytes: &[u8]) -> Result<Vec<Module>, String> {
+    let mut reader = Reader { bytes, bit: 0 };
+    let mut modules = vec![];
+    let mut module = Module::default();
+    let mut location = Address { segment: Segment::Code, offset: 0 };
+
+    loop {
+        // Some files are padded out to a whole CP/M record after their last module.
+        let fresh = module.bytes.is_empty() && module.name.is_empty() && reader.bit.is_multiple_of(8);
+        if reader.bit / 8 >= bytes.len() || fresh && bytes[reader.bit / 8] == 0x1a {
+            return Ok(modules)
+        }
+
+        if reader.bits(1)? == 0 {
+            module.bytes.insert(location, reader.bits(8)? as u8);
+            location = location.next(1);
+            continue
+        }
+
+        let kind = reader.bits(2)?;
+        if kind != 0 {
+            let segment = match kind {
+                1 => Segment::Code,
+                2 => Segment::Data,
+                _ => return Err(format!("{}: COMMON blocks aren't supported", module.name)),
+            };
+            let value = reader.word()?;
+            module.bytes.insert(location, value as u8);
+            module.bytes.insert(location.next(1), (value >> 8) as u8);
+            module.relocations.insert(location, segment);
+            location = location.next(2);
+            continue
+        }
+
+        let control = reader.bits(4)?;
+        let a = match control {
+            COMMON_SIZE..=END_PROGRAM => Some(reader.address()?),
+            _ => None,
+        };
+        let b = match control {
+            ENTRY_SYMBOL..=CHAIN_EXTERNAL | ENTRY_POINT => Some(reader.name()?),
+            _ => None,
+        };
+        let (a, b) = (a.unwrap_or(Address::absolute(0)), b.unwrap_or_default());
+
+        match control {
+            // Only there to speed up library searches, which read ENTRY_POINT instead.
+            ENTRY_SYMBOL | LIBRARY_SEARCH => {},
+            SELECT_COMMON | COMMON_SIZE => return Err(format!("{}: COMMON blocks aren't supported", module.name)),
+            PROGRAM_NAME => module.name = b,
+            EXTENSION => return Err(format!("{}: extension link items aren't supported", module.name)),
+            CHAIN_EXTERNAL => module.chains.push((a, Chain::External(b))),
+            ENTRY_POINT => {
+                module.publics.insert(b, a);
+            },
+            EXTERNAL_MINUS => {
+                module.offsets.insert(location, -(a.offset as i32));
+            },
+            EXTERNAL_PLUS => {
+                module.offsets.insert(location, a.offset as i32);
+            },
+            DATA_SIZE => module.data_size = a.offset,
+            SET_LOCATION => location = a,
+            CHAIN_ADDRESS => module.chains.push((a, Chain::Location(location))),
+            PROGRAM_SIZE => module.code_size = a.offset,
+            END_PROGRAM => {
+                if a != Address::absolute(0) {
+                    module.entry = Some(a);
+                }
+                modules.push(module);
+                module = Module::default();
+                location = Address { segment: Segment::Code, offset: 0 };
+                reader.align();
+            },
+            _ => return Ok(modules),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(offset: u16) -> Address {
+        Address { segment: Segment::Code, offset }
+    }
+
+    fn data(offset: u16) -> Address {
+        Address { segment: Segment::Data, offset }
+    }
+
+    // LXI H,MESSAGE; CALL PRINT; CALL PRINT+3; JMP START, then a message and a pointer back
+    // to the code in the data segment.
+    fn module() -> Module {
+        let mut module = Module { name: "MAIN".to_string(), code_size: 12, data_size: 5, ..Module::default() };
+        let code_bytes = [0x21, 0x00, 0x00, 0xcd, 0x00, 0x00, 0xcd, 0x03, 0x00, 0xc3, 0x00, 0x00];
+        for (offset, &byte) in code_bytes.iter().enumerate() {
+            module.bytes.insert(code(offset as u16), byte);
+        }
+        for (offset, &byte) in [b'H', b'I', 0, 0x00, 0x00].iter().enumerate() {
+            module.bytes.insert(data(offset as u16), byte);
+        }
+        module.relocations.insert(code(1), Segment::Data);
+        module.relocations.insert(code(7), Segment::Code);
+        module.relocations.insert(code(10), Segment::Code);
+        module.relocations.insert(data(3), Segment::Code);
+        module.chains.push((code(7), Chain::External("PRINT".to_string())));
+        module.chains.push((Address::absolute(0x40), Chain::Location(data(0))));
+        module.offsets.insert(code(7), 3);
+        module.offsets.insert(code(4), -2);
+        module.publics.insert("START".to_string(), code(0));
+        module.publics.insert("MESSAGE".to_string(), data(0));
+        module.entry = Some(code(0));
+        module
+    }
+
+    #[test]
+    fn modules_survive_writing_and_reading() {
+        let module = module();
+        assert_eq!(read(&write(&module)), Ok(vec![module]));
+    }
+
+    #[test]
+    fn libraries_are_modules_one_after_another() {
+        let first = module();
+        let second = Module { name: "EMPTY".to_string(), ..Module::default() };
+        // A library has one end of file mark, after its last module, where each written
+        // module has its own in its last byte.
+        let mut bytes = write(&first);
+        bytes.pop();
+        bytes.extend(write(&second));
+        // CP/M pads the last record out with ^Z.
+        bytes.extend([0x1a; 8].iter());
+        assert_eq!(read(&bytes), Ok(vec![first, second]));
+    }
+
+    #[test]
+    fn names_are_cut_short_and_upper_cased() {
+        assert_eq!(name("longname"), "LONGNAM");
+        let module = Module { name: name("printer"), ..Module::default() };
+        assert_eq!(read(&write(&module)).unwrap()[0].name, "PRINTER");
+    }
+
+    #[test]
+    fn common_blocks_are_refused() {
+        let mut writer = Writer { bytes: vec![], bits: 0 };
+        writer.special(PROGRAM_NAME, None, Some("MAIN"));
+        writer.special(SELECT_COMMON, None, Some("BLOCK"));
+        assert_eq!(read(&writer.bytes), Err("MAIN: COMMON blocks aren't supported".to_string()));
+
+        let mut writer = Writer { bytes: vec![], bits: 0 };
+        writer.special(PROGRAM_NAME, None, Some("MAIN"));
+        writer.bits(0b111, 3);
+        writer.word(0x1234);
+        assert_eq!(read(&writer.bytes), Err("MAIN: COMMON blocks aren't supported".to_string()));
+
+        let mut writer = Writer { bytes: vec![], bits: 0 };
+        writer.special(PROGRAM_NAME, None, Some("MAIN"));
+        writer.bits(0b100, 3);
+        writer.bits(SET_LOCATION, 4);
+        writer.bits(0b11, 2);
+        writer.word(0);
+        assert_eq!(read(&writer.bytes), Err("COMMON blocks aren't supported".to_string()));
+    }
+
+    #[test]
+    fn a_truncated_file_is_an_error() {
+        let bytes = write(&module());
+        assert_eq!(read(&bytes[..bytes.len() / 2]), Err("unexpected end of file".to_string()));
+    }
+}
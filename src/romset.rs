@@ -0,0 +1,206 @@
+//! Arcade boards keep their program in several ROM chips, which are dumped one file each.
+//! A ROM set is a directory or ZIP archive of those files.
+
+use std::fs;
+use std::path::Path;
+
+use checksum;
+use loader::{Block, Image, Memory};
+use zip;
+
+/// One chip, and the checksums a good dump of it has.
+#[derive(Clone, Copy, Debug)]
+pub struct Rom {
+    pub name: &'static str,
+    pub address: u16,
+    pub size: usize,
+    pub crc32: u32,
+    pub sha1: &'static str,
+}
+
+/// The Midway Space Invaders board, with its four 2K ROMs.
+pub const INVADERS: [Rom; 4] = [
+    Rom { name: "invaders.h", address: 0x0000, size: 0x800, crc32: 0x734f_5ad8, sha1: "ff6200af4c9110d8181249cbcef1a8a40fa40b7f" },
+    Rom { name: "invaders.g", address: 0x0800, size: 0x800, crc32: 0x6bfa_ca4a, sha1: "16f48649b531bdef8c2d1446c429b5f414524350" },
+    Rom { name: "invaders.f", address: 0x1000, size: 0x800, crc32: 0x0cce_ad96, sha1: "537aef03468f63c5b9e11dd61e253f7ae17d9743" },
+    Rom { name: "invaders.e", address: 0x1800, size: 0x800, crc32: 0x14e5_38b0, sha1: "1d6ca0c99f9df71e2990b610deb9d7da0125e2d8" },
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    Good,
+    Missing,
+    /// The file isn't the size of the chip, so it wasn't loaded.
+    WrongSize(usize),
+    /// The file is the right size but its checksums are wrong. It is loaded anyway, since it
+    /// may be a deliberate patch.
+    BadDump { crc32: u32, sha1: String },
+}
+
+pub struct Report {
+    pub roms: Vec<(Rom, Status)>,
+}
+
+impl Report {
+    /// Whether every chip was loaded, even if some dumps are bad.
+    pub fn complete(&self) -> bool {
+        self.roms.iter().all(|(_, status)| !matches!(status, Status::Missing | Status::WrongSize(_)))
+    }
+
+    pub fn verified(&self) -> bool {
+        self.roms.iter().all(|(_, status)| *status == Status::Good)
+    }
+
+    /// A line for each chip, saying where it goes and what was wrong with it, if anything.
+    pub fn describe(&self) -> String {
+        let mut report = String::new();
+        for (rom, status) in &self.roms {
+            let status = match *status {
+                Status::Good => "ok".to_string(),
+                Status::Missing => "missing".to_string(),
+                Status::WrongSize(size) => format!("wrong size: {} bytes, expected {}", size, rom.size),
+                Status::BadDump { crc32, ref sha1 } => format!(
+                    "bad dump: crc32 {:08x} sha1 {}, expected crc32 {:08x} sha1 {}", crc32, sha1, rom.crc32, rom.sha1),
+            };
+            report.push_str(&format!("{:<12}  {:04x}-{:04x}  {}\n", rom.name, rom.address, rom.address as usize + rom.size - 1, status));
+        }
+        report
+    }
+}
+
+/// Whether `path` looks like a ROM set rather than a single image.
+pub fn is_romset(path: &str) -> bool {
+    let path = Path::new(path);
+    path.is_dir() || path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+// The files in a directory or archive, by their names without any directories.
+fn files(path: &str) -> Result<Vec<(String, Vec<u8>)>, String> {
+    if Path::new(path).is_dir() {
+        let mut files = vec![];
+        for entry in fs::read_dir(path).map_err(|error| format!("{}: {}", path, error))? {
+            let entry = entry.map_err(|error| format!("{}: {}", path, error))?;
+            if entry.path().is_file() {
+                let bytes = fs::read(entry.path()).map_err(|error| format!("{}: {}", entry.path().display(), error))?;
+                files.push((entry.file_name().to_string_lossy().into_owned(), bytes));
+            }
+        }
+        return Ok(files)
+    }
+
+    let bytes = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    let entries = zip::read(&bytes).map_err(|error| format!("{}: {}", path, error))?;
+    Ok(entries.into_iter().map(|entry| (entry.name.rsplit('/').next().unwrap_or_default().to_string(), entry.bytes)).collect())
+}
+
+/// Loads each of `roms` from the set at `path` into `memory`. Files are found by name,
+/// ignoring case, or failing that by CRC, since sets are often renamed.
+pub fn load(path: &str, roms: &[Rom], memory: &mut Memory) -> Result<Report, String> {
+    let files = files(path)?;
+    let mut report = Report { roms: vec![] };
+
+    for rom in roms {
+        let file = files.iter().find(|(name, _)| name.eq_ignore_ascii_case(rom.name))
+            .or_else(|| files.iter().find(|(_, bytes)| checksum::crc32(bytes) == rom.crc32));
+        let status = match file {
+            None => Status::Missing,
+            Some((_, bytes)) if bytes.len() != rom.size => Status::WrongSize(bytes.len()),
+            Some((name, bytes)) => {
+                let image = Image { blocks: vec![Block { address: rom.address as usize, bytes: bytes.clone() }], entry: None };
+                memory.load(&format!("{}/{}", path, name), image)?;
+
+                let (crc32, sha1) = (checksum::crc32(bytes), checksum::hex(&checksum::sha1(bytes)));
+                if crc32 == rom.crc32 && sha1 == rom.sha1 {
+                    Status::Good
+                } else {
+                    Status::BadDump { crc32, sha1 }
+                }
+            },
+        };
+        report.roms.push((*rom, status));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use zip::tests::archive;
+
+    // Three-byte chips, so that "abc" is a good dump of the first.
+    const ROMS: [Rom; 3] = [
+        Rom { name: "one.bin", address: 0x0000, size: 3, crc32: 0x3524_41c2, sha1: "a9993e364706816aba3e25717850c26c9cd0d89d" },
+        Rom { name: "two.bin", address: 0x0010, size: 3, crc32: 0x3524_41c2, sha1: "a9993e364706816aba3e25717850c26c9cd0d89d" },
+        Rom { name: "three.bin", address: 0x0020, size: 3, crc32: 0, sha1: "" },
+    ];
+
+    fn load_zip(name: &str, files: &[(&str, &[u8])]) -> (Result<Report, String>, Memory) {
+        let files: Vec<(&str, u16, &[u8], u32)> = files.iter().map(|&(name, bytes)| (name, 0, bytes, checksum::crc32(bytes))).collect();
+        let path = env::temp_dir().join(format!("rs8080-{}-{}.zip", name, std::process::id()));
+        fs::write(&path, archive(&files)).unwrap();
+        let mut memory = Memory::default();
+        let report = load(&path.to_string_lossy(), &ROMS, &mut memory);
+        fs::remove_file(&path).unwrap();
+        (report, memory)
+    }
+
+    fn statuses(report: &Report) -> Vec<Status> {
+        report.roms.iter().map(|(_, status)| status.clone()).collect()
+    }
+
+    #[test]
+    fn chips_are_found_by_name_and_checked() {
+        let (report, memory) = load_zip("names", &[("set/ONE.BIN", b"abc"), ("two.bin", b"xyz"), ("three.bin", b"toolong")]);
+        let report = report.unwrap();
+        assert_eq!(statuses(&report), [
+            Status::Good,
+            Status::BadDump { crc32: checksum::crc32(b"xyz"), sha1: checksum::hex(&checksum::sha1(b"xyz")) },
+            Status::WrongSize(7),
+        ]);
+        assert!(!report.complete() && !report.verified());
+        assert_eq!(memory.bytes[0..3], *b"abc");
+        assert_eq!(memory.bytes[0x10..0x13], *b"xyz", "bad dumps are loaded anyway");
+        assert_eq!(memory.bytes[0x20..0x23], [0; 3]);
+    }
+
+    #[test]
+    fn renamed_chips_are_found_by_crc() {
+        let (report, memory) = load_zip("crc", &[("renamed", b"abc")]);
+        let report = report.unwrap();
+        assert_eq!(statuses(&report), [Status::Good, Status::Good, Status::Missing]);
+        assert_eq!(memory.bytes[0x10..0x13], *b"abc");
+    }
+
+    #[test]
+    fn the_report_says_what_is_wrong() {
+        let (report, _) = load_zip("report", &[("one.bin", b"abc"), ("two.bin", b"xyz")]);
+        let description = report.unwrap().describe();
+        let lines: Vec<&str> = description.lines().collect();
+        assert_eq!(lines[0], "one.bin       0000-0002  ok");
+        assert!(lines[1].starts_with("two.bin       0010-0012  bad dump: crc32 "), "{}", lines[1]);
+        assert_eq!(lines[2], "three.bin     0020-0022  missing");
+    }
+
+    #[test]
+    fn a_corrupt_archive_is_an_error() {
+        let path = env::temp_dir().join(format!("rs8080-corrupt-{}.zip", std::process::id()));
+        fs::write(&path, archive(&[("one.bin", 0, b"abc", checksum::crc32(b"abd"))])).unwrap();
+        let error = load(&path.to_string_lossy(), &ROMS, &mut Memory::default()).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(error.ends_with(".zip: one.bin: CRC doesn't match the archive's"), "{}", error);
+    }
+
+    #[test]
+    fn directories_are_sets_too() {
+        let directory = env::temp_dir().join(format!("rs8080-romset-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("One.Bin"), b"abc").unwrap();
+        let path = directory.to_string_lossy().into_owned();
+        assert!(is_romset(&path) && is_romset("set.ZIP") && !is_romset("set.bin"));
+        let mut memory = Memory::default();
+        let report = load(&path, &ROMS, &mut memory);
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(statuses(&report.unwrap()), [Status::Good, Status::Good, Status::Missing]);
+    }
+}
@@ -0,0 +1,279 @@
+//! Snapshots of a whole machine, to resume later or pass on with a bug report. A file is
+//! a header followed by named sections:
+//!
+//! ```text
+//! "RS8080SS"  u16 version  u8 length + machine name
+//! u8 length + section name  u32 length + section contents
+//! ...
+//! ```
+//!
+//! Numbers are little-endian. Each device saves its own section, named after it, so
+//! machines can gain devices without changing the format.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use cpu::State;
+use machine::Machine;
+
+const MAGIC: &[u8; 8] = b"RS8080SS";
+
+/// The version this build writes. Older files are upgraded to it as they are read.
+pub const VERSION: u16 = 1;
+
+const CPU: &str = "cpu";
+const MEMORY: &str = "memory";
+
+type Sections = BTreeMap<String, Vec<u8>>;
+
+type Upgrade = fn(&mut Sections) -> Result<(), String>;
+
+/// Each function upgrades the sections of one version to the next: the first turns version
+/// 1 into 2, and so on.
+const UPGRADES: [Upgrade; VERSION as usize - 1] = [];
+
+pub struct Snapshot {
+    pub machine: Machine,
+    pub state: State,
+    /// The saved state of each device, by name.
+    pub devices: BTreeMap<String, Vec<u8>>,
+}
+
+fn put_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.push(name.len() as u8);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+fn put_section(bytes: &mut Vec<u8>, name: &str, contents: &[u8]) {
+    put_name(bytes, name);
+    bytes.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(contents);
+}
+
+pub fn save(machine: Machine, state: &State, devices: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    put_name(&mut bytes, machine.name());
+
+    let mut cpu = vec![state.a, state.b, state.c, state.d, state.e, state.h, state.l, state.flags];
+    cpu.extend_from_slice(&state.pc.to_le_bytes());
+    cpu.extend_from_slice(&state.sp.to_le_bytes());
+    cpu.extend_from_slice(&state.cycles.to_le_bytes());
+    cpu.push(state.interrupts as u8);
+    put_section(&mut bytes, CPU, &cpu);
+    put_section(&mut bytes, MEMORY, &state.memory);
+
+    for &(name, ref contents) in devices {
+        put_section(&mut bytes, name, contents);
+    }
+    bytes
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let taken = self.bytes.get(self.at..self.at + count).ok_or_else(|| "save state is truncated".to_string())?;
+        self.at += count;
+        Ok(taken)
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let length = self.take(1)?[0] as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+}
+
+/// Brings the sections of a `version` file up to date, with `upgrades` laid out like
+/// `UPGRADES`.
+fn upgrade(sections: &mut Sections, version: u16, upgrades: &[Upgrade]) -> Result<(), String> {
+    for upgrade in &upgrades[version as usize - 1..] {
+        upgrade(sections)?;
+    }
+    Ok(())
+}
+
+fn cpu(state: &mut State, bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() != 21 {
+        return Err(format!("cpu section is {} bytes, expected 21", bytes.len()))
+    }
+    state.a = bytes[0];
+    state.b = bytes[1];
+    state.c = bytes[2];
+    state.d = bytes[3];
+    state.e = bytes[4];
+    state.h = bytes[5];
+    state.l = bytes[6];
+    state.flags = bytes[7];
+    state.pc = u16::from_le_bytes([bytes[8], bytes[9]]);
+    state.sp = u16::from_le_bytes([bytes[10], bytes[11]]);
+    let mut cycles = [0; 8];
+    cycles.copy_from_slice(&bytes[12..20]);
+    state.cycles = u64::from_le_bytes(cycles);
+    state.interrupts = bytes[20] != 0;
+    Ok(())
+}
+
+pub fn load(bytes: &[u8]) -> Result<Snapshot, String> {
+    let mut reader = Reader { bytes, at: 0 };
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err("not a save state".to_string())
+    }
+    let version = reader.take(2).map(|version| u16::from_le_bytes([version[0], version[1]]))?;
+    if version == 0 {
+        return Err("save state has no version".to_string())
+    }
+    if version > VERSION {
+        return Err(format!("save state version {} is newer than this build understands ({})", version, VERSION))
+    }
+    let name = reader.name()?;
+    let machine = Machine::named(&name).ok_or_else(|| format!("save state is for an unknown machine: {}", name))?;
+
+    let mut sections = Sections::new();
+    while reader.at < bytes.len() {
+        let name = reader.name()?;
+        let length = reader.take(4).map(|length| u32::from_le_bytes([length[0], length[1], length[2], length[3]]))?;
+        sections.insert(name, reader.take(length as usize)?.to_vec());
+    }
+    upgrade(&mut sections, version, &UPGRADES)?;
+
+    let mut state = State::new(&[0; 0x10000], 0);
+    state.map = machine.memory_map();
+    cpu(&mut state, &sections.remove(CPU).ok_or_else(|| "save state has no cpu section".to_string())?)?;
+    let memory = sections.remove(MEMORY).ok_or_else(|| "save state has no memory section".to_string())?;
+    if memory.len() != state.memory.len() {
+        return Err(format!("memory section is {} bytes, expected {}", memory.len(), state.memory.len()))
+    }
+    state.memory.copy_from_slice(&memory);
+
+    Ok(Snapshot { machine, state, devices: sections })
+}
+
+pub fn write_file(path: &str, machine: Machine, state: &State, devices: &[(&str, Vec<u8>)]) -> Result<(), String> {
+    fs::write(path, save(machine, state, devices)).map_err(|error| format!("{}: {}", path, error))
+}
+
+pub fn read_file(path: &str) -> Result<Snapshot, String> {
+    let bytes = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+    load(&bytes).map_err(|error| format!("{}: {}", path, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        let mut memory = vec![0; 0x10000];
+        for (address, byte) in memory.iter_mut().enumerate() {
+            *byte = (address * 7 % 251) as u8;
+        }
+        let mut state = State::new(&memory, 0x1234);
+        state.a = 1;
+        state.b = 2;
+        state.c = 3;
+        state.d = 4;
+        state.e = 5;
+        state.h = 6;
+        state.l = 7;
+        state.flags = 0x83;
+        state.sp = 0x2400;
+        state.cycles = 0x1_2345_6789;
+        state.interrupts = true;
+        state
+    }
+
+    #[test]
+    fn snapshots_survive_saving_and_loading() {
+        let saved = state();
+        let devices = [("shifter", vec![1, 2, 3]), ("sound", vec![])];
+        let snapshot = load(&save(Machine::Invaders, &saved, &devices)).unwrap();
+
+        assert_eq!(snapshot.machine, Machine::Invaders);
+        let state = &snapshot.state;
+        assert_eq!(format!("{:?}", state), format!("{:?}", saved));
+        assert_eq!((state.cycles, state.interrupts), (saved.cycles, saved.interrupts));
+        assert!(state.memory[..] == saved.memory[..]);
+        assert_eq!(state.map, Machine::Invaders.memory_map(), "the memory map comes from the machine");
+        assert_eq!(snapshot.devices.into_iter().collect::<Vec<_>>(), [("shifter".to_string(), vec![1, 2, 3]), ("sound".to_string(), vec![])]);
+    }
+
+    #[test]
+    fn files_that_arent_snapshots_are_refused() {
+        let bytes = save(Machine::Cpm, &state(), &[]);
+        assert_eq!(load(b"RS8080").err(), Some("not a save state".to_string()));
+        assert_eq!(load(&bytes[..bytes.len() - 1]).err(), Some("save state is truncated".to_string()));
+
+        let mut newer = bytes.clone();
+        newer[8] = VERSION as u8 + 1;
+        assert_eq!(load(&newer).err(), Some(format!("save state version {} is newer than this build understands ({})", VERSION + 1, VERSION)));
+        let mut unversioned = bytes.clone();
+        unversioned[8..10].copy_from_slice(&[0, 0]);
+        assert_eq!(load(&unversioned).err(), Some("save state has no version".to_string()));
+    }
+
+    #[test]
+    fn missing_sections_are_errors() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        put_name(&mut bytes, "cpm");
+        assert_eq!(load(&bytes).err(), Some("save state has no cpu section".to_string()));
+        put_section(&mut bytes, CPU, &[0; 21]);
+        assert_eq!(load(&bytes).err(), Some("save state has no memory section".to_string()));
+        put_section(&mut bytes, MEMORY, &[0; 16]);
+        assert_eq!(load(&bytes).err(), Some("memory section is 16 bytes, expected 65536".to_string()));
+    }
+
+    // A version 2 that split the cpu section's cycle count out into a section of its own,
+    // then a version 3 that renamed the memory section.
+    fn split_cycles(sections: &mut Sections) -> Result<(), String> {
+        let cpu = sections.get_mut(CPU).ok_or_else(|| "no cpu section".to_string())?;
+        let cycles = cpu.drain(12..20).collect();
+        sections.insert("cycles".to_string(), cycles);
+        Ok(())
+    }
+
+    fn rename_memory(sections: &mut Sections) -> Result<(), String> {
+        let memory = sections.remove(MEMORY).ok_or_else(|| "no memory section".to_string())?;
+        sections.insert("ram".to_string(), memory);
+        Ok(())
+    }
+
+    const FABRICATED: [Upgrade; 2] = [split_cycles, rename_memory];
+
+    fn version_1() -> Sections {
+        let mut sections = Sections::new();
+        sections.insert(CPU.to_string(), (0..21).collect());
+        sections.insert(MEMORY.to_string(), vec![0xaa; 4]);
+        sections
+    }
+
+    #[test]
+    fn old_files_go_through_every_upgrade_since() {
+        let mut sections = version_1();
+        upgrade(&mut sections, 1, &FABRICATED).unwrap();
+        assert_eq!(sections.keys().collect::<Vec<_>>(), ["cpu", "cycles", "ram"]);
+        assert_eq!(sections["cpu"], [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 20]);
+        assert_eq!(sections["cycles"], [12, 13, 14, 15, 16, 17, 18, 19]);
+    }
+
+    #[test]
+    fn newer_files_skip_the_upgrades_they_had() {
+        let mut sections = version_1();
+        upgrade(&mut sections, 2, &FABRICATED).unwrap();
+        assert_eq!(sections.keys().collect::<Vec<_>>(), ["cpu", "ram"]);
+        assert_eq!(sections["cpu"].len(), 21);
+
+        let mut sections = version_1();
+        upgrade(&mut sections, 3, &FABRICATED).unwrap();
+        assert_eq!(sections.keys().collect::<Vec<_>>(), ["cpu", "memory"]);
+    }
+
+    #[test]
+    fn a_failed_upgrade_is_an_error() {
+        let mut sections = Sections::new();
+        assert_eq!(upgrade(&mut sections, 1, &FABRICATED), Err("no cpu section".to_string()));
+    }
+}
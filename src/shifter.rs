@@ -0,0 +1,159 @@
+//! The Midway boards' hardware shift register, which lets the 8080 draw sprites at any bit
+//! position without shifting them itself. The last two bytes written are held as a 16-bit
+//! word, newest on top, and any eight consecutive bits of it can be read back.
+
+use ports::Device;
+
+/// OUT sets how many bits from the top of the word the result starts.
+pub const OFFSET_PORT: u8 = 2;
+/// IN reads the result.
+pub const RESULT_PORT: u8 = 3;
+/// OUT shifts a byte in at the top, pushing the older one down.
+pub const DATA_PORT: u8 = 4;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShiftRegister {
+    value: u16,
+    offset: u8,
+}
+
+impl ShiftRegister {
+    pub fn shift_in(&mut self, data: u8) {
+        self.value = (data as u16) << 8 | self.value >> 8;
+    }
+
+    /// Only the low three bits are wired up.
+    pub fn set_offset(&mut self, offset: u8) {
+        self.offset = offset & 7;
+    }
+
+    pub fn result(&self) -> u8 {
+        (self.value >> (8 - self.offset)) as u8
+    }
+}
+
+impl Device for ShiftRegister {
+    fn name(&self) -> &'static str {
+        "shifter"
+    }
+
+    fn input(&mut self, port: u8) -> Option<u8> {
+        if port == RESULT_PORT { Some(self.result()) } else { None }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            OFFSET_PORT => self.set_offset(value),
+            DATA_PORT => self.shift_in(value),
+            _ => {},
+        }
+    }
+
+    fn reset_line(&mut self, _cycles: u64) {
+        *self = ShiftRegister::default();
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let mut bytes = self.value.to_le_bytes().to_vec();
+        bytes.push(self.offset);
+        bytes
+    }
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.len() != 3 {
+            return Err(format!("expected 3 bytes, got {}", bytes.len()))
+        }
+        self.value = u16::from_le_bytes([bytes[0], bytes[1]]);
+        self.set_offset(bytes[2]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two bytes whose bits are all different at every offset.
+    const LOW: u8 = 0b1010_0110;
+    const HIGH: u8 = 0b1100_1011;
+
+    fn loaded() -> ShiftRegister {
+        let mut shifter = ShiftRegister::default();
+        shifter.output(DATA_PORT, LOW);
+        shifter.output(DATA_PORT, HIGH);
+        shifter
+    }
+
+    #[test]
+    fn every_offset_reads_eight_bits_down_from_the_top() {
+        let word = (HIGH as u16) << 8 | LOW as u16;
+        for offset in 0..8 {
+            let mut shifter = loaded();
+            shifter.output(OFFSET_PORT, offset);
+            assert_eq!(shifter.input(RESULT_PORT), Some((word << offset >> 8) as u8), "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn offset_zero_reads_the_newest_byte() {
+        let mut shifter = loaded();
+        shifter.output(OFFSET_PORT, 0);
+        assert_eq!(shifter.input(RESULT_PORT), Some(HIGH));
+    }
+
+    #[test]
+    fn offset_seven_reads_one_bit_of_the_older_byte() {
+        let mut shifter = loaded();
+        shifter.output(OFFSET_PORT, 7);
+        assert_eq!(shifter.input(RESULT_PORT), Some(HIGH << 7 | LOW >> 1));
+    }
+
+    #[test]
+    fn only_the_low_three_offset_bits_count() {
+        for offset in 0..=255u8 {
+            let (mut shifter, mut expected) = (loaded(), loaded());
+            shifter.output(OFFSET_PORT, offset);
+            expected.output(OFFSET_PORT, offset & 7);
+            assert_eq!(shifter.input(RESULT_PORT), expected.input(RESULT_PORT), "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn each_byte_pushes_the_older_one_down() {
+        let mut shifter = loaded();
+        shifter.output(DATA_PORT, 0x5a);
+        for offset in 0..8 {
+            shifter.output(OFFSET_PORT, offset);
+            let word = 0x5a00 | HIGH as u16;
+            assert_eq!(shifter.input(RESULT_PORT), Some((word << offset >> 8) as u8), "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn the_offset_survives_new_data() {
+        let mut shifter = loaded();
+        shifter.output(OFFSET_PORT, 3);
+        shifter.output(DATA_PORT, 0xff);
+        assert_eq!(shifter.input(RESULT_PORT), Some(((0xff00 | HIGH as u16) << 3 >> 8) as u8));
+    }
+
+    #[test]
+    fn other_ports_are_left_alone() {
+        let mut shifter = loaded();
+        let before = shifter.clone();
+        for port in (0..=255).filter(|&port| port != OFFSET_PORT && port != DATA_PORT) {
+            shifter.output(port, 0x77);
+        }
+        assert_eq!(shifter, before);
+        assert!((0..=255).filter(|&port| port != RESULT_PORT).all(|port| shifter.input(port).is_none()));
+    }
+
+    #[test]
+    fn saving_and_restoring_keeps_value_and_offset() {
+        let mut shifter = loaded();
+        shifter.output(OFFSET_PORT, 5);
+        let mut restored = ShiftRegister::default();
+        restored.restore(&shifter.save()).unwrap();
+        assert_eq!(restored, shifter);
+    }
+}
@@ -0,0 +1,245 @@
+//! The Space Invaders sound board, which OUT 3 and OUT 5 switch each sound on and off
+//! through. Writes are turned into events as the sounds start and stop, for frontends and
+//! tests to listen to.
+
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use ports::Device;
+
+pub const PORT_3: u8 = 3;
+pub const PORT_5: u8 = 5;
+
+/// The bit of port 5 that turns the screen round on the cocktail cabinet, which is latched
+/// with the sounds.
+const FLIP: u8 = 0x20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sound {
+    /// The saucer crossing the top of the screen, which plays for as long as it is on.
+    Ufo,
+    Shot,
+    PlayerDeath,
+    InvaderHit,
+    ExtendedPlay,
+    /// The amplifier, which mutes every sound while it is off.
+    Amplifier,
+    /// One of the four notes the fleet marches to, 1 to 4.
+    Fleet(u8),
+    UfoHit,
+}
+
+/// Every sound the board makes, leaving out the amplifier.
+pub const SOUNDS: [Sound; 10] = [
+    Sound::Ufo, Sound::Shot, Sound::PlayerDeath, Sound::InvaderHit, Sound::ExtendedPlay,
+    Sound::Fleet(1), Sound::Fleet(2), Sound::Fleet(3), Sound::Fleet(4), Sound::UfoHit,
+];
+
+impl Sound {
+    /// The sound called `name`, as it is written in events.
+    pub fn named(name: &str) -> Option<Sound> {
+        SOUNDS.iter().cloned().find(|sound| sound.to_string() == name.to_lowercase())
+    }
+
+    /// The sound switched by `bit` of `port`, if any.
+    pub fn wired(port: u8, bit: u8) -> Option<Sound> {
+        match (port, bit) {
+            (PORT_3, 0) => Some(Sound::Ufo),
+            (PORT_3, 1) => Some(Sound::Shot),
+            (PORT_3, 2) => Some(Sound::PlayerDeath),
+            (PORT_3, 3) => Some(Sound::InvaderHit),
+            (PORT_3, 4) => Some(Sound::ExtendedPlay),
+            (PORT_3, 5) => Some(Sound::Amplifier),
+            (PORT_5, 0..=3) => Some(Sound::Fleet(bit + 1)),
+            (PORT_5, 4) => Some(Sound::UfoHit),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Sound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Sound::Ufo => write!(f, "ufo"),
+            Sound::Shot => write!(f, "shot"),
+            Sound::PlayerDeath => write!(f, "player-death"),
+            Sound::InvaderHit => write!(f, "invader-hit"),
+            Sound::ExtendedPlay => write!(f, "extended-play"),
+            Sound::Amplifier => write!(f, "amplifier"),
+            Sound::Fleet(note) => write!(f, "fleet{}", note),
+            Sound::UfoHit => write!(f, "ufo-hit"),
+        }
+    }
+}
+
+/// A sound starting or stopping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    /// The CPU's cycle count as the OUT that caused it started.
+    pub cycles: u64,
+    pub sound: Sound,
+    pub on: bool,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.cycles, self.sound, if self.on { "on" } else { "off" })
+    }
+}
+
+/// The latches on ports 3 and 5, which hold what was last written to them.
+#[derive(Default)]
+pub struct SoundBoard {
+    pub port3: u8,
+    pub port5: u8,
+    cycles: u64,
+    listeners: Vec<Sender<Event>>,
+}
+
+impl SoundBoard {
+    /// A channel that gets every event from now on.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.listeners.push(sender);
+        receiver
+    }
+
+    /// Whether the program wants the screen turned round for the second player.
+    pub fn flipped(&self) -> bool {
+        self.port5 & FLIP != 0
+    }
+
+    /// The sounds switched on now.
+    pub fn latched(&self) -> Vec<Sound> {
+        let bits = |port: u8, value: u8| (0..8).filter(move |bit| value >> bit & 1 != 0).filter_map(move |bit| Sound::wired(port, bit));
+        bits(PORT_3, self.port3).chain(bits(PORT_5, self.port5)).collect()
+    }
+
+    fn latch(&mut self, port: u8, value: u8) {
+        let latched = if port == PORT_3 { &mut self.port3 } else { &mut self.port5 };
+        let changed = *latched ^ value;
+        *latched = value;
+
+        for bit in (0..8).filter(|bit| changed >> bit & 1 != 0) {
+            if let Some(sound) = Sound::wired(port, bit) {
+                let event = Event { cycles: self.cycles, sound, on: value >> bit & 1 != 0 };
+                // Listeners that have gone away are forgotten.
+                self.listeners.retain(|listener| listener.send(event).is_ok());
+            }
+        }
+    }
+}
+
+impl Device for SoundBoard {
+    fn name(&self) -> &'static str {
+        "sound"
+    }
+
+    fn input(&mut self, _port: u8) -> Option<u8> {
+        None
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        if port == PORT_3 || port == PORT_5 {
+            self.latch(port, value);
+        }
+    }
+
+    // Not for raising interrupts, but to know the time, as this is asked before each
+    // instruction.
+    fn interrupt(&mut self, cycles: u64) -> Option<u8> {
+        self.cycles = cycles;
+        None
+    }
+
+    // Clearing the latches switches off whatever was playing, and turns the screen back.
+    fn reset_line(&mut self, cycles: u64) {
+        self.cycles = cycles;
+        self.latch(PORT_3, 0);
+        self.latch(PORT_5, 0);
+    }
+
+    fn save(&self) -> Vec<u8> {
+        vec![self.port3, self.port5]
+    }
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.len() != 2 {
+            return Err(format!("expected 2 bytes, got {}", bytes.len()))
+        }
+        self.port3 = bytes[0];
+        self.port5 = bytes[1];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sounds_are_named_as_they_print() {
+        for &sound in SOUNDS.iter() {
+            assert_eq!(Sound::named(&sound.to_string()), Some(sound));
+        }
+        assert_eq!(Sound::named("UFO-HIT"), Some(Sound::UfoHit));
+        assert_eq!(Sound::named("amplifier"), None, "the amplifier isn't a sound");
+        assert_eq!(Sound::wired(PORT_5, 5), None, "the flip bit");
+    }
+
+    #[test]
+    fn set_and_cleared_bits_are_events() {
+        let mut board = SoundBoard::default();
+        let events = board.subscribe();
+        board.interrupt(100);
+        board.output(PORT_3, 0x21);
+        board.interrupt(250);
+        board.output(PORT_3, 0x22);
+        board.output(PORT_5, 0x11);
+
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), [
+            Event { cycles: 100, sound: Sound::Ufo, on: true },
+            Event { cycles: 100, sound: Sound::Amplifier, on: true },
+            Event { cycles: 250, sound: Sound::Ufo, on: false },
+            Event { cycles: 250, sound: Sound::Shot, on: true },
+            Event { cycles: 250, sound: Sound::Fleet(1), on: true },
+            Event { cycles: 250, sound: Sound::UfoHit, on: true },
+        ]);
+        assert_eq!(board.latched(), [Sound::Shot, Sound::Amplifier, Sound::Fleet(1), Sound::UfoHit]);
+        assert_eq!(Event { cycles: 250, sound: Sound::Fleet(1), on: false }.to_string(), "250 fleet1 off");
+    }
+
+    #[test]
+    fn unchanged_and_unwired_bits_are_quiet() {
+        let mut board = SoundBoard::default();
+        let events = board.subscribe();
+        board.output(PORT_3, 0xc0);
+        board.output(PORT_5, FLIP);
+        board.output(PORT_5, FLIP);
+        board.output(4, 0xff);
+        assert_eq!(events.try_iter().count(), 0);
+        assert!(board.flipped());
+        assert_eq!(board.latched(), []);
+    }
+
+    #[test]
+    fn listeners_that_go_away_are_dropped() {
+        let mut board = SoundBoard::default();
+        drop(board.subscribe());
+        let events = board.subscribe();
+        board.output(PORT_3, 0x02);
+        assert_eq!(board.listeners.len(), 1);
+        assert_eq!(events.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn latches_survive_saving_and_restoring() {
+        let mut board = SoundBoard::default();
+        board.output(PORT_3, 0x21);
+        board.output(PORT_5, 0x24);
+        let mut restored = SoundBoard::default();
+        restored.restore(&board.save()).unwrap();
+        assert_eq!((restored.port3, restored.port5), (0x21, 0x24));
+        assert_eq!(restored.restore(&[0]), Err("expected 2 bytes, got 1".to_string()));
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use disassembler::Analysis;
+
+/// Names and comments for addresses, read from a symbol file with one address per line:
+///
+/// ```text
+/// 18d4 reset          ; entry point after power-on
+/// 20c0 ; frame counter
+/// ```
+#[derive(Default)]
+pub struct Symbols {
+    pub labels: BTreeMap<u16, String>,
+    pub comments: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn read(path: &str) -> io::Result<Symbols> {
+        let mut source = String::new();
+        File::open(path)?.read_to_string(&mut source)?;
+
+        let mut symbols = Symbols::default();
+        for (number, line) in source.lines().enumerate() {
+            let (line, comment) = match line.find(';') {
+                Some(index) => (&line[..index], Some(line[index + 1..].trim())),
+                None => (line, None),
+            };
+            let mut fields = line.split_whitespace();
+            let address = match fields.next() {
+                Some(address) => address,
+                None => continue,
+            };
+            let address = u16::from_str_radix(address.trim_start_matches("0x").trim_start_matches('$').trim_end_matches(['h', 'H']), 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: invalid address: {}", path, number + 1, address)))?;
+
+            if let Some(label) = fields.next() {
+                symbols.labels.insert(address, label.to_string());
+            }
+            if let Some(comment) = comment {
+                symbols.comments.insert(address, comment.to_string());
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// Replaces generated labels with the names given in the symbol file.
+    pub fn apply(&self, analysis: &mut Analysis) {
+        for (&address, label) in &self.labels {
+            analysis.labels.insert(address, label.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::env;
+    use std::fs;
+
+    fn read(name: &str, text: &str) -> io::Result<Symbols> {
+        let path = env::temp_dir().join(format!("rs8080-symbols-{}-{}.sym", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        let symbols = Symbols::read(&path.to_string_lossy());
+        fs::remove_file(&path).unwrap();
+        symbols
+    }
+
+    #[test]
+    fn lines_give_labels_and_comments() {
+        let symbols = read("lines", "18d4 reset          ; entry point after power-on\n\n20c0 ; frame counter\n$0010 rst2\n0x0020 rst4\n30H rst6\n").unwrap();
+        assert_eq!(symbols.labels.iter().map(|(&address, label)| (address, label.as_str())).collect::<Vec<_>>(),
+            [(0x10, "rst2"), (0x20, "rst4"), (0x30, "rst6"), (0x18d4, "reset")]);
+        assert_eq!(symbols.comments.iter().map(|(&address, comment)| (address, comment.as_str())).collect::<Vec<_>>(),
+            [(0x18d4, "entry point after power-on"), (0x20c0, "frame counter")]);
+    }
+
+    #[test]
+    fn a_bad_address_names_the_line() {
+        let error = read("bad", "0000 start\nzz oops\n").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().ends_with(":2: invalid address: zz"), "{}", error);
+    }
+
+    #[test]
+    fn labels_replace_generated_ones() {
+        let mut analysis = Analysis { code: BTreeSet::new(), subroutines: BTreeSet::new(), data: BTreeSet::new(), labels: BTreeMap::new(), indirect: BTreeMap::new() };
+        analysis.labels.insert(0x10, "sub_0010".to_string());
+        analysis.labels.insert(0x20, "loc_0020".to_string());
+        let symbols = read("apply", "10 print\n").unwrap();
+        symbols.apply(&mut analysis);
+        assert_eq!(analysis.label(0x10), Some("print"));
+        assert_eq!(analysis.label(0x20), Some("loc_0020"));
+    }
+}
@@ -0,0 +1,158 @@
+//! Rough copies of the sound board's analog circuits, for when there are no samples of them
+//! to play. Each sound is worked out once, as a sample of it, and then mixed like one.
+//!
+//! The explosions are shift-register noise through a low-pass filter, the UFO is an
+//! oscillator swept up and down by a slower one, and the fleet plays four low notes.
+
+use std::f32::consts::PI;
+
+use sound::Sound;
+
+/// How loud each sound is, leaving room for several to play at once.
+const VOLUME: f32 = 0.4;
+
+/// The noise the board makes from a 17-bit shift register, clocked at `clock` Hz.
+struct Noise {
+    register: u32,
+    clock: f32,
+    phase: f32,
+    level: f32,
+}
+
+impl Noise {
+    fn new(clock: f32) -> Noise {
+        Noise { register: 1, clock, phase: 0.0, level: 1.0 }
+    }
+
+    // The noise one sample later, at `rate` samples a second.
+    fn next(&mut self, rate: f32) -> f32 {
+        self.phase += self.clock / rate;
+        while self.phase >= 1.0 {
+            self.phase -= 1.0;
+            let bit = (self.register ^ self.register >> 3) & 1;
+            self.register = self.register >> 1 | bit << 16;
+            self.level = if bit != 0 { 1.0 } else { -1.0 };
+        }
+        self.level
+    }
+}
+
+// Noise through a filter that cuts above `cutoff` Hz, dying away over `decay` seconds.
+fn explosion(rate: u32, seconds: f32, clock: f32, cutoff: f32, decay: f32) -> Vec<f32> {
+    let rate = rate as f32;
+    let mut noise = Noise::new(clock);
+    let smoothing = 1.0 - (-2.0 * PI * cutoff / rate).exp();
+    let mut filtered = 0.0;
+    (0..(seconds * rate) as usize).map(|index| {
+        filtered += smoothing * (noise.next(rate) - filtered);
+        filtered * (-(index as f32 / rate) / decay).exp()
+    }).collect()
+}
+
+// A square wave, its frequency and loudness at each moment given by `frequency` and `level`.
+fn tone(rate: u32, seconds: f32, frequency: &dyn Fn(f32) -> f32, level: &dyn Fn(f32) -> f32) -> Vec<f32> {
+    let rate = rate as f32;
+    let mut phase = 0.0f32;
+    (0..(seconds * rate).round() as usize).map(|index| {
+        let time = index as f32 / rate;
+        phase = (phase + frequency(time) / rate).fract();
+        (if phase < 0.5 { 1.0 } else { -1.0 }) * level(time)
+    }).collect()
+}
+
+// A triangle from 0 up to 1 and back over each period of `frequency` Hz.
+fn triangle(time: f32, frequency: f32) -> f32 {
+    let phase = (time * frequency).fract();
+    1.0 - (2.0 * phase - 1.0).abs()
+}
+
+/// The sound, as a sample at `rate` samples a second. The UFO's is one sweep long, to be
+/// played over and over.
+pub fn synthesize(sound: Sound, rate: u32) -> Vec<f32> {
+    let samples = match sound {
+        // Sweeping from 550 to 1100Hz and back 5.5 times a second goes through a whole
+        // number of cycles each sweep, so it loops without a click.
+        Sound::Ufo => tone(rate, 1.0 / 5.5, &|time| 550.0 + 550.0 * triangle(time, 5.5), &|_| 0.6),
+        Sound::Shot => {
+            let hiss = explosion(rate, 0.4, 16000.0, 4000.0, 0.1);
+            let whistle = tone(rate, 0.4, &|time| 1200.0 - 2000.0 * time, &|time| 0.3 * (-time / 0.08).exp());
+            hiss.iter().zip(whistle).map(|(hiss, whistle)| hiss * 0.7 + whistle).collect()
+        },
+        Sound::PlayerDeath => explosion(rate, 1.6, 3000.0, 600.0, 0.4),
+        Sound::InvaderHit => explosion(rate, 0.35, 9000.0, 2000.0, 0.07),
+        // A beep eight times a second for a second.
+        Sound::ExtendedPlay => tone(rate, 1.0, &|_| 480.0, &|time| if (time * 8.0).fract() < 0.5 { 0.5 } else { 0.0 }),
+        // The fleet's notes step down as it marches, each a short thump.
+        Sound::Fleet(note) => {
+            let frequency = [98.0, 87.0, 78.0, 73.0][(note.clamp(1, 4) - 1) as usize];
+            tone(rate, 0.15, &move |_| frequency, &|time| (-time / 0.05).exp())
+        },
+        // The saucer breaking up: a warbling tone falling away.
+        Sound::UfoHit => tone(rate, 1.0, &|time| (1200.0 - 900.0 * time) * (1.0 + 0.15 * triangle(time, 16.0)), &|time| 0.6 * (1.0 - time)),
+        Sound::Amplifier => vec![],
+    };
+    samples.into_iter().map(|sample| sample * VOLUME).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sound::SOUNDS;
+
+    const RATE: u32 = 44100;
+
+    // The loudest of `samples`.
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    // How many times a square wave goes from low to high, which is how many cycles it has.
+    fn cycles(samples: &[f32]) -> usize {
+        samples.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] > 0.0).count()
+    }
+
+    #[test]
+    fn sounds_last_as_long_as_they_should() {
+        let seconds = |sound| synthesize(sound, RATE).len() as f32 / RATE as f32;
+        assert_eq!(synthesize(Sound::Ufo, RATE).len(), 8018, "one sweep, 1/5.5 of a second");
+        assert_eq!(seconds(Sound::Shot), 0.4);
+        assert_eq!(seconds(Sound::PlayerDeath), 1.6);
+        assert_eq!(seconds(Sound::ExtendedPlay), 1.0);
+        assert_eq!(seconds(Sound::Fleet(3)), 0.15);
+        assert_eq!(synthesize(Sound::Amplifier, RATE), []);
+    }
+
+    #[test]
+    fn sounds_leave_room_to_mix() {
+        for &sound in SOUNDS.iter() {
+            let samples = synthesize(sound, RATE);
+            assert!(peak(&samples) <= VOLUME, "{} peaks at {}", sound, peak(&samples));
+            assert!(peak(&samples) > VOLUME / 10.0, "{} is silent", sound);
+            assert_eq!(synthesize(sound, RATE), samples, "{} comes out the same every time", sound);
+        }
+    }
+
+    #[test]
+    fn explosions_die_away() {
+        for &sound in &[Sound::PlayerDeath, Sound::InvaderHit, Sound::Shot] {
+            let samples = synthesize(sound, RATE);
+            let tenth = samples.len() / 10;
+            assert!(peak(&samples[samples.len() - tenth..]) < peak(&samples[..tenth]) / 4.0, "{}", sound);
+        }
+    }
+
+    #[test]
+    fn the_fleet_marches_down_four_notes() {
+        let notes: Vec<usize> = (1..=4).map(|note| cycles(&synthesize(Sound::Fleet(note), RATE))).collect();
+        assert_eq!(notes, [14, 13, 11, 10]);
+    }
+
+    #[test]
+    fn the_ufo_sweep_is_a_whole_number_of_cycles() {
+        let samples = synthesize(Sound::Ufo, RATE);
+        // Averaging 825Hz over the sweep makes 150 cycles, the first starting high rather
+        // than rising. The last ends low, so playing it again carries straight on.
+        assert_eq!(cycles(&samples), 149);
+        assert!(samples[0] > 0.0 && samples[samples.len() - 1] < 0.0);
+    }
+}
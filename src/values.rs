@@ -0,0 +1,287 @@
+use std::mem;
+use std::ops::Range;
+
+use disassembler::Instruction;
+
+/// What is known about the contents of a register or register pair.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value {
+    Unknown,
+    Constant(u16),
+    /// A table base plus some index that can't be known statically.
+    Indexed(u16),
+    /// The low byte of a word read through HL, waiting for its high byte. The flag says
+    /// whether HL was indexed rather than a constant.
+    LowByte(u16, bool),
+    HighByteOf(u16, bool),
+    /// A word read from the table at this base.
+    Entry(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Indirect {
+    Target(u16),
+    Table(u16),
+    Unresolved,
+}
+
+struct Registers {
+    a: Value,
+    bc: Value,
+    de: Value,
+    hl: Value,
+}
+
+fn read_word(memory: &[u8], range: &Range<usize>, address: u16) -> Value {
+    let address = address as usize;
+    if range.contains(&address) && range.contains(&(address + 1)) {
+        Value::Constant(((memory[address + 1] as u16) << 8) | memory[address] as u16)
+    } else {
+        Value::Unknown
+    }
+}
+
+fn add(left: Value, right: Value) -> Value {
+    match (left, right) {
+        (Value::Constant(left), Value::Constant(right)) => Value::Constant(left.wrapping_add(right)),
+        (Value::Constant(base), Value::Unknown) | (Value::Unknown, Value::Constant(base)) => Value::Indexed(base),
+        (Value::Indexed(base), Value::Unknown) | (Value::Unknown, Value::Indexed(base)) => Value::Indexed(base),
+        _ => Value::Unknown,
+    }
+}
+
+/// What reading the low byte of a word through `hl` gives.
+fn low_byte(hl: Value) -> Value {
+    match hl {
+        Value::Constant(address) => Value::LowByte(address, false),
+        Value::Indexed(base) => Value::LowByte(base, true),
+        _ => Value::Unknown,
+    }
+}
+
+/// A whole word read through HL: a table entry if HL was indexed, otherwise whatever is
+/// stored at that fixed address.
+fn word(memory: &[u8], range: &Range<usize>, address: u16, indexed: bool) -> Value {
+    if indexed { Value::Entry(address) } else { read_word(memory, range, address) }
+}
+
+fn pair<'a>(registers: &'a mut Registers, name: &str) -> Option<&'a mut Value> {
+    match name {
+        "B" => Some(&mut registers.bc),
+        "D" => Some(&mut registers.de),
+        "H" => Some(&mut registers.hl),
+        _ => None,
+    }
+}
+
+fn pair_of(register: &str) -> &str {
+    match register {
+        "B" | "C" => "B",
+        "D" | "E" => "D",
+        "H" | "L" => "H",
+        _ => register,
+    }
+}
+
+/// Runs the straight-line `path` leading up to a PCHL, tracking what HL holds when it is
+/// reached. This recognises the usual ways of getting there: loading HL directly, and
+/// indexing a table of addresses with `MOV E,M / INX H / MOV D,M / XCHG` or
+/// `MOV A,M / INX H / MOV H,M / MOV L,A`.
+pub fn resolve_indirect(memory: &[u8], range: &Range<usize>, path: &[Instruction]) -> Indirect {
+    let mut registers = Registers { a: Value::Unknown, bc: Value::Unknown, de: Value::Unknown, hl: Value::Unknown };
+
+    for instruction in path {
+        let operand = if instruction.bytes.len() == 3 {
+            ((instruction.bytes[2] as u16) << 8) | instruction.bytes[1] as u16
+        } else {
+            0
+        };
+        let registers_field = instruction.opcode.registers;
+
+        match (instruction.opcode.mnemonic, registers_field) {
+            ("LXI", rp) => if let Some(value) = pair(&mut registers, rp) { *value = Value::Constant(operand) },
+            ("LHLD", _) => registers.hl = read_word(memory, range, operand),
+            ("XCHG", _) => mem::swap(&mut registers.de, &mut registers.hl),
+            // Doubling an indexed HL doubles the table's base too, so it's no longer known.
+            ("DAD", "H") => registers.hl = match registers.hl {
+                Value::Constant(value) => Value::Constant(value.wrapping_mul(2)),
+                _ => Value::Unknown,
+            },
+            ("DAD", rp) => {
+                let other = match rp { "B" => registers.bc, "D" => registers.de, _ => Value::Unknown };
+                registers.hl = add(registers.hl, other);
+            },
+            ("INX", rp) => if let Some(value) = pair(&mut registers, rp) {
+                *value = match *value {
+                    Value::Constant(address) => Value::Constant(address.wrapping_add(1)),
+                    other => other,
+                }
+            },
+            ("MOV", "E,M") => registers.de = low_byte(registers.hl),
+            ("MOV", "A,M") => registers.a = low_byte(registers.hl),
+            ("MOV", "D,M") => registers.de = match registers.de {
+                Value::LowByte(address, indexed) => word(memory, range, address, indexed),
+                _ => Value::Unknown,
+            },
+            ("MOV", "H,M") => registers.hl = match registers.a {
+                Value::LowByte(address, indexed) => Value::HighByteOf(address, indexed),
+                _ => Value::Unknown,
+            },
+            ("MOV", "L,A") => registers.hl = match (registers.hl, registers.a) {
+                (Value::HighByteOf(address, indexed), Value::LowByte(low, _)) if address == low => word(memory, range, address, indexed),
+                _ => Value::Unknown,
+            },
+            ("MOV", registers_field) => {
+                let destination = &registers_field[..1];
+                if destination == "A" {
+                    registers.a = Value::Unknown;
+                } else if let Some(value) = pair(&mut registers, pair_of(destination)) {
+                    *value = Value::Unknown;
+                }
+            },
+            (mnemonic, registers_field) => {
+                let written = match mnemonic {
+                    "MVI" | "INR" | "DCR" | "POP" | "DCX" => pair_of(&registers_field[..1]),
+                    "XTHL" => "H",
+                    "PUSH" | "CMP" | "CPI" | "STA" | "STAX" | "SHLD" | "OUT" | "NOP" | "STC" | "CMC" | "EI" | "DI" | "SPHL" => "",
+                    // Everything else is arithmetic, logic or a load into the accumulator.
+                    _ => "A",
+                };
+                match written {
+                    "A" | "P" => registers.a = Value::Unknown,
+                    rp => if let Some(value) = pair(&mut registers, rp) { *value = Value::Unknown },
+                }
+            },
+        }
+    }
+
+    match registers.hl {
+        Value::Constant(target) => Indirect::Target(target),
+        Value::Entry(base) => Indirect::Table(base),
+        _ => Indirect::Unresolved,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disassembler;
+
+    // Loads `code` at 0, followed by a table of `words` and then ONE: HLT and TWO: HLT, and
+    // returns memory and the instructions leading up to the first PCHL.
+    fn lead_up(code: &[u8], words: &[u16]) -> (Vec<u8>, Range<usize>, Vec<Instruction>) {
+        let mut image = code.to_vec();
+        for word in words {
+            image.extend_from_slice(&[*word as u8, (*word >> 8) as u8]);
+        }
+        image.extend_from_slice(&[0x76, 0x76]);
+        let mut memory = vec![0; 0x10000];
+        memory[..image.len()].copy_from_slice(&image);
+        let range = 0..image.len();
+        let path = disassembler::sweep(&memory, range.clone()).into_iter()
+            .take_while(|instruction| instruction.opcode.mnemonic != "PCHL")
+            .collect();
+        (memory, range, path)
+    }
+
+    // An index doubled and added to TABLE at 13, with the entry read into DE and exchanged.
+    const INDEXED: [u8; 13] = [
+        0x6f,             // MOV L,A
+        0x26, 0x00,       // MVI H,0
+        0x29,             // DAD H
+        0x11, 0x0d, 0x00, // LXI D,TABLE
+        0x19,             // DAD D
+        0x5e,             // MOV E,M
+        0x23,             // INX H
+        0x56,             // MOV D,M
+        0xeb,             // XCHG
+        0xe9,             // PCHL
+    ];
+
+    #[test]
+    fn a_constant_hl_is_the_target() {
+        // LXI H,ONE; PCHL, with the table at 4 and ONE at 8.
+        let (memory, range, path) = lead_up(&[0x21, 0x08, 0x00, 0xe9], &[8, 9]);
+        assert_eq!(resolve_indirect(&memory, &range, &path), Indirect::Target(8));
+    }
+
+    #[test]
+    fn an_address_loaded_from_memory_is_the_target() {
+        // LHLD TABLE; PCHL
+        let (memory, range, path) = lead_up(&[0x2a, 0x04, 0x00, 0xe9], &[8, 9]);
+        assert_eq!(resolve_indirect(&memory, &range, &path), Indirect::Target(8));
+    }
+
+    #[test]
+    fn an_index_doubled_and_added_to_a_base_reads_a_table() {
+        let (memory, range, path) = lead_up(&INDEXED, &[17, 18]);
+        assert_eq!(resolve_indirect(&memory, &range, &path), Indirect::Table(13));
+    }
+
+    #[test]
+    fn a_table_can_be_read_through_the_accumulator() {
+        let code = [
+            0x6f,             // MOV L,A
+            0x26, 0x00,       // MVI H,0
+            0x11, 0x0c, 0x00, // LXI D,TABLE
+            0x19,             // DAD D
+            0x7e,             // MOV A,M
+            0x23,             // INX H
+            0x66,             // MOV H,M
+            0x6f,             // MOV L,A
+            0xe9,             // PCHL
+        ];
+        let (memory, range, path) = lead_up(&code, &[16, 17]);
+        assert_eq!(resolve_indirect(&memory, &range, &path), Indirect::Table(12));
+    }
+
+    #[test]
+    fn doubling_an_indexed_hl_loses_the_table() {
+        let code = [
+            0x6f,             // MOV L,A
+            0x26, 0x00,       // MVI H,0
+            0x11, 0x0d, 0x00, // LXI D,TABLE
+            0x19,             // DAD D
+            0x29,             // DAD H
+            0x5e,             // MOV E,M
+            0x23,             // INX H
+            0x56,             // MOV D,M
+            0xeb,             // XCHG
+            0xe9,             // PCHL
+        ];
+        let (memory, range, path) = lead_up(&code, &[17, 18]);
+        assert_eq!(resolve_indirect(&memory, &range, &path), Indirect::Unresolved);
+    }
+
+    #[test]
+    fn an_unknown_hl_is_unresolved() {
+        // POP H; PCHL
+        let (memory, range, path) = lead_up(&[0xe1, 0xe9], &[6, 7]);
+        assert_eq!(resolve_indirect(&memory, &range, &path), Indirect::Unresolved);
+    }
+
+    #[test]
+    fn the_analysis_follows_every_table_entry() {
+        let (memory, range, _) = lead_up(&INDEXED, &[17, 18]);
+        let analysis = disassembler::analyse(&memory, &range, &[0]);
+        assert_eq!(analysis.indirect.get(&12), Some(&vec![17, 18]));
+        assert!(analysis.code.contains(&17) && analysis.code.contains(&18));
+        assert!(analysis.data.contains(&13) && analysis.data.contains(&15));
+    }
+
+    #[test]
+    fn a_table_ends_where_the_data_after_it_starts() {
+        // Padding, then a word that lands inside MVI H,0, come between the table and the code.
+        let (memory, range, _) = lead_up(&INDEXED, &[21, 22, 0, 2]);
+        let analysis = disassembler::analyse(&memory, &range, &[0]);
+        assert_eq!(analysis.indirect.get(&12), Some(&vec![21, 22]));
+        assert!(!analysis.data.contains(&17) && !analysis.data.contains(&19));
+        assert_eq!(analysis.label(2), None, "no label inside an instruction");
+
+        // A repeated entry is taken as the first word past the end.
+        let (memory, range, _) = lead_up(&INDEXED, &[21, 22, 21, 3]);
+        let analysis = disassembler::analyse(&memory, &range, &[0]);
+        assert_eq!(analysis.indirect.get(&12), Some(&vec![21, 22]));
+        assert_eq!(analysis.label(3), None, "the word after the repeat isn't read");
+    }
+}
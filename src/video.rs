@@ -0,0 +1,250 @@
+//! Turning video RAM into pictures, without needing a display to show them on.
+//!
+//! The Space Invaders monitor is turned on its side, so video RAM's 224 lines of 256 pixels
+//! come out as 224 columns of 256, with the first line on the left and the first pixel of
+//! each line at the bottom.
+
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use checksum;
+
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+
+const WHITE: [u8; 3] = [0xff, 0xff, 0xff];
+const BLACK: [u8; 3] = [0x00, 0x00, 0x00];
+const RED: [u8; 3] = [0xff, 0x20, 0x20];
+const GREEN: [u8; 3] = [0x20, 0xff, 0x20];
+
+/// The coloured strips stuck over the upright cabinet's screen: rows from the top, the
+/// columns they cover and their colour. The UFO flies under the red one and the shields
+/// and the player's base sit under the green, with the ships left along the bottom.
+const OVERLAY: [(Range<usize>, Range<usize>, [u8; 3]); 3] = [
+    (32..64, 0..WIDTH, RED),
+    (184..240, 0..WIDTH, GREEN),
+    (240..HEIGHT, 16..134, GREEN),
+];
+
+/// How to show the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Options {
+    /// Colour the picture as the upright cabinet's overlay did.
+    pub overlay: bool,
+    /// Turn the picture round when the program asks, as the cocktail cabinet does for the
+    /// second player, who sits across the table.
+    pub cocktail: bool,
+}
+
+/// A picture of the screen, in rows of RGB pixels from the top.
+pub struct Frame {
+    pub pixels: Vec<[u8; 3]>,
+}
+
+/// Decodes one bit a pixel `vram`, lines of 32 bytes with the lowest bit of each byte first.
+pub fn render(vram: &[u8]) -> Frame {
+    let mut pixels = vec![BLACK; WIDTH * HEIGHT];
+    for (index, &byte) in vram.iter().enumerate().take(WIDTH * HEIGHT / 8) {
+        let (line, x) = (index / 32, index % 32 * 8);
+        for bit in (0..8).filter(|bit| byte >> bit & 1 != 0) {
+            pixels[(HEIGHT - 1 - (x + bit)) * WIDTH + line] = WHITE;
+        }
+    }
+    Frame { pixels }
+}
+
+// A PNG chunk: its length, type and data, and a CRC of the type and data.
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = checksum::crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream holding `data` in stored, uncompressed, deflate blocks.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+    for (index, block) in blocks.iter().enumerate() {
+        stream.push((index + 1 == blocks.len()) as u8);
+        stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend_from_slice(&(b << 16 | a).to_be_bytes());
+    stream
+}
+
+impl Frame {
+    /// Turns the picture upside down and back to front.
+    pub fn flip(&mut self) {
+        self.pixels.reverse();
+    }
+
+    /// Tints the lit pixels under each of the overlay's strips.
+    pub fn overlay(&mut self) {
+        for (rows, columns, colour) in OVERLAY.iter() {
+            for row in rows.clone() {
+                for pixel in &mut self.pixels[row * WIDTH + columns.start..row * WIDTH + columns.end] {
+                    if *pixel == WHITE {
+                        *pixel = *colour;
+                    }
+                }
+            }
+        }
+    }
+
+    /// A binary PPM, which almost anything can read.
+    pub fn ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+        for pixel in &self.pixels {
+            ppm.extend_from_slice(pixel);
+        }
+        ppm
+    }
+
+    pub fn png(&self) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+        let mut header = vec![];
+        header.extend_from_slice(&(WIDTH as u32).to_be_bytes());
+        header.extend_from_slice(&(HEIGHT as u32).to_be_bytes());
+        // Eight bit RGB, with the only compression, filter and interlace methods there are.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        chunk(&mut png, b"IHDR", &header);
+
+        // Each row starts with the filter it uses, which is none.
+        let mut rows = vec![];
+        for row in self.pixels.chunks(WIDTH) {
+            rows.push(0);
+            for pixel in row {
+                rows.extend_from_slice(pixel);
+            }
+        }
+        chunk(&mut png, b"IDAT", &zlib(&rows));
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// Writes the frame to `path`, as a PNG if its name ends in .png and a PPM otherwise.
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let png = Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+        let bytes = if png { self.png() } else { self.ppm() };
+        fs::write(path, bytes).map_err(|error| format!("{}: {}", path, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inflate;
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    // A frame with the first pixel of video RAM lit, and the last.
+    fn corners() -> Frame {
+        let mut vram = vec![0; 0x1c00];
+        vram[0] = 0x01;
+        vram[0x1bff] = 0x80;
+        render(&vram)
+    }
+
+    #[test]
+    fn video_ram_is_turned_on_its_side() {
+        let frame = corners();
+        assert_eq!(frame.pixels.len(), WIDTH * HEIGHT);
+        assert_eq!(frame.pixels[(HEIGHT - 1) * WIDTH], WHITE, "the first pixel is bottom left");
+        assert_eq!(frame.pixels[WIDTH - 1], WHITE, "the last pixel is top right");
+        assert_eq!(frame.pixels.iter().filter(|&&pixel| pixel == WHITE).count(), 2);
+    }
+
+    #[test]
+    fn ppm_is_a_header_and_the_pixels() {
+        let ppm = corners().ppm();
+        let header = b"P6\n224 256\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(ppm.len(), header.len() + WIDTH * HEIGHT * 3);
+        assert_eq!(ppm[header.len() + (WIDTH - 1) * 3..header.len() + WIDTH * 3], WHITE);
+    }
+
+    #[test]
+    fn png_chunks_are_well_formed() {
+        let png = corners().png();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        let mut chunks = vec![];
+        let mut at = 8;
+        while at < png.len() {
+            let length = u32_at(&png, at) as usize;
+            let (kind, data) = (&png[at + 4..at + 8], &png[at + 8..at + 8 + length]);
+            assert_eq!(u32_at(&png, at + 8 + length), checksum::crc32(&png[at + 4..at + 8 + length]), "{} CRC", String::from_utf8_lossy(kind));
+            chunks.push((kind.to_vec(), data.to_vec()));
+            at += 12 + length;
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+        assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 224, 0, 0, 1, 0, 8, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn png_rows_decompress_to_the_pixels() {
+        let frame = corners();
+        let png = frame.png();
+        let length = u32_at(&png, 33) as usize;
+        let stream = &png[41..41 + length];
+        assert_eq!(stream[..2], [0x78, 0x01]);
+        let rows = inflate::inflate(&stream[2..stream.len() - 4]).unwrap();
+
+        assert_eq!(rows.len(), HEIGHT * (1 + WIDTH * 3));
+        for (row, pixels) in rows.chunks(1 + WIDTH * 3).zip(frame.pixels.chunks(WIDTH)) {
+            assert_eq!(row[0], 0, "no filter");
+            assert!(row[1..].chunks(3).eq(pixels.iter().map(|pixel| &pixel[..])));
+        }
+
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in &rows {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        assert_eq!(u32_at(stream, stream.len() - 4), b << 16 | a, "Adler-32");
+    }
+
+    #[test]
+    fn flipping_turns_the_picture_round() {
+        let mut vram = vec![0; 0x1c00];
+        vram[0] = 0x01;
+        let mut frame = render(&vram);
+        frame.flip();
+        assert_eq!(frame.pixels[(HEIGHT - 1) * WIDTH], BLACK);
+        assert_eq!(frame.pixels[WIDTH - 1], WHITE, "the first pixel is now top right");
+        assert_eq!(frame.pixels.iter().filter(|&&pixel| pixel == WHITE).count(), 1);
+        frame.flip();
+        assert!(frame.pixels == render(&vram).pixels, "twice is back where it started");
+    }
+
+    #[test]
+    fn the_overlay_tints_only_lit_pixels() {
+        let mut frame = render(&vec![0xff; 0x1c00]);
+        frame.pixels[40 * WIDTH + 100] = BLACK;
+        frame.overlay();
+
+        let at = |row: usize, column: usize| frame.pixels[row * WIDTH + column];
+        assert_eq!((at(0, 0), at(31, 223)), (WHITE, WHITE));
+        assert_eq!((at(32, 0), at(63, 223)), (RED, RED));
+        assert_eq!(at(40, 100), BLACK, "nothing to tint");
+        assert_eq!((at(64, 0), at(183, 223)), (WHITE, WHITE));
+        assert_eq!((at(184, 0), at(239, 223)), (GREEN, GREEN));
+        assert_eq!((at(240, 15), at(240, 16), at(255, 133), at(255, 134)), (WHITE, GREEN, GREEN, WHITE));
+    }
+}
@@ -0,0 +1,155 @@
+//! A watchdog, which resets the CPU unless the program writes to its port often enough, so
+//! a board that has hung starts over by itself.
+
+use ports::Device;
+
+pub struct Watchdog {
+    pub port: u8,
+    /// How many cycles may pass between writes before the CPU is reset, or None if it never is.
+    pub timeout: Option<u64>,
+    /// When the program last wrote to the port, or the count started.
+    serviced: Option<u64>,
+    cycles: u64,
+}
+
+impl Watchdog {
+    pub fn new(port: u8, timeout: Option<u64>) -> Watchdog {
+        Watchdog { port, timeout, serviced: None, cycles: 0 }
+    }
+}
+
+impl Device for Watchdog {
+    fn name(&self) -> &'static str {
+        "watchdog"
+    }
+
+    fn input(&mut self, _port: u8) -> Option<u8> {
+        None
+    }
+
+    fn output(&mut self, port: u8, _value: u8) {
+        if port == self.port {
+            self.serviced = Some(self.cycles);
+        }
+    }
+
+    fn reset(&mut self, cycles: u64) -> bool {
+        self.cycles = cycles;
+        // The count starts from when the watchdog first sees the time, so resuming a save
+        // state from before it existed doesn't set it off straight away. It starts over, too,
+        // if time goes backwards, as it does when an older save state is loaded.
+        let serviced = match self.serviced {
+            Some(serviced) if serviced <= cycles => serviced,
+            _ => *self.serviced.insert(cycles),
+        };
+        if self.timeout.is_none_or(|timeout| cycles - serviced < timeout) {
+            return false
+        }
+        self.serviced = Some(cycles);
+        true
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.serviced.unwrap_or(self.cycles).to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.len() != 8 {
+            return Err(format!("expected 8 bytes, got {}", bytes.len()))
+        }
+        let mut serviced = [0; 8];
+        serviced.copy_from_slice(bytes);
+        self.serviced = Some(u64::from_le_bytes(serviced));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use invaders;
+    use machine::{Machine, Stop};
+    use shifter::{self, ShiftRegister};
+    use sound::{Sound, SoundBoard};
+
+    #[test]
+    fn writes_to_the_port_put_off_the_reset() {
+        let mut watchdog = Watchdog::new(6, Some(100));
+        assert!(!watchdog.reset(1000));
+        assert!(!watchdog.reset(1099));
+        watchdog.output(6, 0);
+        watchdog.output(7, 0);
+        assert!(!watchdog.reset(1198));
+        assert!(watchdog.reset(1199));
+        assert!(!watchdog.reset(1200), "the count starts over after a reset");
+        assert!(!Watchdog::new(6, None).reset(u64::MAX));
+    }
+
+    #[test]
+    fn going_back_in_time_starts_the_count_over() {
+        let mut watchdog = Watchdog::new(6, Some(100));
+        watchdog.reset(5000);
+        assert!(!watchdog.reset(10), "an older save state");
+        assert!(!watchdog.reset(109));
+        assert!(watchdog.reset(110));
+    }
+
+    #[test]
+    fn restored_watchdogs_carry_on_the_count() {
+        let mut watchdog = Watchdog::new(6, Some(100));
+        watchdog.reset(50);
+        watchdog.output(6, 0);
+        let mut restored = Watchdog::new(6, Some(100));
+        restored.restore(&watchdog.save()).unwrap();
+        assert!(!restored.reset(149));
+        assert!(restored.reset(150));
+        assert_eq!(restored.restore(&[]), Err("expected 8 bytes, got 0".to_string()));
+    }
+
+    // Runs `rom` on the invaders board with a short watchdog, for up to `limit` cycles.
+    fn run(rom: &[u8], limit: u64) -> (u64, Stop) {
+        let mut memory = rom.to_vec();
+        memory.resize(0x10000, 0);
+        let mut ports = invaders::ports();
+        ports.find::<Watchdog>().unwrap().timeout = Some(100);
+        let (state, stop) = Machine::Invaders.run(invaders::boot(&memory, None), &mut ports, Some(limit), &BTreeSet::new(), &mut vec![]);
+        (state.cycles, stop)
+    }
+
+    #[test]
+    fn programs_that_never_write_are_reset() {
+        // JMP 0
+        assert_eq!(run(&[0xc3, 0x00, 0x00], 1000), (100, Stop::Watchdog));
+    }
+
+    #[test]
+    fn programs_that_write_are_left_alone() {
+        // OUT 6 / JMP 0
+        assert_eq!(run(&[0xd3, invaders::WATCHDOG_PORT, 0xc3, 0x00, 0x00], 1000), (1000, Stop::CycleLimit));
+    }
+
+    #[test]
+    fn a_reset_resets_the_whole_board() {
+        // OUT 3 / OUT 5 / OUT 2 / OUT 4 / JMP 8, with A set to switch on the UFO, the amplifier,
+        // the first fleet note and the flip, and shift something in.
+        let mut memory = vec![0xd3, 3, 0xd3, 5, 0xd3, shifter::OFFSET_PORT, 0xd3, shifter::DATA_PORT, 0xc3, 0x08, 0x00];
+        memory.resize(0x10000, 0);
+        let mut state = invaders::boot(&memory, None);
+        state.a = 0x21;
+        let mut ports = invaders::ports();
+        ports.find::<Watchdog>().unwrap().timeout = Some(100);
+        let events = ports.find::<SoundBoard>().unwrap().subscribe();
+
+        let (_, stop) = Machine::Invaders.run(state, &mut ports, Some(1000), &BTreeSet::new(), &mut vec![]);
+        assert_eq!(stop, Stop::Watchdog);
+        let board = ports.find::<SoundBoard>().unwrap();
+        assert_eq!((board.latched(), board.flipped()), (vec![], false));
+        let switched: Vec<(Sound, bool)> = events.try_iter().map(|event| (event.sound, event.on)).collect();
+        assert_eq!(switched, [
+            (Sound::Ufo, true), (Sound::Amplifier, true), (Sound::Fleet(1), true),
+            (Sound::Ufo, false), (Sound::Amplifier, false), (Sound::Fleet(1), false),
+        ]);
+        assert_eq!(ports.find::<ShiftRegister>(), Some(&mut ShiftRegister::default()));
+    }
+}
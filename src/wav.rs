@@ -0,0 +1,186 @@
+//! Reading and writing PCM WAV files. Sound is handled as mono samples from -1 to 1.
+
+use std::fs;
+
+/// Sound at `rate` samples a second.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Wave {
+    pub rate: u32,
+    pub samples: Vec<f32>,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+impl Wave {
+    /// Decodes 8, 16, 24 or 32-bit PCM, mixing the channels down to one.
+    pub fn read(bytes: &[u8]) -> Result<Wave, String> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("not a WAV file".to_string())
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut at = 12;
+        while at + 8 <= bytes.len() {
+            let length = u32_at(bytes, at + 4) as usize;
+            let contents = bytes.get(at + 8..at + 8 + length).ok_or_else(|| "WAV file is truncated".to_string())?;
+            match &bytes[at..at + 4] {
+                b"fmt " if length >= 16 => format = Some(contents),
+                b"data" => data = Some(contents),
+                _ => {},
+            }
+            // Chunks are padded to an even length.
+            at += 8 + length + length % 2;
+        }
+        let format = format.ok_or_else(|| "WAV file has no format".to_string())?;
+        let data = data.ok_or_else(|| "WAV file has no data".to_string())?;
+
+        // 0xfffe is the extensible format, which is still PCM for the files read here.
+        let (kind, channels, rate, bits) = (u16_at(format, 0), u16_at(format, 2) as usize, u32_at(format, 4), u16_at(format, 14) as usize);
+        if kind != 1 && kind != 0xfffe {
+            return Err(format!("WAV format {} isn't PCM", kind))
+        }
+        if channels == 0 || rate == 0 || !matches!(bits, 8 | 16 | 24 | 32) {
+            return Err(format!("unsupported WAV format: {} channels of {} bits at {}Hz", channels, bits, rate))
+        }
+
+        let width = bits / 8;
+        let sample = |bytes: &[u8]| match width {
+            // Eight-bit samples are unsigned; the rest are signed.
+            1 => (bytes[0] as f32 - 128.0) / 128.0,
+            2 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            3 => (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8388608.0,
+            _ => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0,
+        };
+        let samples = data.chunks_exact(width * channels)
+            .map(|frame| frame.chunks(width).map(sample).sum::<f32>() / channels as f32)
+            .collect();
+        Ok(Wave { rate, samples })
+    }
+
+    /// Encodes the samples as 16-bit mono PCM, clipping any beyond -1 to 1.
+    pub fn write(&self) -> Vec<u8> {
+        let data = self.samples.len() * 2;
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(36 + data as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, one channel.
+        bytes.extend_from_slice(&[1, 0, 1, 0]);
+        bytes.extend_from_slice(&self.rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.rate * 2).to_le_bytes());
+        // Two bytes a frame, 16 bits a sample.
+        bytes.extend_from_slice(&[2, 0, 16, 0]);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data as u32).to_le_bytes());
+        for sample in &self.samples {
+            bytes.extend_from_slice(&((sample.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes());
+        }
+        bytes
+    }
+
+    /// The same sound at `rate` samples a second, interpolating between the samples there are.
+    pub fn resample(&self, rate: u32) -> Wave {
+        if rate == self.rate || self.samples.is_empty() {
+            return Wave { rate, samples: self.samples.clone() }
+        }
+        let length = (self.samples.len() as u64 * rate as u64 / self.rate as u64) as usize;
+        let step = self.rate as f64 / rate as f64;
+        let samples = (0..length).map(|index| {
+            let at = index as f64 * step;
+            let (whole, part) = (at as usize, (at.fract()) as f32);
+            let next = self.samples.get(whole + 1).cloned().unwrap_or(self.samples[whole]);
+            self.samples[whole] + (next - self.samples[whole]) * part
+        }).collect();
+        Wave { rate, samples }
+    }
+
+    pub fn read_file(path: &str) -> Result<Wave, String> {
+        let bytes = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+        Wave::read(&bytes).map_err(|error| format!("{}: {}", path, error))
+    }
+
+    pub fn write_file(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.write()).map_err(|error| format!("{}: {}", path, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A format chunk and a data chunk, as other programs might write them.
+    fn wav(channels: u16, rate: u32, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&rate.to_le_bytes());
+        bytes.extend_from_slice(&(rate * (channels * bits / 8) as u32).to_le_bytes());
+        bytes.extend_from_slice(&(channels * bits / 8).to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn written_waves_read_back() {
+        let wave = Wave { rate: 11025, samples: vec![0.0, 0.5, -0.5, 0.25, -1.0, 1.0] };
+        let bytes = wave.write();
+        assert_eq!(bytes, wav(1, 11025, 16, &bytes[44..]), "the header");
+        let read = Wave::read(&bytes).unwrap();
+        assert_eq!(read.rate, 11025);
+        assert_eq!(read.samples.len(), wave.samples.len());
+        for (read, written) in read.samples.iter().zip(&wave.samples) {
+            assert!((read - written).abs() < 1.0 / 16384.0, "{} read back as {}", written, read);
+        }
+    }
+
+    #[test]
+    fn samples_beyond_full_scale_are_clipped() {
+        let bytes = Wave { rate: 8000, samples: vec![2.0, -2.0] }.write();
+        assert_eq!(bytes[44..], [0xff, 0x7f, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn channels_are_mixed_down() {
+        // Eight-bit stereo, then 24-bit mono, with a chunk of odd length to skip between.
+        let mut bytes = wav(2, 22050, 8, &[0xc0, 0x40, 0xff, 0xff]);
+        assert_eq!(Wave::read(&bytes).unwrap().samples, [0.0, 127.0 / 128.0]);
+
+        bytes = wav(1, 22050, 24, &[0x00, 0x00, 0x40, 0x00, 0x00, 0xc0]);
+        bytes.splice(36..36, b"LIST\x03\x00\x00\x00abc\x00".iter().cloned());
+        assert_eq!(Wave::read(&bytes).unwrap().samples, [0.5, -0.5]);
+    }
+
+    #[test]
+    fn bad_files_are_refused() {
+        assert_eq!(Wave::read(b"RIFF\0\0\0\0AVI ").err(), Some("not a WAV file".to_string()));
+        let bytes = wav(1, 8000, 16, &[0, 0]);
+        assert_eq!(Wave::read(&bytes[..bytes.len() - 1]).err(), Some("WAV file is truncated".to_string()));
+        assert_eq!(Wave::read(&bytes[..36]).err(), Some("WAV file has no data".to_string()));
+        assert_eq!(Wave::read(&wav(1, 8000, 12, &[])).err(), Some("unsupported WAV format: 1 channels of 12 bits at 8000Hz".to_string()));
+        let mut float = bytes.clone();
+        float[20] = 3;
+        assert_eq!(Wave::read(&float).err(), Some("WAV format 3 isn't PCM".to_string()));
+    }
+
+    #[test]
+    fn resampling_interpolates() {
+        let wave = Wave { rate: 100, samples: vec![0.0, 1.0, 0.0, -1.0] };
+        assert_eq!(wave.resample(200).samples, [0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]);
+        assert_eq!(wave.resample(50).samples, [0.0, 0.0]);
+        assert_eq!(wave.resample(100), wave);
+        assert_eq!(Wave { rate: 100, samples: vec![] }.resample(44100), Wave { rate: 44100, samples: vec![] });
+    }
+}
@@ -0,0 +1,164 @@
+//! Reading files out of ZIP archives, which is how arcade ROM sets are usually kept.
+
+use checksum;
+use inflate;
+
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+pub struct Entry {
+    /// The entry's path inside the archive.
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+fn word(bytes: &[u8], at: usize) -> Result<u16, String> {
+    bytes.get(at..at + 2).map(|word| u16::from_le_bytes([word[0], word[1]])).ok_or_else(|| "archive is truncated".to_string())
+}
+
+fn long(bytes: &[u8], at: usize) -> Result<u32, String> {
+    bytes.get(at..at + 4).map(|long| u32::from_le_bytes([long[0], long[1], long[2], long[3]])).ok_or_else(|| "archive is truncated".to_string())
+}
+
+/// Every file in the archive, decompressed and checked against its CRC.
+pub fn read(bytes: &[u8]) -> Result<Vec<Entry>, String> {
+    // The end of directory record is at the very end, unless there's an archive comment.
+    let end = (0..bytes.len().saturating_sub(21)).rev()
+        .find(|&at| long(bytes, at) == Ok(END_OF_DIRECTORY))
+        .ok_or_else(|| "not a ZIP archive".to_string())?;
+    let count = word(bytes, end + 10)? as usize;
+    let mut at = long(bytes, end + 16)? as usize;
+
+    let mut entries = vec![];
+    for _ in 0..count {
+        if long(bytes, at)? != DIRECTORY_ENTRY {
+            return Err("archive directory is corrupt".to_string())
+        }
+        let flags = word(bytes, at + 8)?;
+        let method = word(bytes, at + 10)?;
+        let crc = long(bytes, at + 16)?;
+        let compressed = long(bytes, at + 20)? as usize;
+        let name_length = word(bytes, at + 28)? as usize;
+        let skip = name_length + word(bytes, at + 30)? as usize + word(bytes, at + 32)? as usize;
+        let header = long(bytes, at + 42)? as usize;
+        let name = String::from_utf8_lossy(bytes.get(at + 46..at + 46 + name_length).ok_or_else(|| "archive is truncated".to_string())?).into_owned();
+        at += 46 + skip;
+
+        if name.ends_with('/') {
+            continue
+        }
+        if flags & 1 != 0 {
+            return Err(format!("{} is encrypted", name))
+        }
+        if compressed == 0xffff_ffff {
+            return Err(format!("{} needs ZIP64, which isn't supported", name))
+        }
+
+        if long(bytes, header)? != LOCAL_HEADER {
+            return Err(format!("{}: local header is corrupt", name))
+        }
+        let start = header + 30 + word(bytes, header + 26)? as usize + word(bytes, header + 28)? as usize;
+        let data = bytes.get(start..start + compressed).ok_or_else(|| format!("{}: archive is truncated", name))?;
+        let data = match method {
+            STORED => data.to_vec(),
+            DEFLATED => inflate::inflate(data).map_err(|error| format!("{}: {}", name, error))?,
+            _ => return Err(format!("{}: unsupported compression method {}", name, method)),
+        };
+        if checksum::crc32(&data) != crc {
+            return Err(format!("{}: CRC doesn't match the archive's", name))
+        }
+        entries.push(Entry { name, bytes: data });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// An archive holding each of `files`, as (name, method, data as stored, CRC of the
+    /// data once extracted).
+    pub fn archive(files: &[(&str, u16, &[u8], u32)]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut directory = vec![];
+        for &(name, method, data, crc) in files {
+            let header = bytes.len() as u32;
+            let fields = |bytes: &mut Vec<u8>| {
+                bytes.extend_from_slice(&[20, 0, 0, 0]);
+                bytes.extend_from_slice(&method.to_le_bytes());
+                bytes.extend_from_slice(&[0; 4]);
+                bytes.extend_from_slice(&crc.to_le_bytes());
+                bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                // The size once extracted, which reading doesn't need.
+                bytes.extend_from_slice(&0u32.to_le_bytes());
+                bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                bytes.extend_from_slice(&[0; 2]);
+            };
+            bytes.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            fields(&mut bytes);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(data);
+
+            directory.extend_from_slice(&DIRECTORY_ENTRY.to_le_bytes());
+            directory.extend_from_slice(&[20, 0]);
+            fields(&mut directory);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&header.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+
+        let start = bytes.len() as u32;
+        let size = directory.len() as u32;
+        bytes.extend(directory);
+        bytes.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&size.to_le_bytes());
+        bytes.extend_from_slice(&start.to_le_bytes());
+        bytes.extend_from_slice(&[0; 2]);
+        bytes
+    }
+
+    // "hello hello hello", compressed with fixed Huffman codes.
+    const DEFLATED_HELLO: [u8; 10] = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00];
+
+    #[test]
+    fn stored_and_deflated_files_are_extracted() {
+        let bytes = archive(&[
+            ("dir/", STORED, b"", 0),
+            ("dir/stored.txt", STORED, b"abc", checksum::crc32(b"abc")),
+            ("deflated.txt", DEFLATED, &DEFLATED_HELLO, checksum::crc32(b"hello hello hello")),
+        ]);
+        let entries = read(&bytes).unwrap();
+        let entries: Vec<(&str, &[u8])> = entries.iter().map(|entry| (entry.name.as_str(), entry.bytes.as_slice())).collect();
+        assert_eq!(entries, [("dir/stored.txt", &b"abc"[..]), ("deflated.txt", &b"hello hello hello"[..])]);
+    }
+
+    #[test]
+    fn a_file_that_fails_its_crc_is_an_error() {
+        let bytes = archive(&[("bad.bin", STORED, b"abc", checksum::crc32(b"abd"))]);
+        assert_eq!(read(&bytes).err(), Some("bad.bin: CRC doesn't match the archive's".to_string()));
+    }
+
+    #[test]
+    fn other_compression_is_refused() {
+        let bytes = archive(&[("shrunk.bin", 1, b"abc", 0)]);
+        assert_eq!(read(&bytes).err(), Some("shrunk.bin: unsupported compression method 1".to_string()));
+    }
+
+    #[test]
+    fn broken_archives_are_errors() {
+        assert_eq!(read(b"not a zip file at all").err(), Some("not a ZIP archive".to_string()));
+        let bytes = archive(&[("a.bin", STORED, b"abc", checksum::crc32(b"abc"))]);
+        // Cutting out the local header leaves the directory pointing at the wrong place.
+        assert_eq!(read(&bytes[4..]).err(), Some("archive directory is corrupt".to_string()));
+        let mut corrupt = bytes.clone();
+        corrupt[0] = 0;
+        assert_eq!(read(&corrupt).err(), Some("a.bin: local header is corrupt".to_string()));
+    }
+}